@@ -0,0 +1,5 @@
+//! Builds `chip_8::ffi` as a C library; see include/chip8.h.
+
+extern crate chip_8;
+
+pub use chip_8::ffi::*;
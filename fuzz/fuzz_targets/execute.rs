@@ -0,0 +1,44 @@
+//! Runs arbitrary bytes as a ROM. The first bytes pick the quirks, the
+//! seed and the keys held down, and the rest is the program.
+
+#![no_main]
+
+use chip_8::headless::{FrameBuffer, NullAudio, ScriptedInput};
+use chip_8::quirks::{Quirks, NAMES};
+use chip_8::Chip8;
+use libfuzzer_sys::fuzz_target;
+
+const FRAMES:u64 = 30;
+
+fuzz_target!(|data:&[u8]| {
+    if data.len() < 4 {
+        return;
+    }
+    let (config, rom) = data.split_at(4);
+
+    let mut quirks = Quirks::default();
+    for (bit, name) in NAMES.iter().enumerate() {
+        quirks.set(name, config[0] & (1 << bit) != 0);
+    }
+    // keys outside 0-F are passed through, as a broken input device might
+    let keys:Vec<(u64, u8)> = (0..FRAMES)
+        .map(|frame| (frame, config[3]))
+        .collect();
+
+    let mut machine = Chip8::new(
+        NullAudio::default(),
+        FrameBuffer::default(),
+        ScriptedInput::new(&keys),
+    );
+    machine.set_quirks(quirks);
+    machine.set_speed(1 + config[1] as usize % 32);
+    machine.seed(config[2] as u64);
+    if machine.load_rom(rom).is_err() {
+        return;
+    }
+    for _ in 0..FRAMES {
+        if machine.run_frame().is_err() {
+            break;
+        }
+    }
+});
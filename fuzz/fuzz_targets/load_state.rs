@@ -0,0 +1,25 @@
+//! Loads arbitrary bytes as a save state, then runs the machine whether
+//! they loaded or not, as a rejected state must leave it runnable.
+
+#![no_main]
+
+use chip_8::headless::{FrameBuffer, NoInput, NullAudio};
+use chip_8::Chip8;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data:&[u8]| {
+    let mut machine = Chip8::new(
+        NullAudio::default(),
+        FrameBuffer::default(),
+        NoInput{},
+    );
+    // calls a subroutine forever, so a state half-restored over it would
+    // return from an empty stack
+    machine.load_rom(&[0x22, 0x04, 0x12, 0x00, 0x00, 0xEE]).unwrap();
+    let _ = machine.load_state(data);
+    for _ in 0..10 {
+        if machine.run_frame().is_err() {
+            break;
+        }
+    }
+});
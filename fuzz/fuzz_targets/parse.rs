@@ -0,0 +1,15 @@
+//! Feeds arbitrary text to the movie, ROM database and snapshot parsers.
+
+#![no_main]
+
+use chip_8::movie::Movie;
+use chip_8::romdb::RomDatabase;
+use chip_8::snapshot;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data:&[u8]| {
+    let _ = snapshot::parse(data);
+    let text = String::from_utf8_lossy(data);
+    let _ = Movie::parse(&text);
+    let _ = RomDatabase::parse(&text);
+});
@@ -0,0 +1,130 @@
+#ifndef CHIP8_H
+#define CHIP8_H
+
+/* Generated by cbindgen from src/ffi.rs; don't edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Bumped whenever the ABI changes incompatibly.
+#define CHIP8_ABI_VERSION 1
+
+// The framebuffer's size, one byte per pixel.
+#define CHIP8_SCREEN_WIDTH 64
+
+#define CHIP8_SCREEN_HEIGHT 32
+
+// What a call did. Every function that can fail returns one.
+typedef enum Chip8Status {
+  CHIP8_STATUS_OK = 0,
+  // A pointer argument was null.
+  CHIP8_STATUS_NULL_POINTER = 1,
+  CHIP8_STATUS_ROM_EMPTY = 2,
+  // The ROM doesn't fit in memory.
+  CHIP8_STATUS_ROM_TOO_LARGE = 3,
+  // An instruction failed, stopping the frame before it.
+  CHIP8_STATUS_EXEC_FAILED = 4,
+  // The save state is corrupt or from an unsupported version.
+  CHIP8_STATUS_BAD_STATE = 5,
+  // The output buffer is too small; nothing was written.
+  CHIP8_STATUS_BUFFER_TOO_SMALL = 6,
+  // The emulator panicked. The machine can only be freed.
+  CHIP8_STATUS_PANICKED = 7,
+} Chip8Status;
+
+// A machine, known to C only by pointer.
+typedef struct Chip8Machine Chip8Machine;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The version of the ABI the library was built with, to compare with
+// the header's CHIP8_ABI_VERSION.
+uint32_t chip8_abi_version(void);
+
+// A description of `status`, a `Chip8Status`, as a static string.
+// Values that aren't a status give "unknown status".
+const char *chip8_status_message(uint32_t status);
+
+// Creates a machine with no ROM loaded, to free with `chip8_free`.
+// Returns null if that panics.
+struct Chip8Machine *chip8_new(void);
+
+// Frees a machine from `chip8_new`. Null is ignored.
+//
+// # Safety
+//
+// `machine` must be null or from `chip8_new`, and not already freed.
+void chip8_free(struct Chip8Machine *machine);
+
+// Loads `size` bytes of ROM from `rom` and starts it over.
+//
+// # Safety
+//
+// `machine` must be from `chip8_new`, and `rom` must point to `size`
+// bytes.
+enum Chip8Status chip8_load_rom(struct Chip8Machine *machine,
+                                const uint8_t *rom,
+                                size_t size);
+
+// Runs one frame. The caller paces frames to 60 Hz.
+//
+// # Safety
+//
+// `machine` must be from `chip8_new`.
+enum Chip8Status chip8_run_frame(struct Chip8Machine *machine);
+
+// Sets which keys are held down, bit `n` set for key `n`. Only keys
+// that weren't already down are pressed, so this can be called with the
+// keys down every frame.
+//
+// # Safety
+//
+// `machine` must be from `chip8_new`.
+enum Chip8Status chip8_set_keys(struct Chip8Machine *machine, uint16_t keys);
+
+// Copies the screen to `out`, row by row, a byte per pixel: 1 for lit
+// and 0 for dark. `size` must be at least CHIP8_SCREEN_WIDTH *
+// CHIP8_SCREEN_HEIGHT.
+//
+// # Safety
+//
+// `machine` must be from `chip8_new`, and `out` must point to `size`
+// writable bytes.
+enum Chip8Status chip8_framebuffer(struct Chip8Machine *machine,
+                                   uint8_t *out,
+                                   size_t size);
+
+// Saves the machine's state to `out`, storing its length in `written`.
+// If `size` is too small, only stores the length, so calling with a
+// null `out` and a `size` of 0 asks how large a buffer to pass.
+//
+// # Safety
+//
+// `machine` must be from `chip8_new`, `out` must be null or point to
+// `size` writable bytes, and `written` must point to a `size_t`.
+enum Chip8Status chip8_save_state(struct Chip8Machine *machine,
+                                  uint8_t *out,
+                                  size_t size,
+                                  size_t *written);
+
+// Restores a state from `chip8_save_state`. An invalid state leaves the
+// machine as it was.
+//
+// # Safety
+//
+// `machine` must be from `chip8_new`, and `state` must point to `size`
+// bytes.
+enum Chip8Status chip8_load_state(struct Chip8Machine *machine,
+                                  const uint8_t *state,
+                                  size_t size);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CHIP8_H */
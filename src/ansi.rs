@@ -0,0 +1,584 @@
+use std::cell::RefCell;
+use std::env;
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
+use std::str;
+use std::time::{Duration, Instant};
+
+use super::libc;
+use super::screen::{self, key_f, Key, Style};
+use super::screen::{KEY_B2, KEY_DOWN, KEY_ESCAPE, KEY_HOME, KEY_LEFT,
+                    KEY_NPAGE, KEY_PPAGE, KEY_RIGHT, KEY_UP};
+use super::theme::{Rgb, Theme, SHADES};
+
+/// Milliseconds to wait after an escape for the rest of an escape
+/// sequence, before taking it as the escape key.
+const ESCAPE_DELAY:i32 = 25;
+
+/// Switches to the alternate screen with the cursor hidden and lines not
+/// wrapping.
+const ENTER:&str = "\x1b[?1049h\x1b[?25l\x1b[?7l\x1b[2J";
+/// Undoes `ENTER`.
+const LEAVE:&str = "\x1b[0m\x1b[?7h\x1b[?25h\x1b[?1049l";
+
+/// Asks for the kitty keyboard protocol flags, then for the device
+/// attributes, which every terminal answers, so a terminal without the
+/// protocol is found out without waiting for the timeout.
+const QUERY:&str = "\x1b[?u\x1b[c";
+/// Milliseconds to wait for the answer to `QUERY`.
+const QUERY_TIMEOUT:u64 = 500;
+/// Asks the kitty keyboard protocol to report every key as an escape
+/// sequence, releases included (flags 1, 2 and 8).
+const PUSH_FLAGS:&str = "\x1b[>11u";
+/// Undoes `PUSH_FLAGS`.
+const POP_FLAGS:&str = "\x1b[<u";
+/// The kitty keyboard flag for reporting repeats and releases.
+const REPORT_EVENTS:u32 = 0x2;
+
+// Keys
+////////////////////////////////////////////////////////////////////////
+
+/// What `parse` found at the start of the input.
+#[derive(Debug, PartialEq)]
+enum Parsed {
+    /// A key, read from the given number of bytes.
+    Key(Key, usize),
+    /// Bytes that aren't a key, such as an unknown escape sequence.
+    Skip(usize),
+    /// The start of a key whose rest hasn't been read yet.
+    Incomplete,
+}
+
+/// Parses the key at the start of `input`: a character, a legacy escape
+/// sequence or one from the kitty keyboard protocol.
+fn parse(input:&[u8]) -> Parsed {
+    match *input {
+        [] | [0x1B] | [0x1B, b'O'] => Parsed::Incomplete,
+        [0x1B, b'[', ref rest @ ..] => match parse_csi(rest) {
+            Parsed::Key(key, length) => Parsed::Key(key, 2 + length),
+            Parsed::Skip(length) => Parsed::Skip(2 + length),
+            Parsed::Incomplete => Parsed::Incomplete,
+        },
+        [0x1B, b'O', last, ..] => match last {
+            b'A'..=b'E' | b'H' => Parsed::Key(Key::Press(cursor_key(last)), 3),
+            b'P'..=b'S' => Parsed::Key(Key::Press(key_f(1 + last - b'P')), 3),
+            b'M' => Parsed::Key(Key::Press(0x0A), 3),
+            _ => Parsed::Skip(3),
+        },
+        // the terminal's interrupt key reaches raw input as a key
+        [0x1B, ..] | [0x03, ..] => Parsed::Key(Key::Press(KEY_ESCAPE), 1),
+        [b'\r', ..] => Parsed::Key(Key::Press(0x0A), 1),
+        [first, ..] => {
+            let length = match first {
+                0x00..=0x7F => 1,
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => return Parsed::Skip(1),
+            };
+            if input.len() < length {
+                return Parsed::Incomplete;
+            }
+            match ::std::str::from_utf8(&input[..length]) {
+                Ok(text) => Parsed::Key(
+                    Key::Press(text.chars().next().unwrap() as i32), length),
+                Err(_) => Parsed::Skip(1),
+            }
+        },
+    }
+}
+
+/// Parses a control sequence, after its `ESC [`: parameters separated by
+/// `;`, each with sub-parameters separated by `:`, then a final byte.
+fn parse_csi(input:&[u8]) -> Parsed {
+    let end = match input.iter().position(|&byte| byte >= 0x40) {
+        Some(end) => end,
+        None if input.len() < 0x20 => return Parsed::Incomplete,
+        None => return Parsed::Skip(input.len()),
+    };
+    let length = end + 1;
+    if input[end] > 0x7E || input[..end].iter().any(|&byte| byte < 0x20) {
+        return Parsed::Skip(0);
+    }
+    // replies and private sequences start with one of < = > ?
+    if input[..end].first().is_some_and(|&byte| byte >= 0x3C) {
+        return Parsed::Skip(length);
+    }
+
+    let params:Vec<Vec<&str>> = ::std::str::from_utf8(&input[..end])
+        .unwrap_or("")
+        .split(';')
+        .map(|param| param.split(':').collect())
+        .collect();
+    let param = |index:usize, sub:usize, default:u32| {
+        params.get(index).and_then(|param| param.get(sub))
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    };
+    let code = match input[end] {
+        b'u' => kitty_key(param(0, 0, 0), param(1, 0, 1)),
+        last @ (b'A'..=b'E' | b'H') => Some(cursor_key(last)),
+        last @ b'P'..=b'S' => Some(key_f(1 + last - b'P')),
+        b'~' => tilde_key(param(0, 0, 0)),
+        _ => None,
+    };
+    // the kitty protocol adds the event type to the modifiers: 1 for a
+    // press, 2 for a repeat, 3 for a release
+    match (code, param(1, 1, 1)) {
+        (Some(code), 3) => Parsed::Key(Key::Release(code), length),
+        (Some(code), _) => Parsed::Key(Key::Press(code), length),
+        (None, _) => Parsed::Skip(length),
+    }
+}
+
+/// The key for the final byte of a cursor key sequence.
+fn cursor_key(last:u8) -> i32 {
+    match last {
+        b'A' => KEY_UP,
+        b'B' => KEY_DOWN,
+        b'C' => KEY_RIGHT,
+        b'D' => KEY_LEFT,
+        b'E' => KEY_B2,
+        _ => KEY_HOME,
+    }
+}
+
+/// The key for a `CSI <number> ~` sequence.
+fn tilde_key(number:u32) -> Option<i32> {
+    match number {
+        1 | 7 => Some(KEY_HOME),
+        5 => Some(KEY_PPAGE),
+        6 => Some(KEY_NPAGE),
+        11..=15 => Some(key_f(number as u8 - 10)),
+        17..=21 => Some(key_f(number as u8 - 11)),
+        23 | 24 => Some(key_f(number as u8 - 12)),
+        _ => None,
+    }
+}
+
+/// The key for a kitty protocol key code with `modifiers`, one more than
+/// the bits for shift, alt and control. Keypad keys become the keys
+/// they're labelled with, as in curses.
+fn kitty_key(code:u32, modifiers:u32) -> Option<i32> {
+    const CONTROL:u32 = 0x4;
+    const KEYPAD:&[u8] = b"0123456789./*-+\n=";
+    let key = match code {
+        0x63 if modifiers.saturating_sub(1) & CONTROL != 0 => KEY_ESCAPE,
+        0x0D => 0x0A,
+        57399..=57415 => KEYPAD[code as usize - 57399] as i32,
+        57417 => KEY_LEFT,
+        57418 => KEY_RIGHT,
+        57419 => KEY_UP,
+        57420 => KEY_DOWN,
+        57421 => KEY_PPAGE,
+        57422 => KEY_NPAGE,
+        57423 => KEY_HOME,
+        57427 => KEY_B2,
+        // the rest of the private use area is other function keys
+        57344..=63743 => return None,
+        code => code as i32,
+    };
+    Some(key)
+}
+
+/// Takes the answers to `QUERY` out of `input`, leaving any keys read
+/// around them. Sets `flags` to the kitty keyboard flags if they're
+/// there, and is true once the device attributes, which come last, are.
+fn take_replies(input:&mut Vec<u8>, flags:&mut Option<u32>) -> bool {
+    let mut done = false;
+    let mut at = 0;
+    while at < input.len() {
+        if input[at..].starts_with(b"\x1b[?") {
+            let rest = &input[at + 3..];
+            let end = rest.iter()
+                .position(|&byte| !byte.is_ascii_digit() && byte != b';');
+            let last = end.map(|end| rest[end]);
+            if last == Some(b'u') || last == Some(b'c') {
+                let end = end.unwrap();
+                if last == Some(b'u') {
+                    *flags = str::from_utf8(&rest[..end]).ok()
+                        .and_then(|value| value.parse().ok());
+                } else {
+                    done = true;
+                }
+                input.drain(at..at + 3 + end + 1);
+                continue;
+            }
+        }
+        at += 1;
+    }
+    done
+}
+
+/// Sends `QUERY` and waits for the answer, keeping keys read meanwhile
+/// in `input`. The kitty keyboard flags, or `None` if the terminal
+/// doesn't have the protocol or didn't answer in time.
+fn query_flags(input:&mut Vec<u8>) -> Option<u32> {
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(QUERY.as_bytes());
+    let _ = stdout.flush();
+
+    let deadline = Instant::now() + Duration::from_millis(QUERY_TIMEOUT);
+    let mut flags = None;
+    while !take_replies(input, &mut flags) {
+        let left = deadline.saturating_duration_since(Instant::now())
+            .as_millis() as i32;
+        if left == 0 || !read_input(input, left) {
+            return None;
+        }
+    }
+    flags
+}
+
+/// Appends whatever input is waiting to `input`, waiting up to `timeout`
+/// milliseconds for some. False if none came.
+fn read_input(input:&mut Vec<u8>, timeout:i32) -> bool {
+    let mut poll = libc::pollfd{fd:libc::STDIN_FILENO, events:libc::POLLIN,
+                                revents:0};
+    if unsafe { libc::poll(&mut poll, 1, timeout) } <= 0 {
+        return false;
+    }
+    let mut buffer = [0x0u8;0x100];
+    let count = unsafe {
+        libc::read(libc::STDIN_FILENO, buffer.as_mut_ptr() as *mut libc::c_void,
+                   buffer.len())
+    };
+    if count <= 0 {
+        return false;
+    }
+    input.extend_from_slice(&buffer[..count as usize]);
+    true
+}
+
+// Output
+////////////////////////////////////////////////////////////////////////
+
+/// How the terminal shows colors, from `COLORTERM` and `TERM`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColorMode {
+    Direct,
+    Xterm256,
+    Basic,
+}
+
+impl ColorMode {
+    fn detect() -> Option<ColorMode> {
+        let term = env::var("TERM").unwrap_or_default();
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        if term.is_empty() || term == "dumb" {
+            None
+        } else if colorterm == "truecolor" || colorterm == "24bit"
+                || term.ends_with("direct") {
+            Some(ColorMode::Direct)
+        } else if term.contains("256color") {
+            Some(ColorMode::Xterm256)
+        } else {
+            Some(ColorMode::Basic)
+        }
+    }
+
+    /// The parameters to select `color`, for the background if
+    /// `background`.
+    fn select(self, color:Rgb, background:bool) -> String {
+        let base = if background {40} else {30};
+        match self {
+            ColorMode::Direct => format!("{};2;{};{};{}", base + 8, color.0,
+                                         color.1, color.2),
+            ColorMode::Xterm256 => format!("{};5;{}", base + 8,
+                                           color.xterm256()),
+            ColorMode::Basic => format!("{}", base + color.basic()),
+        }
+    }
+}
+
+/// The select graphic rendition sequence for `style`, with `colors` the
+/// foreground and background parameters for each shade.
+fn sgr(style:Style, colors:&[(String, String)]) -> String {
+    let mut sequence = "\x1b[0".to_string();
+    for &(flag, parameter) in &[(screen::BOLD, ";1"), (screen::DIM, ";2"),
+                                (screen::UNDERLINE, ";4"),
+                                (screen::REVERSE, ";7")] {
+        if style.attributes & flag != 0 {
+            sequence += parameter;
+        }
+    }
+    if let Some((foreground, background)) = style.colors {
+        if let (Some(foreground), Some(background)) =
+                (colors.get(foreground), colors.get(background)) {
+            sequence += &format!(";{};{}", foreground.0, background.1);
+        }
+    }
+    sequence + "m"
+}
+
+// Screen
+////////////////////////////////////////////////////////////////////////
+
+struct Terminal {
+    /// The settings to restore when done.
+    original:libc::termios,
+    rows:usize,
+    columns:usize,
+    /// Escape sequences and text to write on the next refresh.
+    output:Vec<u8>,
+    /// Input read but not yet parsed.
+    input:Vec<u8>,
+    /// The foreground and background parameters for each shade.
+    colors:Vec<(String, String)>,
+    /// Whether the kitty keyboard flags were pushed, to pop when done.
+    pushed:bool,
+    /// Whether the terminal accepted the flag to report releases.
+    releases:bool,
+}
+
+/// The terminal, driven with ANSI escape sequences and read in raw mode.
+/// Key releases are reported on terminals with the kitty keyboard
+/// protocol, which is asked for only once the terminal says it has it.
+#[derive(Clone)]
+pub struct Screen {
+    terminal:Rc<RefCell<Terminal>>,
+}
+
+impl screen::Screen for Screen {
+    fn start() -> Result<Screen, String> {
+        let mut original:libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err("standard input is not a terminal".to_string());
+        }
+        let mut raw = original;
+        raw.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK
+                         | libc::ISTRIP | libc::IXON);
+        raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN
+                         | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw); }
+
+        let mut size:libc::winsize = unsafe { mem::zeroed() };
+        let result = unsafe {
+            libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size)
+        };
+        let (rows, columns) = match result {
+            0 if size.ws_row > 0 => (size.ws_row as usize,
+                                     size.ws_col as usize),
+            _ => (24, 80),
+        };
+
+        let mut input = Vec::new();
+        let pushed = query_flags(&mut input).is_some();
+        let releases = pushed && {
+            let _ = io::stdout().write_all(PUSH_FLAGS.as_bytes());
+            // read back, as the terminal may not take every flag
+            query_flags(&mut input)
+                .is_some_and(|flags| flags & REPORT_EVENTS != 0)
+        };
+
+        let screen = Screen{terminal:Rc::new(RefCell::new(Terminal{
+            original,
+            rows,
+            columns,
+            output:ENTER.as_bytes().to_vec(),
+            input,
+            colors:Vec::new(),
+            pushed,
+            releases,
+        }))};
+        screen.refresh();
+        Ok(screen)
+    }
+
+    fn stop(&self){
+        let mut terminal = self.terminal.borrow_mut();
+        if terminal.pushed {
+            terminal.output.extend_from_slice(POP_FLAGS.as_bytes());
+        }
+        terminal.output.extend_from_slice(LEAVE.as_bytes());
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(&terminal.output);
+        let _ = stdout.flush();
+        terminal.output.clear();
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW,
+                            &terminal.original);
+        }
+    }
+
+    fn size(&self) -> (usize, usize) {
+        let terminal = self.terminal.borrow();
+        (terminal.rows, terminal.columns)
+    }
+
+    fn put(&self, row:usize, col:usize, text:&str, style:Style) -> bool {
+        let mut terminal = self.terminal.borrow_mut();
+        if row >= terminal.rows || col >= terminal.columns {
+            return false;
+        }
+        let text:String = text.chars().take(terminal.columns - col).collect();
+        let sequence = format!("\x1b[{};{}H{}{}", row + 1, col + 1,
+                               sgr(style, &terminal.colors), text);
+        terminal.output.extend_from_slice(sequence.as_bytes());
+        true
+    }
+
+    fn put_stipple(&self, row:usize, col:usize, style:Style) -> bool {
+        self.put(row, col, "▒", style)
+    }
+
+    fn clear_line(&self, row:usize, col:usize){
+        let mut terminal = self.terminal.borrow_mut();
+        if row < terminal.rows {
+            let sequence = format!("\x1b[{};{}H\x1b[0m\x1b[K", row + 1,
+                                   col + 1);
+            terminal.output.extend_from_slice(sequence.as_bytes());
+        }
+    }
+
+    fn refresh(&self){
+        let mut terminal = self.terminal.borrow_mut();
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(&terminal.output);
+        let _ = stdout.flush();
+        terminal.output.clear();
+    }
+
+    fn beep(&self){
+        self.terminal.borrow_mut().output.push(0x07);
+        self.refresh();
+    }
+
+    /// Draws the shades of `theme` in 24-bit color on terminals with
+    /// direct color, or else in the nearest of the terminal's colors.
+    fn start_colors(&self, theme:&Theme) -> bool {
+        let mode = match ColorMode::detect() {
+            Some(mode) => mode,
+            None => return false,
+        };
+        self.terminal.borrow_mut().colors = (0..SHADES).map(|shade| {
+            let color = theme.shade(shade);
+            (mode.select(color, false), mode.select(color, true))
+        }).collect();
+        true
+    }
+
+    fn reports_releases(&self) -> bool {
+        self.terminal.borrow().releases
+    }
+
+    fn read_key(&self) -> Option<Key> {
+        let mut terminal = self.terminal.borrow_mut();
+        loop {
+            match parse(&terminal.input) {
+                Parsed::Key(key, length) => {
+                    terminal.input.drain(..length);
+                    return Some(key);
+                },
+                Parsed::Skip(length) => {
+                    terminal.input.drain(..length.max(1));
+                },
+                Parsed::Incomplete => {
+                    // wait briefly for the rest of a key already started
+                    let timeout = match terminal.input.is_empty() {
+                        true => 0,
+                        false => ESCAPE_DELAY,
+                    };
+                    if read_input(&mut terminal.input, timeout) {
+                        continue;
+                    }
+                    // a lone escape is the escape key, and the start of
+                    // anything else is dropped
+                    let first = *terminal.input.first()?;
+                    terminal.input.drain(..1);
+                    if first == 0x1B {
+                        return Some(Key::Press(KEY_ESCAPE));
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(input:&str) -> Parsed {
+        parse(input.as_bytes())
+    }
+
+    #[test]
+    fn test_parse_keys(){
+        assert_eq!(key("wq"), Parsed::Key(Key::Press('w' as i32), 1));
+        assert_eq!(key("é"), Parsed::Key(Key::Press('é' as i32), 2));
+        assert_eq!(key("\r"), Parsed::Key(Key::Press(0x0A), 1));
+        assert_eq!(key("\x1b[A"), Parsed::Key(Key::Press(KEY_UP), 3));
+        assert_eq!(key("\x1bOD"), Parsed::Key(Key::Press(KEY_LEFT), 3));
+        assert_eq!(key("\x1b[1;5C"), Parsed::Key(Key::Press(KEY_RIGHT), 6));
+        assert_eq!(key("\x1bOP"), Parsed::Key(Key::Press(key_f(1)), 3));
+        assert_eq!(key("\x1b[21~"), Parsed::Key(Key::Press(key_f(10)), 5));
+        assert_eq!(key("\x1b[6~x"), Parsed::Key(Key::Press(KEY_NPAGE), 4));
+        assert_eq!(key("\x1bx"), Parsed::Key(Key::Press(KEY_ESCAPE), 1));
+        assert_eq!(key("\x03"), Parsed::Key(Key::Press(KEY_ESCAPE), 1));
+
+        assert_eq!(key("\x1b"), Parsed::Incomplete);
+        assert_eq!(key("\x1b[1;5"), Parsed::Incomplete);
+        assert_eq!(parse(&"é".as_bytes()[..1]), Parsed::Incomplete);
+        assert_eq!(key("\x1b[?1u"), Parsed::Skip(5));
+        assert_eq!(key("\x1b[200~"), Parsed::Skip(6));
+    }
+
+    #[test]
+    fn test_parse_kitty_keys(){
+        assert_eq!(key("\x1b[119u"), Parsed::Key(Key::Press('w' as i32), 6));
+        assert_eq!(key("\x1b[119;1:2u"),
+                   Parsed::Key(Key::Press('w' as i32), 10));
+        assert_eq!(key("\x1b[119;1:3u"),
+                   Parsed::Key(Key::Release('w' as i32), 10));
+        assert_eq!(key("\x1b[119;2:3u"),
+                   Parsed::Key(Key::Release('w' as i32), 10));
+        assert_eq!(key("\x1b[27u"), Parsed::Key(Key::Press(KEY_ESCAPE), 5));
+        assert_eq!(key("\x1b[99;5u"), Parsed::Key(Key::Press(KEY_ESCAPE), 7));
+        assert_eq!(key("\x1b[13;1:3u"), Parsed::Key(Key::Release(0x0A), 9));
+        assert_eq!(key("\x1b[57400u"), Parsed::Key(Key::Press('1' as i32), 8));
+        assert_eq!(key("\x1b[1;1:3A"), Parsed::Key(Key::Release(KEY_UP), 8));
+        assert_eq!(key("\x1b[13;1:3~"),
+                   Parsed::Key(Key::Release(key_f(3)), 9));
+        // shift on its own
+        assert_eq!(key("\x1b[57441;2u"), Parsed::Skip(10));
+    }
+
+    #[test]
+    fn test_take_replies(){
+        // keys typed around the answers are kept
+        let mut input = b"w\x1b[?11u\x1b[119u\x1b[?62;22c".to_vec();
+        let mut flags = None;
+        assert!(take_replies(&mut input, &mut flags));
+        assert_eq!(flags, Some(11));
+        assert_eq!(input, b"w\x1b[119u");
+
+        // a terminal without the protocol answers only the second query
+        let mut input = b"\x1b[?1;2c".to_vec();
+        let mut flags = None;
+        assert!(take_replies(&mut input, &mut flags));
+        assert_eq!((flags, &input[..]), (None, &b""[..]));
+
+        // not all there yet
+        let mut input = b"\x1b[?0u\x1b[?1;".to_vec();
+        assert!(!take_replies(&mut input, &mut flags));
+        assert_eq!((flags, &input[..]), (Some(0), &b"\x1b[?1;"[..]));
+    }
+
+    #[test]
+    fn test_sgr(){
+        let colors = vec![("30".to_string(), "40".to_string()),
+                          ("38;5;214".to_string(), "48;5;214".to_string())];
+        assert_eq!(sgr(Style::NORMAL, &colors), "\x1b[0m");
+        assert_eq!(sgr(Style::attributes(screen::BOLD | screen::REVERSE),
+                       &colors), "\x1b[0;1;7m");
+        assert_eq!(sgr(Style::colors(1, 0), &colors), "\x1b[0;38;5;214;40m");
+        assert_eq!(ColorMode::Direct.select(Rgb(0xFF, 0xB0, 0x00), true),
+                   "48;2;255;176;0");
+        assert_eq!(ColorMode::Basic.select(Rgb(0xFF, 0xB0, 0x00), false),
+                   "33");
+    }
+}
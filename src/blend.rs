@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::string::String;
+use std::vec::Vec;
+
+use super::io::{Display, Pixel, MAX_LEVEL};
+use super::io::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+// Blend Modes
+////////////////////////////////////////////////////////////////////////
+
+/// Longest history `Blend::Or` keeps, a quarter of a second.
+pub const MAX_FRAMES:usize = 15;
+
+/// How frames are combined before they reach the display, to hide the
+/// flicker of sprites being erased and redrawn.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Blend {
+    /// Each frame as it was drawn.
+    #[default]
+    None,
+    /// A pixel is lit while it was lit in any of the last `n` frames.
+    Or(usize),
+    /// A pixel lights fully, then once dark loses `n` percent of its
+    /// brightness each frame, like the phosphor of a CRT.
+    Decay(u8),
+}
+
+impl Blend {
+    /// Parses `none`, `or:<frames>` or `decay:<percent>`.
+    pub fn parse(text:&str) -> Result<Blend, String> {
+        let (mode, arg) = match text.find(':') {
+            Some(split) => (&text[..split], Some(&text[split + 1..])),
+            None => (text, None),
+        };
+        let number = |max:usize| -> Result<usize, String> {
+            arg.and_then(|arg| arg.parse().ok())
+                .filter(|n| (1..=max).contains(n))
+                .ok_or_else(|| format!("{} expects a number from 1 to {}, \
+                                        as in {}:{}", mode, max, mode, max))
+        };
+        match mode {
+            "none" if arg.is_none() => Ok(Blend::None),
+            "or" => Ok(Blend::Or(number(MAX_FRAMES)?)),
+            "decay" => Ok(Blend::Decay(number(100)? as u8)),
+            _ => Err(format!("unknown blend mode '{}' (expected none, \
+                              or:<frames> or decay:<percent>)", text)),
+        }
+    }
+}
+
+impl fmt::Display for Blend {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Blend::None => write!(f, "none"),
+            Blend::Or(frames) => write!(f, "or:{}", frames),
+            Blend::Decay(percent) => write!(f, "decay:{}", percent),
+        }
+    }
+}
+
+// Blending Display
+////////////////////////////////////////////////////////////////////////
+
+const SCREEN_SIZE:usize = SCREEN_WIDTH*SCREEN_HEIGHT;
+
+/// Display that blends the frames drawn to it before passing them on to
+/// another display as brightness levels. With `Blend::None` it passes
+/// pixels straight through.
+pub struct Blender<D:Display> {
+    inner:D,
+    mode:Blend,
+    drawn:Vec<bool>,
+    history:VecDeque<Vec<bool>>,
+    levels:Vec<u8>,
+    shown:Vec<u8>,
+}
+
+impl<D:Display> Blender<D> {
+    pub fn new(inner:D, mode:Blend) -> Blender<D> {
+        Blender{
+            inner,
+            mode,
+            drawn:vec![false;SCREEN_SIZE],
+            history:VecDeque::new(),
+            levels:vec![0x0;SCREEN_SIZE],
+            // differs from every level, so the first frame is all sent
+            shown:vec![0x1;SCREEN_SIZE],
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn mode(&self) -> Blend {
+        self.mode
+    }
+
+    fn blend(&mut self){
+        match self.mode {
+            Blend::None => (),
+            Blend::Or(frames) => {
+                if self.history.len() == frames {
+                    self.history.pop_front();
+                }
+                self.history.push_back(self.drawn.clone());
+                for (i, level) in self.levels.iter_mut().enumerate() {
+                    let lit = self.history.iter().any(|frame| frame[i]);
+                    *level = if lit {MAX_LEVEL} else {0x0};
+                }
+            },
+            Blend::Decay(percent) => {
+                let keep = 100 - percent as u32;
+                for (level, &lit) in self.levels.iter_mut().zip(&self.drawn) {
+                    *level = if lit {
+                        MAX_LEVEL
+                    } else {
+                        (*level as u32 * keep / 100) as u8
+                    };
+                }
+            },
+        }
+    }
+}
+
+impl<D:Display> Display for Blender<D> {
+    fn set(&mut self, row:usize, col:usize, state:Pixel) -> Result<(),()> {
+        if self.mode == Blend::None {
+            return self.inner.set(row, col, state);
+        }
+        if row >= SCREEN_HEIGHT || col >= SCREEN_WIDTH {
+            return Err(());
+        }
+        self.drawn[row*SCREEN_WIDTH + col] = state == Pixel::On;
+        Ok(())
+    }
+
+    fn refresh(&mut self){
+        // blended frames are shown at the end of the frame
+        if self.mode == Blend::None {
+            self.inner.refresh();
+        }
+    }
+
+    fn end_frame(&mut self){
+        if self.mode == Blend::None {
+            return self.inner.end_frame();
+        }
+        self.blend();
+        let mut changed = false;
+        for (i, (&level, shown)) in self.levels.iter()
+                .zip(self.shown.iter_mut()).enumerate() {
+            if level != *shown {
+                let _ = self.inner.set_level(i / SCREEN_WIDTH,
+                                             i % SCREEN_WIDTH, level);
+                *shown = level;
+                changed = true;
+            }
+        }
+        if changed {
+            self.inner.refresh();
+        }
+        self.inner.end_frame();
+    }
+}
@@ -20,9 +20,9 @@ impl<A, D, I> Bus<A, D, I>
     pub fn new(audio:A, display:D, input:I) -> Bus<A, D, I> {
         Bus{
             memory:Memory::default(),
-            audio:audio,
-            display:display,
-            input:input,
+            audio,
+            display,
+            input,
         }
     }
 }
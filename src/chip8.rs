@@ -1,6 +1,94 @@
+use std::boxed::Box;
+use std::error;
+use std::fmt;
+#[cfg(feature = "std")]
+use std::io::Write;
+use std::mem;
+use std::string::String;
+#[cfg(feature = "std")]
+use std::thread;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
 use super::bus::Bus;
 use super::io::{Audio, Display, Input};
-use super::processor::Processor;
+use super::memory::{Memory, RAM_SIZE};
+use super::movie::{Check, Movie, MovieError, Playback, CHECK_INTERVAL};
+use super::processor::{ExecError, Processor, Registers};
+use super::quirks::Quirks;
+use super::random::{Random, XorShift};
+use super::sha1;
+use super::state::{Reader, StateError, Writer};
+
+// Constants
+///////////////////////////////////////////////////////////////////////
+
+/// Instructions executed per frame unless changed with `set_speed`.
+pub const DEFAULT_SPEED:usize = 10;
+
+/// Where programs are loaded unless `load_rom_at` says otherwise.
+pub const PROGRAM_START:u16 = 0x200;
+
+#[cfg(feature = "std")]
+const FRAME_NANOS:u64 = 1_000_000_000 / 60;
+
+// Errors
+///////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    Empty,
+    /// The ROM is `size` bytes but only `max` fit above the load address.
+    TooLarge{size:usize, max:usize},
+    /// Programs can't be loaded below `PROGRAM_START` or past the end of
+    /// memory.
+    BadAddress(u16),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Empty => write!(f, "ROM is empty"),
+            LoadError::TooLarge{size, max} => write!(f,
+                "ROM is {} bytes but at most {} bytes fit in memory",
+                size, max),
+            LoadError::BadAddress(address) => write!(f,
+                "cannot load a ROM at {:#05X}", address),
+        }
+    }
+}
+
+impl error::Error for LoadError {}
+
+/// Problems with a ROM that loaded anyway.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadWarning {
+    /// Instructions are two bytes, so an odd length suggests a truncated
+    /// or corrupt file.
+    OddLength(usize),
+}
+
+/// How much of the machine `Chip8::reset` restores.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reset {
+    /// Registers, timers, stack, keys and screen. Memory is left as is,
+    /// including anything the program wrote to it.
+    Soft,
+    /// Everything a soft reset does, and memory, including the font, is
+    /// restored with the ROM reloaded.
+    Hard,
+}
+
+impl fmt::Display for LoadWarning {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadWarning::OddLength(size) => write!(f,
+                "ROM is an odd number of bytes ({}), it may be truncated",
+                size),
+        }
+    }
+}
 
 // Chip-8 Implementation
 ///////////////////////////////////////////////////////////////////////
@@ -11,7 +99,17 @@ pub struct Chip8<A, D, I>
             D: Display,
             I: Input {
     processor:Processor,
-    bus:Bus<A, D, I>
+    bus:Bus<A, D, I>,
+    speed:usize,
+    steps:usize, // instructions run so far this frame
+    instructions:u64,
+    #[cfg(feature = "std")]
+    trace:Option<Box<dyn Write>>,
+    rom:Vec<u8>,
+    start:u16,
+    rom_hash:String,
+    recording:Option<Movie>,
+    playback:Option<Playback>,
 }
 
 impl<A, D, I> Chip8<A, D, I>
@@ -23,6 +121,16 @@ impl<A, D, I> Chip8<A, D, I>
         Chip8{
             processor:Processor::default(),
             bus:Bus::new(audio, display, input),
+            speed:DEFAULT_SPEED,
+            steps:0,
+            instructions:0,
+            #[cfg(feature = "std")]
+            trace:None,
+            rom:Vec::new(),
+            start:PROGRAM_START,
+            rom_hash:String::new(),
+            recording:None,
+            playback:None,
         }
     }
 }
@@ -32,13 +140,332 @@ impl<A, D, I> Chip8<A, D, I>
             A: Audio,
             D: Display,
             I: Input {
-    pub fn load_rom(&mut self, buff:&[u8]){
-        self.bus.memory.set_range(0x200, buff)
+    pub fn load_rom(&mut self, buff:&[u8])
+            -> Result<Vec<LoadWarning>, LoadError> {
+        self.load_rom_at(PROGRAM_START, buff)
+    }
+
+    /// Loads a program at `address` and starts executing there, after
+    /// clearing any program loaded before and soft resetting. The
+    /// ETI-660, for example, loads programs at 0x600.
+    pub fn load_rom_at(&mut self, address:u16, buff:&[u8])
+            -> Result<Vec<LoadWarning>, LoadError> {
+        if address < PROGRAM_START || address as usize >= RAM_SIZE {
+            return Err(LoadError::BadAddress(address));
+        }
+        let max = RAM_SIZE - address as usize;
+        if buff.is_empty() {
+            return Err(LoadError::Empty);
+        } else if buff.len() > max {
+            return Err(LoadError::TooLarge{size:buff.len(), max});
+        }
+
+        self.bus.memory.clear_program();
+        self.bus.memory.set_range(address, buff);
+        self.processor.reset(address);
+        self.steps = 0;
+        self.rom = buff.to_vec();
+        self.start = address;
+        self.rom_hash = sha1::hex_digest(buff);
+        self.stop_movie();
+
+        let mut warnings = Vec::new();
+        if buff.len() % 2 == 1 {
+            warnings.push(LoadWarning::OddLength(buff.len()));
+        }
+        Ok(warnings)
+    }
+
+    /// Restarts the loaded program. See `Reset` for what each kind of
+    /// reset restores. Stops any recording or playback.
+    pub fn reset(&mut self, reset:Reset){
+        if reset == Reset::Hard {
+            self.bus.memory = Memory::default();
+            self.bus.memory.set_range(self.start, &self.rom);
+        }
+        self.processor.reset(self.start);
+        self.steps = 0;
+        self.stop_movie();
+    }
+
+    /// Stops any recording or playback, for changes movies can't follow.
+    fn stop_movie(&mut self){
+        self.recording = None;
+        self.playback = None;
+        self.processor.take_recording();
+    }
+
+    /// Hard resets with the random number generator seeded with `seed`
+    /// and starts recording a movie of the run. Loading a ROM or state,
+    /// resetting, or changing the speed, quirks or random number source
+    /// stops the recording, as movies can't record those mid-run. They
+    /// stop playback the same way.
+    pub fn start_recording(&mut self, seed:u64){
+        self.seed(seed);
+        self.reset(Reset::Hard);
+        self.processor.start_recording();
+        self.recording = Some(Movie{
+            rom_hash:self.rom_hash.clone(),
+            seed,
+            quirks:self.processor.quirks(),
+            speed:self.speed,
+            start:self.start,
+            frames:0,
+            events:Vec::new(),
+            checks:Vec::new(),
+        });
+    }
+
+    /// Stops recording, returning the movie recorded, or `None` if the
+    /// machine wasn't recording.
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        let mut movie = self.recording.take()?;
+        movie.events = self.processor.take_recording().unwrap_or_default();
+        movie.frames = self.processor.frame();
+        Some(movie)
+    }
+
+    /// Replays `movie`, which must have been recorded with the loaded
+    /// ROM: the machine is set up as the recording was and hard reset.
+    /// The input device should be a `movie::Player` for the movie, fresh
+    /// or rewound. The state hashes in the movie are compared as frames
+    /// run; see `desync`.
+    pub fn play(&mut self, movie:&Movie) -> Result<(), MovieError> {
+        if movie.rom_hash != self.rom_hash {
+            return Err(MovieError::WrongRom{
+                expected:movie.rom_hash.clone(),
+                found:self.rom_hash.clone(),
+            });
+        }
+        if movie.start != self.start {
+            let rom = mem::take(&mut self.rom);
+            let result = self.load_rom_at(movie.start, &rom);
+            if result.is_err() {
+                self.rom = rom;
+                return Err(MovieError::BadStart(movie.start));
+            }
+        }
+
+        self.set_speed(movie.speed);
+        self.set_quirks(movie.quirks);
+        self.seed(movie.seed);
+        self.reset(Reset::Hard);
+        self.playback = Some(Playback::new(movie));
+        Ok(())
+    }
+
+    /// True while a movie is playing and has frames left.
+    pub fn playing(&self) -> bool {
+        match self.playback {
+            Some(ref playback) => self.processor.frame() < playback.frames(),
+            None => false,
+        }
+    }
+
+    /// The first frame of the movie being played whose state differs
+    /// from the recording's, if any.
+    pub fn desync(&self) -> Option<u64> {
+        self.playback.as_ref().and_then(|playback| playback.desync())
+    }
+
+    /// Frames run since the last reset.
+    pub fn frame(&self) -> u64 {
+        self.processor.frame()
+    }
+
+    /// The SHA-1 hash of the last ROM loaded, as used to key the ROM
+    /// database. Empty if no ROM has been loaded.
+    pub fn rom_hash(&self) -> &str {
+        &self.rom_hash
+    }
+
+    /// Returns the register `FX0A` will store the next key press in, or
+    /// `None` if the machine is not waiting on input.
+    pub fn waiting_for_key(&self) -> Option<usize> {
+        self.processor.waiting_for_key()
+    }
+
+    pub fn registers(&self) -> Registers {
+        self.processor.registers()
+    }
+
+    pub fn read_memory(&self, address:u16) -> u8 {
+        self.bus.memory.read_memory(address)
+    }
+
+    /// The number of instructions run since the machine was created, not
+    /// counting time spent waiting for a key or for the display.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn input(&self) -> &I {
+        &self.bus.input
+    }
+
+    pub fn display(&self) -> &D {
+        &self.bus.display
+    }
+
+    pub fn audio(&self) -> &A {
+        &self.bus.audio
+    }
+
+    /// Sets the number of instructions executed per 60 Hz frame. A new
+    /// speed stops any recording or playback.
+    pub fn set_speed(&mut self, speed:usize){
+        if speed != self.speed {
+            self.stop_movie();
+        }
+        self.speed = speed;
+    }
+
+    /// Sets the quirks. New quirks stop any recording or playback.
+    pub fn set_quirks(&mut self, quirks:Quirks){
+        if quirks != self.processor.quirks() {
+            self.stop_movie();
+        }
+        self.processor.set_quirks(quirks);
+    }
+
+    /// Replaces the random number source used by `CXNN` with the default
+    /// generator seeded with `seed`, making runs reproducible.
+    pub fn seed(&mut self, seed:u64){
+        self.set_rng(Box::new(XorShift::new(seed)));
+    }
+
+    /// Replaces the random number source used by `CXNN`. Its state is
+    /// included in save states. Stops any recording or playback.
+    pub fn set_rng(&mut self, rng:Box<dyn Random>){
+        self.stop_movie();
+        self.processor.set_rng(rng);
+    }
+
+    /// Writes the address and opcode of every instruction executed from
+    /// now on to `out`, one per line.
+    #[cfg(feature = "std")]
+    pub fn set_trace(&mut self, out:Option<Box<dyn Write>>){
+        self.trace = out;
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Writer::default();
+        self.processor.save_state(&mut state);
+        self.bus.memory.save_state(&mut state);
+        state.finish()
+    }
+
+    /// Restores a state from `save_state`, stopping any recording or
+    /// playback. On error the machine is left as it was.
+    pub fn load_state(&mut self, buff:&[u8]) -> Result<(), StateError> {
+        let mut state = Reader::new(buff)?;
+        let (processor, rng) = self.processor.read_state(&mut state)?;
+        let mut memory = Memory::default();
+        memory.load_state(&mut state)?;
+        state.finish()?;
+        self.processor.finish_load(processor, rng);
+
+        self.bus.memory = memory;
+        self.stop_movie();
+        self.steps = 0;
+        Ok(())
+    }
+
+    /// Runs one 60 Hz frame as fast as possible: `speed` instructions,
+    /// then a timer tick, screen refresh and input poll. Stops at an
+    /// instruction that fails, without finishing the frame. After `step`,
+    /// finishes the frame it started.
+    pub fn run_frame(&mut self) -> Result<(), ExecError> {
+        if self.speed == 0 {
+            self.end_frame();
+            return Ok(());
+        }
+        let frame = self.frame();
+        while self.frame() == frame {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Runs one instruction, for debuggers. The frame ends, as at the end
+    /// of `run_frame`, after `speed` instructions, or when a `DXYN` waits
+    /// for it. Fails without counting the instruction toward the frame.
+    pub fn step(&mut self) -> Result<(), ExecError> {
+        let runs = self.processor.waiting_for_key().is_none()
+            && !self.processor.waiting_for_display();
+        #[cfg(feature = "std")]
+        let line = self.trace.as_ref().map(|_| {
+            let pc = self.processor.pc() & 0x0FFF;
+            format!("{:03X}: {:02X}{:02X}", pc,
+                    self.bus.memory.read_memory(pc),
+                    self.bus.memory.read_memory(pc + 1))
+        });
+        let result = self.processor.step(&mut self.bus);
+        // a DXYN that starts waiting for the display hasn't run yet, and
+        // is counted and traced when it runs next frame
+        let retired = runs && !self.processor.waiting_for_display();
+        #[cfg(feature = "std")]
+        if let (Some(line), Some(out)) = (line, self.trace.as_mut()) {
+            if retired || result.is_err() {
+                let _ = writeln!(out, "{}", line);
+            }
+        }
+        result?;
+        if retired {
+            self.instructions += 1;
+        }
+        self.steps += 1;
+        if self.steps >= self.speed || self.processor.waiting_for_display() {
+            self.end_frame();
+        }
+        Ok(())
+    }
+
+    fn end_frame(&mut self){
+        self.steps = 0;
+        self.processor.tick(&mut self.bus);
+
+        let frame = self.processor.frame();
+        if frame % CHECK_INTERVAL == 0
+                && (self.recording.is_some() || self.playback.is_some()) {
+            let state = self.save_state();
+            if let Some(ref mut movie) = self.recording {
+                movie.checks.push(Check{frame, hash:sha1::hex_digest(&state)});
+            }
+            if let Some(ref mut playback) = self.playback {
+                playback.check(frame, &state);
+            }
+        }
+    }
+
+    /// Runs `frames` frames paced to 60 Hz, stopping at an instruction
+    /// that fails. Without std, which has no clock to pace with, call
+    /// `run_frame` from a 60 Hz timer instead.
+    #[cfg(feature = "std")]
+    pub fn run_frames(&mut self, frames:u64) -> Result<(), ExecError> {
+        let frame = Duration::from_nanos(FRAME_NANOS);
+        let mut next = Instant::now();
+        for _ in 0..frames {
+            self.run_frame()?;
+            next += frame;
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            } else {
+                // running behind, so don't try to catch up
+                next = now;
+            }
+        }
+        Ok(())
     }
 
-    pub fn run(&mut self) -> ! {
+    /// Runs paced to 60 Hz until an instruction fails, returning why.
+    #[cfg(feature = "std")]
+    pub fn run(&mut self) -> ExecError {
         loop {
-            self.processor.cycle(&mut self.bus);
+            if let Err(err) = self.run_frames(u64::MAX) {
+                return err;
+            }
         }
     }
 }
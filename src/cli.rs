@@ -0,0 +1,273 @@
+use std::str::FromStr;
+
+use config::Settings;
+
+pub const USAGE:&str = "\
+usage: chip-8 [options] <rom>
+
+options:
+    --speed <n>             instructions per frame (default 10)
+    --quirks <profile>      modern, vip, schip or xochip (default modern)
+    --scale <n>             terminal cells per pixel with --display
+                            block (default 1)
+    --keymap <name|file>    qwerty, azerty, dvorak, keypad or a keymap file
+    --show-keymap           print the keymap beside the screen
+    --seed <n>              seed the random number generator
+    --mute                  disable the beep
+    --status                show a status line below the screen
+    --colors <name>         default or inverse
+    --theme <name|colors>   draw in green, amber or lcd, or in colors
+                            given as #rrggbb,#rrggbb for off and on
+                            (default none)
+    --display <mode>        auto, block, halfblock or braille; auto
+                            uses half blocks if they fit the terminal
+                            and braille if not (default auto)
+    --blend <mode>          reduce flicker: none, or:<frames> to show
+                            pixels lit in any recent frame, or
+                            decay:<percent> to fade them out (default
+                            none)
+    --headless              run without a terminal, printing the final
+                            screen to stdout; requires --frames or
+                            --replay
+    --frames <n>            stop after n frames
+    --watch                 reload the ROM whenever the file changes
+    --debug                 run paused in a full-screen debugger
+    --load-address <addr>   load the ROM at addr, e.g. 0x600 for ETI-660
+                            programs (default 0x200)
+    --load-state <file>     resume from a save state
+    --save-state <file>     write a save state on exit
+    --record <file>         record the keys pressed to a movie file
+    --replay <file>         play back a movie recorded with --record,
+                            reporting any desync
+    --trace                 log each instruction to stderr
+    --config <file>         read settings from file instead of
+                            ~/.config/chip-8/config.ini
+    --romdb <file>          read ROM database overrides from file instead
+                            of ~/.config/chip-8/romdb.ini
+    --print-config          print the effective settings for the ROM
+    -h, --help              show this message
+
+Press Esc to quit, F1 to show the registers and upcoming instructions,
+F2 to show or hide the status line and F3 to pause.
+";
+
+// Options
+////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub rom:String,
+    /// Settings given on the command line, overriding the config file.
+    pub settings:Settings,
+    pub show_keymap:bool,
+    pub seed:Option<u64>,
+    pub headless:bool,
+    pub frames:Option<u64>,
+    pub watch:bool,
+    pub debug:bool,
+    pub load_address:Option<u16>,
+    pub load_state:Option<String>,
+    pub save_state:Option<String>,
+    pub record:Option<String>,
+    pub replay:Option<String>,
+    pub trace:bool,
+    pub config:Option<String>,
+    pub romdb:Option<String>,
+    pub print_config:bool,
+}
+
+impl Options {
+    fn new(rom:String) -> Options {
+        Options{
+            rom,
+            settings:Settings::default(),
+            show_keymap:false,
+            seed:None,
+            headless:false,
+            frames:None,
+            watch:false,
+            debug:false,
+            load_address:None,
+            load_state:None,
+            save_state:None,
+            record:None,
+            replay:None,
+            trace:false,
+            config:None,
+            romdb:None,
+            print_config:false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Run(Box<Options>),
+}
+
+// Parsing
+////////////////////////////////////////////////////////////////////////
+
+fn value<I:Iterator<Item=String>>(args:&mut I, flag:&str)
+        -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{} requires a value", flag))
+}
+
+fn number<T:FromStr, I:Iterator<Item=String>>(args:&mut I, flag:&str)
+        -> Result<T, String> {
+    let text = value(args, flag)?;
+    text.parse().map_err(|_| format!("{} expects a number, found '{}'",
+                                     flag, text))
+}
+
+/// Parses the arguments following the program name. Errors are messages
+/// suitable for printing after the program name.
+pub fn parse<I:Iterator<Item=String>>(mut args:I) -> Result<Command, String> {
+    let mut rom = None;
+    let mut options = Options::new(String::new());
+
+    while let Some(arg) = args.next() {
+        let flag = arg.as_str();
+        match flag {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--speed" | "--quirks" | "--scale" | "--keymap" | "--colors" |
+            "--theme" | "--display" | "--blend" => {
+                let text = value(&mut args, flag)?;
+                options.settings.set(&flag[2..], &text)
+                    .map_err(|err| format!("--{}", err))?;
+            },
+            "--show-keymap" => options.show_keymap = true,
+            "--seed" => options.seed = Some(number(&mut args, flag)?),
+            "--mute" => options.settings.mute = Some(true),
+            "--status" => options.settings.status = Some(true),
+            "--headless" => options.headless = true,
+            "--frames" => options.frames = Some(number(&mut args, flag)?),
+            "--watch" => options.watch = true,
+            "--debug" => options.debug = true,
+            "--load-address" => {
+                let text = value(&mut args, flag)?;
+                let address = match text.trim_start_matches("0x") {
+                    hex if hex.len() < text.len() =>
+                        u16::from_str_radix(hex, 16),
+                    _ => text.parse(),
+                };
+                options.load_address = Some(address.map_err(|_| {
+                    format!("{} expects an address, found '{}'", flag, text)
+                })?);
+            },
+            "--load-state" =>
+                options.load_state = Some(value(&mut args, flag)?),
+            "--save-state" =>
+                options.save_state = Some(value(&mut args, flag)?),
+            "--record" => options.record = Some(value(&mut args, flag)?),
+            "--replay" => options.replay = Some(value(&mut args, flag)?),
+            "--trace" => options.trace = true,
+            "--config" => options.config = Some(value(&mut args, flag)?),
+            "--romdb" => options.romdb = Some(value(&mut args, flag)?),
+            "--print-config" => options.print_config = true,
+            _ if flag.starts_with('-') && flag.len() > 1 =>
+                return Err(format!("unknown option '{}'", flag)),
+            _ if rom.is_some() =>
+                return Err(format!("unexpected argument '{}'", flag)),
+            _ => rom = Some(arg),
+        }
+    }
+
+    options.rom = rom.ok_or("missing ROM file")?;
+    if options.headless && options.frames.is_none()
+            && options.replay.is_none() {
+        return Err("--headless requires --frames or --replay".to_string());
+    }
+    if options.record.is_some() && options.replay.is_some() {
+        return Err("--record can't be used with --replay".to_string());
+    }
+    if options.load_state.is_some()
+            && (options.record.is_some() || options.replay.is_some()) {
+        return Err("movies start from a reset and can't be used with \
+                    --load-state".to_string());
+    }
+    if options.watch
+            && (options.record.is_some() || options.replay.is_some()) {
+        return Err("--watch can't be used with --record or --replay"
+                   .to_string());
+    }
+    if options.headless && options.watch {
+        return Err("--watch can't be used with --headless".to_string());
+    }
+    if options.headless && options.debug {
+        return Err("--debug can't be used with --headless".to_string());
+    }
+    Ok(Command::Run(Box::new(options)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args:&[&str]) -> Result<Command, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_defaults(){
+        assert_eq!(parse_args(&["pong.ch8"]),
+                   Ok(Command::Run(Box::new(Options::new(
+                       "pong.ch8".to_string())))));
+    }
+
+    #[test]
+    fn test_options(){
+        let command = parse_args(&[
+            "--speed", "20", "--quirks", "vip", "--seed", "42", "--mute",
+            "--headless", "--frames", "60", "--trace",
+            "--load-address", "0x600", "pong.ch8",
+        ]).unwrap();
+        let options = match command {
+            Command::Run(options) => options,
+            Command::Help => panic!("expected options"),
+        };
+        assert_eq!(options.rom, "pong.ch8");
+        assert_eq!(options.settings.speed, Some(20));
+        assert_eq!(options.settings.quirks, Some("vip".to_string()));
+        assert_eq!(options.settings.mute, Some(true));
+        assert_eq!(options.settings.scale, None);
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.frames, Some(60));
+        assert_eq!(options.load_address, Some(0x600));
+        assert!(options.headless && options.trace);
+    }
+
+    #[test]
+    fn test_help(){
+        assert_eq!(parse_args(&["pong.ch8", "--help"]), Ok(Command::Help));
+    }
+
+    #[test]
+    fn test_errors(){
+        assert!(parse_args(&[]).is_err());
+        assert!(parse_args(&["--speed", "fast", "pong.ch8"]).is_err());
+        assert!(parse_args(&["pong.ch8", "--speed"]).is_err());
+        assert!(parse_args(&["--quirks", "cosmac", "pong.ch8"]).is_err());
+        assert!(parse_args(&["--bogus", "pong.ch8"]).is_err());
+        assert!(parse_args(&["pong.ch8", "tetris.ch8"]).is_err());
+        assert!(parse_args(&["--headless", "pong.ch8"]).is_err());
+        assert!(parse_args(&["--headless", "--frames", "1", "--watch",
+                             "pong.ch8"]).is_err());
+        assert!(parse_args(&["--headless", "--frames", "1", "--debug",
+                             "pong.ch8"]).is_err());
+        assert!(parse_args(&["--scale", "0", "pong.ch8"]).is_err());
+        assert!(parse_args(&["--colors", "pink", "pong.ch8"]).is_err());
+        assert!(parse_args(&["--load-address", "0xZZ", "pong.ch8"]).is_err());
+        assert!(parse_args(&["--record", "a.movie", "--replay", "b.movie",
+                             "pong.ch8"]).is_err());
+        assert!(parse_args(&["--replay", "a.movie", "--load-state", "a.state",
+                             "pong.ch8"]).is_err());
+    }
+
+    #[test]
+    fn test_headless_replay(){
+        assert!(parse_args(&["--headless", "--replay", "a.movie",
+                             "pong.ch8"]).is_ok());
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::Read;
+use std::path::PathBuf;
+
+use chip_8::{Quirks, DEFAULT_SPEED};
+use chip_8::blend::Blend;
+use chip_8::ini;
+use chip_8::quirks::PROFILES;
+
+use theme::Theme;
+
+pub const COLORS:[&str;0x2] = ["default", "inverse"];
+pub const DISPLAYS:[&str;0x4] = ["auto", "block", "halfblock", "braille"];
+
+// Settings
+////////////////////////////////////////////////////////////////////////
+
+/// One layer of settings, from a config file section or the command
+/// line. Unset fields fall through to the layer below.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Settings {
+    pub speed:Option<usize>,
+    pub quirks:Option<String>,
+    pub keymap:Option<String>,
+    pub scale:Option<usize>,
+    pub mute:Option<bool>,
+    pub status:Option<bool>,
+    pub colors:Option<String>,
+    pub theme:Option<String>,
+    pub display:Option<String>,
+    pub blend:Option<Blend>,
+}
+
+fn one_of(name:&str, value:&str, values:&[&str]) -> Result<String, String> {
+    if values.contains(&value) {
+        Ok(value.to_string())
+    } else {
+        Err(format!("unknown {} '{}' (expected one of: {})",
+                    name, value, values.join(", ")))
+    }
+}
+
+impl Settings {
+    /// Sets the field named `key` from its text form, validating it.
+    pub fn set(&mut self, key:&str, value:&str) -> Result<(), String> {
+        let number = |value:&str| value.parse().map_err(|_| {
+            format!("{} expects a number, found '{}'", key, value)
+        });
+        let boolean = |value:&str| match value {
+            "true" | "yes" | "on" => Ok(true),
+            "false" | "no" | "off" => Ok(false),
+            _ => Err(format!("{} expects true or false, found '{}'", key,
+                             value)),
+        };
+        match key {
+            "speed" => self.speed = Some(number(value)?),
+            "quirks" => self.quirks = Some(one_of("quirk profile", value,
+                                                  &PROFILES)?),
+            "keymap" => self.keymap = Some(value.to_string()),
+            "scale" => match number(value)? {
+                0 => return Err("scale must be at least 1".to_string()),
+                scale => self.scale = Some(scale),
+            },
+            "mute" => self.mute = Some(boolean(value)?),
+            "status" => self.status = Some(boolean(value)?),
+            "colors" => self.colors = Some(one_of("colors", value, &COLORS)?),
+            "theme" => self.theme = match value {
+                "none" => Some(value.to_string()),
+                _ => Some(Theme::parse(value)?.to_string()),
+            },
+            "display" => self.display = Some(one_of("display mode", value,
+                                                    &DISPLAYS)?),
+            "blend" => self.blend = Some(Blend::parse(value)?),
+            _ => return Err(format!("unknown setting '{}'", key)),
+        }
+        Ok(())
+    }
+
+    /// Overlays the fields set in `other` onto these settings.
+    pub fn merge(&mut self, other:&Settings){
+        macro_rules! merge {
+            ($($field:ident),*) => {$(
+                if other.$field.is_some() {
+                    self.$field = other.$field.clone();
+                }
+            )*}
+        }
+        merge!(speed, quirks, keymap, scale, mute, status, colors, theme,
+               display, blend);
+    }
+
+    pub fn speed(&self) -> usize {
+        self.speed.unwrap_or(DEFAULT_SPEED)
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks.as_ref()
+            .and_then(|name| Quirks::profile(name))
+            .unwrap_or_default()
+    }
+
+    pub fn scale(&self) -> usize {
+        self.scale.unwrap_or(1)
+    }
+
+    /// The display mode, `auto` picking a renderer to fit the terminal.
+    pub fn display(&self) -> &str {
+        self.display.as_ref().map_or("auto", |name| name)
+    }
+
+    pub fn mute(&self) -> bool {
+        self.mute.unwrap_or(false)
+    }
+
+    /// Whether to start with the status line shown.
+    pub fn status(&self) -> bool {
+        self.status.unwrap_or(false)
+    }
+
+    pub fn blend(&self) -> Blend {
+        self.blend.unwrap_or_default()
+    }
+
+    /// The colors to draw in, if not the terminal's own.
+    pub fn theme(&self) -> Option<Theme> {
+        self.theme.as_ref().and_then(|theme| Theme::parse(theme).ok())
+    }
+
+    pub fn inverse(&self) -> bool {
+        self.colors.as_ref().is_some_and(|colors| colors == "inverse")
+    }
+}
+
+/// Prints every setting, with defaults filled in, in config file syntax.
+impl fmt::Display for Settings {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "speed = {}", self.speed())?;
+        writeln!(f, "quirks = {}", self.quirks.as_ref()
+                                      .map_or("modern", |name| name))?;
+        writeln!(f, "keymap = {}", self.keymap.as_ref()
+                                      .map_or("qwerty", |name| name))?;
+        writeln!(f, "scale = {}", self.scale())?;
+        writeln!(f, "mute = {}", self.mute())?;
+        writeln!(f, "status = {}", self.status())?;
+        writeln!(f, "colors = {}", self.colors.as_ref()
+                                      .map_or("default", |name| name))?;
+        writeln!(f, "theme = {}", self.theme.as_ref()
+                                     .map_or("none", |name| name))?;
+        writeln!(f, "display = {}", self.display())?;
+        writeln!(f, "blend = {}", self.blend())
+    }
+}
+
+// Config File
+////////////////////////////////////////////////////////////////////////
+
+/// `$XDG_CONFIG_HOME/chip-8`, falling back on `~/.config/chip-8`.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("chip-8"))
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse{line:usize, message:String},
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref path, ref err) =>
+                write!(f, "{}: {}", path.display(), err),
+            ConfigError::Parse{line, ref message} =>
+                write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+/// Settings from a config file: a global section, then sections headed
+/// `[<sha1>]` that apply only to the ROM with that SHA-1 hash.
+///
+/// ```ini
+/// speed = 12
+/// keymap = azerty
+///
+/// # a ROM written for the COSMAC VIP
+/// [0123456789abcdef0123456789abcdef01234567]
+/// quirks = vip
+/// ```
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    global:Settings,
+    roms:HashMap<String, Settings>,
+}
+
+impl Config {
+    pub fn parse(text:&str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        let mut section:Option<String> = None;
+
+        ini::parse(text, |line| match line {
+            ini::Line::Section(hash) => {
+                config.roms.entry(hash.clone()).or_default();
+                section = Some(hash);
+                Ok(())
+            },
+            ini::Line::Field(key, value) => match section {
+                Some(ref hash) => config.roms.get_mut(hash).unwrap()
+                                      .set(key, value),
+                None => config.global.set(key, value),
+            },
+        }).map_err(|err| ConfigError::Parse{
+            line:err.line,
+            message:err.message,
+        })?;
+
+        Ok(config)
+    }
+
+    /// Loads the config file at `path`. A missing file is an error only
+    /// when `required` is set.
+    pub fn load(path:PathBuf, required:bool) -> Result<Config, ConfigError> {
+        let mut text = String::new();
+        match File::open(&path) {
+            Ok(mut file) => file.read_to_string(&mut text)
+                .map_err(|err| ConfigError::Io(path.clone(), err))?,
+            Err(ref err) if !required && err.kind() == io::ErrorKind::NotFound =>
+                return Ok(Config::default()),
+            Err(err) => return Err(ConfigError::Io(path, err)),
+        };
+        Config::parse(&text)
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("config.ini"))
+    }
+
+    /// The settings for the ROM with SHA-1 hash `hash`: the global ones,
+    /// overlaid with the `recommended` ones from the ROM database, then
+    /// with the ROM's own section.
+    pub fn settings(&self, hash:&str, recommended:&Settings) -> Settings {
+        let mut settings = self.global.clone();
+        settings.merge(recommended);
+        if let Some(rom) = self.roms.get(hash) {
+            settings.merge(rom);
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH:&str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn test_parse_and_merge(){
+        let config = Config::parse("\
+            # global\n\
+            speed = 12\n\
+            keymap = azerty\n\
+            \n\
+            [0123456789ABCDEF0123456789ABCDEF01234567]\n\
+            speed = 30\n\
+            quirks = vip\n").unwrap();
+
+        let settings = config.settings(HASH, &Settings::default());
+        assert_eq!(settings.speed, Some(30));
+        assert_eq!(settings.quirks(), Quirks::profile("vip").unwrap());
+        assert_eq!(settings.keymap, Some("azerty".to_string()));
+
+        let other = config.settings("ffffffffffffffffffffffffffffffffffffffff",
+                                    &Settings::default());
+        assert_eq!(other.speed, Some(12));
+
+        let mut recommended = Settings::default();
+        recommended.set("speed", "20").unwrap();
+        recommended.set("keymap", "dvorak").unwrap();
+        let layered = config.settings(HASH, &recommended);
+        assert_eq!(layered.speed, Some(30));
+        assert_eq!(layered.keymap, Some("dvorak".to_string()));
+        assert_eq!(other.quirks(), Quirks::default());
+
+        let mut cli = Settings::default();
+        cli.set("speed", "5").unwrap();
+        let mut merged = settings.clone();
+        merged.merge(&cli);
+        assert_eq!(merged.speed(), 5);
+        assert_eq!(merged.quirks, settings.quirks);
+    }
+
+    #[test]
+    fn test_parse_errors(){
+        match Config::parse("speed = 1\nspeed = fast\n") {
+            Err(ConfigError::Parse{line:2, ..}) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(Config::parse("[pong]\n").is_err());
+        assert!(Config::parse("quirks = cosmac\n").is_err());
+        assert!(Config::parse("volume = 11\n").is_err());
+        assert!(Config::parse("mute\n").is_err());
+        assert!(Config::parse("display = sixel\n").is_err());
+        assert!(Config::parse("blend = or\n").is_err());
+        assert!(Config::parse("theme = pink\n").is_err());
+        assert!(Config::parse("blend = decay:101\n").is_err());
+    }
+
+    #[test]
+    fn test_display_round_trips(){
+        let mut settings = Settings::default();
+        settings.set("colors", "inverse").unwrap();
+        settings.set("status", "yes").unwrap();
+        settings.set("blend", "decay:40").unwrap();
+        settings.set("display", "braille").unwrap();
+        settings.set("theme", "#000000,#ffb000").unwrap();
+        let printed = settings.to_string();
+        let parsed = Config::parse(&printed).unwrap();
+        let settings = parsed.settings(HASH, &Settings::default());
+        assert_eq!(settings.to_string(), printed);
+        assert!(settings.inverse());
+        assert!(settings.status());
+        assert_eq!(settings.blend(), Blend::Decay(40));
+        assert_eq!(settings.display(), "braille");
+        assert_eq!(settings.theme(), Theme::parse("#000000,#ffb000").ok());
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::BTreeSet;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chip_8::{Chip8, Registers};
+use chip_8::blend::Blender;
+use chip_8::disasm::disassemble;
+use chip_8::io::{Audio, Display, Input};
+
+use screen::{self, key_f, Screen, Style, KEY_HOME, KEY_NPAGE, KEY_PPAGE};
+use terminal;
+
+/// Terminal columns the panes beside the game screen take.
+pub const PANES_WIDTH:usize = 40;
+/// Terminal rows the panes below the game screen take, at the least.
+pub const PANES_HEIGHT:usize = 8;
+
+const FRAME:Duration = Duration::from_nanos(1_000_000_000/60);
+
+// Pane columns, relative to the right of the game screen
+const REGISTERS_COL:usize = 2;
+const MEMORY_COL:usize = 2;
+const STACK_COL:usize = 32;
+const MEMORY_ROW:usize = 8;
+const MEMORY_BYTES:usize = 0x8;
+
+const HELP:&str = "F5 run/pause  F6 frame  F9 breakpoint  F10 step  \
+                   PgUp/PgDn move  Home pc  Esc quit";
+
+// Panes
+////////////////////////////////////////////////////////////////////////
+
+/// The register pane: V0 to VF, then I, PC, SP and the timers.
+pub fn register_lines(registers:&Registers) -> Vec<String> {
+    let mut lines:Vec<String> = registers.v.chunks(0x4).enumerate()
+        .map(|(row, values)| {
+            let cells:Vec<String> = values.iter().enumerate()
+                .map(|(col, value)| format!("v{:x} {:02X}", row*0x4 + col,
+                                            value))
+                .collect();
+            cells.join("  ")
+        })
+        .collect();
+    lines.push(format!("i  {:03X}  pc {:03X}  sp {:X}", registers.index,
+                       registers.pc, registers.sp));
+    lines.push(format!("dt {:02X}   st {:02X}", registers.delay_timer,
+                       registers.sound_timer));
+    lines
+}
+
+/// The stack pane: the calls in use, innermost first.
+pub fn stack_lines(registers:&Registers) -> Vec<String> {
+    (0..registers.sp.min(registers.stack.len())).rev()
+        .map(|level| format!("{:X}: {:03X}", level, registers.stack[level]))
+        .collect()
+}
+
+/// A line of the disassembly pane.
+#[derive(Debug, PartialEq)]
+pub struct Instruction {
+    pub text:String,
+    /// The instruction is the next to run.
+    pub pc:bool,
+    pub cursor:bool,
+}
+
+/// The disassembly pane: `rows` instructions, with `cursor` a third of
+/// the way down, marking breakpoints with `*` and the program counter
+/// with `>`.
+pub fn disassembly<F:Fn(u16) -> u8>(pc:u16, cursor:u16,
+                                    breakpoints:&BTreeSet<u16>, rows:usize,
+                                    read:F) -> Vec<Instruction> {
+    let start = cursor.wrapping_sub(2*(rows/3) as u16);
+    (0..rows).map(|row| {
+        let address = start.wrapping_add(2*row as u16) & 0x0FFF;
+        let opcode = (read(address) as u16) << 0x8
+            | read(address.wrapping_add(1) & 0x0FFF) as u16;
+        let mark = |set:bool, chr:char| if set {chr} else {' '};
+        Instruction{
+            text:format!("{}{} {:03X}: {:04X}  {}",
+                         mark(breakpoints.contains(&address), '*'),
+                         mark(address == pc, '>'), address, opcode,
+                         disassemble(opcode)),
+            pc:address == pc,
+            cursor:address == cursor,
+        }
+    }).collect()
+}
+
+/// The memory pane: `rows` lines of bytes, starting with the line holding
+/// `index`. Returns each line's address and text.
+pub fn memory_lines<F:Fn(u16) -> u8>(index:u16, rows:usize, read:F)
+        -> Vec<(u16, String)> {
+    let first = index & 0x0FFF & !(MEMORY_BYTES as u16 - 1);
+    (0..rows).map(|row| {
+        let address = first.wrapping_add((row*MEMORY_BYTES) as u16) & 0x0FFF;
+        let bytes:Vec<String> = (0..MEMORY_BYTES)
+            .map(|offset| format!("{:02X}",
+                                  read(address.wrapping_add(offset as u16))))
+            .collect();
+        (address, format!("{:03X}: {}", address, bytes.join(" ")))
+    }).collect()
+}
+
+/// The column of the byte at `index` in a memory line for `address`, if
+/// it's on that line.
+fn memory_column(address:u16, index:u16) -> Option<usize> {
+    let offset = (index & 0x0FFF).wrapping_sub(address) as usize;
+    match offset < MEMORY_BYTES {
+        true => Some(5 + 3*offset),
+        false => None,
+    }
+}
+
+// Debugger
+////////////////////////////////////////////////////////////////////////
+
+/// The debugger's state between frames.
+pub struct Debugger<S:Screen> {
+    screen:S,
+    breakpoints:BTreeSet<u16>,
+    cursor:u16,
+    running:bool,
+    /// Running was just resumed, so a breakpoint on the next instruction
+    /// has already stopped it.
+    resumed:bool,
+    message:String,
+    /// Game screen size in terminal rows and columns.
+    rows:usize,
+    columns:usize,
+}
+
+impl<S:Screen> Debugger<S> {
+    /// Creates a paused debugger for a game screen taking `rows` and
+    /// `columns` of the terminal.
+    pub fn new(screen:S, rows:usize, columns:usize) -> Debugger<S> {
+        Debugger{
+            screen,
+            breakpoints:BTreeSet::new(),
+            cursor:0x0,
+            running:false,
+            resumed:false,
+            message:String::new(),
+            rows,
+            columns,
+        }
+    }
+
+    /// Runs the machine under the debugger until the user quits or `more`
+    /// says to stop. `terminal` finds the terminal's input in `I`.
+    pub fn run<I, M, T>(&mut self,
+            machine:&mut Chip8<terminal::Audio<S>,
+                               Blender<terminal::Display<S>>, I>,
+            more:M, terminal:T)
+            where
+                I: Input,
+                M: Fn(&Chip8<terminal::Audio<S>,
+                             Blender<terminal::Display<S>>, I>) -> bool,
+                T: Fn(&I) -> &terminal::Input<S> {
+        self.cursor = machine.registers().pc;
+        let mut next = Instant::now();
+        while more(machine) && !terminal(machine.input()).quit() {
+            if self.running {
+                let resumed = self.resumed;
+                self.resumed = false;
+                self.run_frame(machine, resumed);
+                next += FRAME;
+                let now = Instant::now();
+                if next > now {
+                    thread::sleep(next - now);
+                } else {
+                    next = now;
+                }
+            } else {
+                // keys for the game are dropped while paused
+                terminal(machine.input()).get_keys();
+                thread::sleep(FRAME);
+                next = Instant::now();
+            }
+
+            for code in terminal(machine.input()).take_unmapped() {
+                self.command(machine, code);
+            }
+            self.draw(machine);
+        }
+    }
+
+    /// Runs the rest of the frame, pausing at a breakpoint, except one on
+    /// the first instruction if `resumed`.
+    fn run_frame<A, D, I>(&mut self, machine:&mut Chip8<A, D, I>,
+                          resumed:bool)
+            where
+                A: Audio,
+                D: Display,
+                I: Input {
+        let frame = machine.frame();
+        let mut skip = resumed;
+        while machine.frame() == frame {
+            let pc = machine.registers().pc;
+            if !skip && self.breakpoints.contains(&pc) {
+                self.pause(pc, format!("breakpoint at {:03X}", pc));
+                return;
+            }
+            skip = false;
+            if let Err(err) = machine.step() {
+                self.pause(pc, err.to_string());
+                return;
+            }
+        }
+        self.cursor = machine.registers().pc;
+    }
+
+    fn toggle_breakpoint(&mut self){
+        if !self.breakpoints.remove(&self.cursor) {
+            self.breakpoints.insert(self.cursor);
+        }
+    }
+
+    fn pause(&mut self, pc:u16, message:String){
+        self.running = false;
+        self.cursor = pc;
+        self.message = message;
+    }
+
+    fn command<A, D, I>(&mut self, machine:&mut Chip8<A, D, I>, code:i32)
+            where
+                A: Audio,
+                D: Display,
+                I: Input {
+        match code {
+            _ if code == key_f(5) => {
+                self.running = !self.running;
+                self.resumed = self.running;
+                self.message.clear();
+            },
+            _ if code == key_f(6) && !self.running => {
+                self.message.clear();
+                self.run_frame(machine, true);
+            },
+            _ if code == key_f(9) => self.toggle_breakpoint(),
+            _ if code == key_f(10) && !self.running => {
+                let pc = machine.registers().pc;
+                self.message.clear();
+                if let Err(err) = machine.step() {
+                    self.message = err.to_string();
+                }
+                self.cursor = machine.registers().pc;
+                if self.cursor == pc && machine.waiting_for_key().is_some() {
+                    self.message = "waiting for a key".to_string();
+                }
+            },
+            KEY_PPAGE => self.cursor = self.cursor.wrapping_sub(2) & 0x0FFF,
+            KEY_NPAGE => self.cursor = self.cursor.wrapping_add(2) & 0x0FFF,
+            KEY_HOME => self.cursor = machine.registers().pc,
+            _ => (),
+        }
+    }
+
+    fn draw<A, D, I>(&self, machine:&Chip8<A, D, I>)
+            where
+                A: Audio,
+                D: Display,
+                I: Input {
+        let (height, width) = self.screen.size();
+        let registers = machine.registers();
+        let read = |address| machine.read_memory(address);
+        let right = self.columns;
+
+        let put = |row:usize, col:usize, text:&str, width:usize,
+                   attributes:u8| {
+            self.screen.put(row, col, &format!("{:<1$.1$}", text, width),
+                            Style::attributes(attributes));
+        };
+        let bold = screen::BOLD;
+        let normal = 0x0;
+
+        put(0, right + REGISTERS_COL, "registers", 28, bold);
+        for (row, line) in register_lines(&registers).iter().enumerate() {
+            put(row + 1, right + REGISTERS_COL, line, 28, normal);
+        }
+
+        put(0, right + STACK_COL, "stack", 8, bold);
+        let stack = stack_lines(&registers);
+        for row in 0..registers.stack.len() {
+            let line = stack.get(row).map_or("", |line| line);
+            put(row + 1, right + STACK_COL, line, 8, normal);
+        }
+
+        let memory_rows = height.saturating_sub(MEMORY_ROW + 2);
+        put(MEMORY_ROW, right + MEMORY_COL, "memory", 28, bold);
+        let memory = memory_lines(registers.index, memory_rows, read);
+        for (row, &(address, ref line)) in memory.iter().enumerate() {
+            let row = MEMORY_ROW + 1 + row;
+            put(row, right + MEMORY_COL, line, 28, normal);
+            if let Some(col) = memory_column(address, registers.index) {
+                put(row, right + MEMORY_COL + col, &line[col..col + 2], 2,
+                    screen::REVERSE);
+            }
+        }
+
+        let top = self.rows + 1;
+        let disassembly_rows = height.saturating_sub(top + 2);
+        put(top, 0, "disassembly", self.columns, bold);
+        let lines = disassembly(registers.pc, self.cursor, &self.breakpoints,
+                                disassembly_rows, read);
+        for (row, line) in lines.iter().enumerate() {
+            let attributes = match (line.pc, line.cursor) {
+                (true, _) => screen::REVERSE,
+                (false, true) => screen::UNDERLINE,
+                (false, false) => normal,
+            };
+            put(top + 1 + row, 0, &line.text, self.columns, attributes);
+        }
+
+        let state = match self.running {
+            true => "running".to_string(),
+            false if self.message.is_empty() =>
+                format!("paused at {:03X}", registers.pc),
+            false => format!("paused: {}", self.message),
+        };
+        put(height.saturating_sub(1), 0, &format!("{} | {}", state, HELP),
+            width, screen::REVERSE);
+        self.screen.refresh();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM:[u8;0x6] = [0x60, 0x05, 0xA2, 0xF0, 0x12, 0x00];
+
+    fn read(address:u16) -> u8 {
+        match address {
+            0x200..=0x205 => PROGRAM[address as usize - 0x200],
+            _ => address as u8,
+        }
+    }
+
+    fn registers() -> Registers {
+        let mut stack = [0x0;0x10];
+        stack[0x0] = 0x204;
+        stack[0x1] = 0x31E;
+        Registers{
+            v:[0x0;0x10],
+            index:0x2F3,
+            pc:0x202,
+            sp:2,
+            stack,
+            delay_timer:0x0,
+            sound_timer:0x10,
+        }
+    }
+
+    #[test]
+    fn test_register_and_stack_lines(){
+        let lines = register_lines(&registers());
+        assert_eq!(lines[0], "v0 00  v1 00  v2 00  v3 00");
+        assert_eq!(lines[4], "i  2F3  pc 202  sp 2");
+        assert_eq!(lines[5], "dt 00   st 10");
+        assert_eq!(stack_lines(&registers()), ["1: 31E", "0: 204"]);
+    }
+
+    #[test]
+    fn test_disassembly(){
+        let breakpoints:BTreeSet<u16> = [0x204].iter().cloned().collect();
+        let lines = disassembly(0x202, 0x200, &breakpoints, 4, read);
+        let text:Vec<&str> = lines.iter().map(|line| line.text.as_str())
+            .collect();
+        assert_eq!(text, [
+            "   1FE: FEFF  db 0xFE, 0xFF",
+            "   200: 6005  ld v0, 0x05",
+            " > 202: A2F0  ld i, 0x2F0",
+            "*  204: 1200  jp 0x200",
+        ]);
+        assert!(lines[2].pc && !lines[2].cursor);
+        assert!(lines[1].cursor);
+    }
+
+    #[test]
+    fn test_memory_lines(){
+        let lines = memory_lines(0x2F3, 2, read);
+        assert_eq!(lines[0], (0x2F0, "2F0: F0 F1 F2 F3 F4 F5 F6 F7"
+                                         .to_string()));
+        assert_eq!(lines[1].0, 0x2F8);
+        assert_eq!(memory_column(0x2F0, 0x2F3), Some(14));
+        assert_eq!(&lines[0].1[14..16], "F3");
+        assert_eq!(memory_column(0x2F8, 0x2F3), None);
+    }
+}
@@ -0,0 +1,52 @@
+use std::string::{String, ToString};
+
+/// The assembly for `opcode`, in the syntax of the listings in
+/// `tests/roms`: `ld v0, 0x3C`, `drw v0, v1, 5`. Addresses are shown as
+/// numbers, with no labels. Opcodes that aren't instructions are shown
+/// as data, `db 0x12, 0x34`.
+pub fn disassemble(opcode:u16) -> String {
+    let x = (opcode >> 0x8) & 0xF;
+    let y = (opcode >> 0x4) & 0xF;
+    let n = opcode & 0xF;
+    let nn = opcode & 0xFF;
+    let nnn = opcode & 0xFFF;
+
+    match (opcode >> 0xC, x, y, n) {
+        (0x0, 0x0, 0xE, 0x0) => "cls".to_string(),
+        (0x0, 0x0, 0xE, 0xE) => "ret".to_string(),
+        (0x0, _, _, _) => format!("sys {:#05X}", nnn),
+        (0x1, _, _, _) => format!("jp {:#05X}", nnn),
+        (0x2, _, _, _) => format!("call {:#05X}", nnn),
+        (0x3, _, _, _) => format!("se v{:x}, {:#04X}", x, nn),
+        (0x4, _, _, _) => format!("sne v{:x}, {:#04X}", x, nn),
+        (0x5, _, _, 0x0) => format!("se v{:x}, v{:x}", x, y),
+        (0x6, _, _, _) => format!("ld v{:x}, {:#04X}", x, nn),
+        (0x7, _, _, _) => format!("add v{:x}, {:#04X}", x, nn),
+        (0x8, _, _, 0x0) => format!("ld v{:x}, v{:x}", x, y),
+        (0x8, _, _, 0x1) => format!("or v{:x}, v{:x}", x, y),
+        (0x8, _, _, 0x2) => format!("and v{:x}, v{:x}", x, y),
+        (0x8, _, _, 0x3) => format!("xor v{:x}, v{:x}", x, y),
+        (0x8, _, _, 0x4) => format!("add v{:x}, v{:x}", x, y),
+        (0x8, _, _, 0x5) => format!("sub v{:x}, v{:x}", x, y),
+        (0x8, _, _, 0x6) => format!("shr v{:x}, v{:x}", x, y),
+        (0x8, _, _, 0x7) => format!("subn v{:x}, v{:x}", x, y),
+        (0x8, _, _, 0xE) => format!("shl v{:x}, v{:x}", x, y),
+        (0x9, _, _, 0x0) => format!("sne v{:x}, v{:x}", x, y),
+        (0xA, _, _, _) => format!("ld i, {:#05X}", nnn),
+        (0xB, _, _, _) => format!("jp v0, {:#05X}", nnn),
+        (0xC, _, _, _) => format!("rnd v{:x}, {:#04X}", x, nn),
+        (0xD, _, _, _) => format!("drw v{:x}, v{:x}, {}", x, y, n),
+        (0xE, _, 0x9, 0xE) => format!("skp v{:x}", x),
+        (0xE, _, 0xA, 0x1) => format!("sknp v{:x}", x),
+        (0xF, _, 0x0, 0x7) => format!("ld v{:x}, dt", x),
+        (0xF, _, 0x0, 0xA) => format!("ld v{:x}, k", x),
+        (0xF, _, 0x1, 0x5) => format!("ld dt, v{:x}", x),
+        (0xF, _, 0x1, 0x8) => format!("ld st, v{:x}", x),
+        (0xF, _, 0x1, 0xE) => format!("add i, v{:x}", x),
+        (0xF, _, 0x2, 0x9) => format!("ld f, v{:x}", x),
+        (0xF, _, 0x3, 0x3) => format!("ld b, v{:x}", x),
+        (0xF, _, 0x5, 0x5) => format!("ld [i], v{:x}", x),
+        (0xF, _, 0x6, 0x5) => format!("ld v{:x}, [i]", x),
+        _ => format!("db {:#04X}, {:#04X}", opcode >> 0x8, nn),
+    }
+}
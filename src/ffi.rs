@@ -0,0 +1,264 @@
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use super::chip8::{Chip8, LoadError};
+use super::headless::{FrameBuffer, HeldKeys, NullAudio};
+use super::io::{key_bit, Pixel};
+
+// C API
+////////////////////////////////////////////////////////////////////////
+// The functions here make up the C ABI, declared in include/chip8.h,
+// which cbindgen generates from this file. Change a signature or a
+// status's value only along with CHIP8_ABI_VERSION.
+
+/// Bumped whenever the ABI changes incompatibly.
+pub const CHIP8_ABI_VERSION:u32 = 1;
+/// The framebuffer's size, one byte per pixel.
+pub const CHIP8_SCREEN_WIDTH:usize = 0x40;
+pub const CHIP8_SCREEN_HEIGHT:usize = 0x20;
+
+/// What a call did. Every function that can fail returns one.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chip8Status {
+    Ok = 0,
+    /// A pointer argument was null.
+    NullPointer = 1,
+    RomEmpty = 2,
+    /// The ROM doesn't fit in memory.
+    RomTooLarge = 3,
+    /// An instruction failed, stopping the frame before it.
+    ExecFailed = 4,
+    /// The save state is corrupt or from an unsupported version.
+    BadState = 5,
+    /// The output buffer is too small; nothing was written.
+    BufferTooSmall = 6,
+    /// The emulator panicked. The machine can only be freed.
+    Panicked = 7,
+}
+
+impl Chip8Status {
+    const ALL:[Chip8Status;0x8] = [
+        Chip8Status::Ok, Chip8Status::NullPointer, Chip8Status::RomEmpty,
+        Chip8Status::RomTooLarge, Chip8Status::ExecFailed,
+        Chip8Status::BadState, Chip8Status::BufferTooSmall,
+        Chip8Status::Panicked,
+    ];
+
+    /// The status with the value `value`, which C may pass out of range.
+    fn from_u32(value:u32) -> Option<Chip8Status> {
+        Chip8Status::ALL.iter().cloned()
+            .find(|&status| status as u32 == value)
+    }
+}
+
+/// A machine, known to C only by pointer.
+pub struct Chip8Machine {
+    chip8:Chip8<NullAudio, FrameBuffer, HeldKeys>,
+}
+
+/// Runs `f` on the machine behind `machine`, turning a null pointer or a
+/// panic into a status.
+unsafe fn with_machine<F>(machine:*mut Chip8Machine, f:F) -> Chip8Status
+        where F: FnOnce(&mut Chip8Machine) -> Chip8Status {
+    if machine.is_null() {
+        return Chip8Status::NullPointer;
+    }
+    let machine = &mut *machine;
+    panic::catch_unwind(AssertUnwindSafe(|| f(machine)))
+        .unwrap_or(Chip8Status::Panicked)
+}
+
+/// The bytes from `data`, which may only be null if `size` is 0.
+unsafe fn bytes<'a>(data:*const u8, size:usize) -> Option<&'a [u8]> {
+    match (data.is_null(), size) {
+        (_, 0x0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts(data, size)),
+    }
+}
+
+/// The version of the ABI the library was built with, to compare with
+/// the header's CHIP8_ABI_VERSION.
+#[no_mangle]
+pub extern "C" fn chip8_abi_version() -> u32 {
+    CHIP8_ABI_VERSION
+}
+
+/// A description of `status`, a `Chip8Status`, as a static string.
+/// Values that aren't a status give "unknown status".
+#[no_mangle]
+pub extern "C" fn chip8_status_message(status:u32) -> *const c_char {
+    let message:&'static [u8] = match Chip8Status::from_u32(status) {
+        Some(Chip8Status::Ok) => b"ok\0",
+        Some(Chip8Status::NullPointer) => b"null pointer\0",
+        Some(Chip8Status::RomEmpty) => b"ROM is empty\0",
+        Some(Chip8Status::RomTooLarge) => b"ROM does not fit in memory\0",
+        Some(Chip8Status::ExecFailed) => b"instruction failed\0",
+        Some(Chip8Status::BadState) => b"invalid save state\0",
+        Some(Chip8Status::BufferTooSmall) => b"buffer too small\0",
+        Some(Chip8Status::Panicked) => b"emulator panicked\0",
+        None => b"unknown status\0",
+    };
+    message.as_ptr() as *const c_char
+}
+
+/// Creates a machine with no ROM loaded, to free with `chip8_free`.
+/// Returns null if that panics.
+#[no_mangle]
+pub extern "C" fn chip8_new() -> *mut Chip8Machine {
+    panic::catch_unwind(|| {
+        let chip8 = Chip8::new(NullAudio::default(), FrameBuffer::default(),
+                               HeldKeys::default());
+        Box::into_raw(Box::new(Chip8Machine{chip8}))
+    }).unwrap_or(ptr::null_mut())
+}
+
+/// Frees a machine from `chip8_new`. Null is ignored.
+///
+/// # Safety
+///
+/// `machine` must be null or from `chip8_new`, and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(machine:*mut Chip8Machine){
+    if !machine.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            drop(Box::from_raw(machine));
+        }));
+    }
+}
+
+/// Loads `size` bytes of ROM from `rom` and starts it over.
+///
+/// # Safety
+///
+/// `machine` must be from `chip8_new`, and `rom` must point to `size`
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(machine:*mut Chip8Machine,
+                                        rom:*const u8, size:usize)
+        -> Chip8Status {
+    let rom = match bytes(rom, size) {
+        Some(rom) => rom,
+        None => return Chip8Status::NullPointer,
+    };
+    with_machine(machine, |machine| match machine.chip8.load_rom(rom) {
+        Ok(_) => Chip8Status::Ok,
+        Err(LoadError::Empty) => Chip8Status::RomEmpty,
+        Err(_) => Chip8Status::RomTooLarge,
+    })
+}
+
+/// Runs one frame. The caller paces frames to 60 Hz.
+///
+/// # Safety
+///
+/// `machine` must be from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frame(machine:*mut Chip8Machine)
+        -> Chip8Status {
+    with_machine(machine, |machine| match machine.chip8.run_frame() {
+        Ok(()) => Chip8Status::Ok,
+        Err(_) => Chip8Status::ExecFailed,
+    })
+}
+
+/// Sets which keys are held down, bit `n` set for key `n`. Only keys
+/// that weren't already down are pressed, so this can be called with the
+/// keys down every frame.
+///
+/// # Safety
+///
+/// `machine` must be from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_keys(machine:*mut Chip8Machine,
+                                        keys:u16) -> Chip8Status {
+    with_machine(machine, |machine| {
+        let input = machine.chip8.input();
+        for key in 0x0..0x10 {
+            input.set(key, keys & key_bit(key) != 0);
+        }
+        Chip8Status::Ok
+    })
+}
+
+/// Copies the screen to `out`, row by row, a byte per pixel: 1 for lit
+/// and 0 for dark. `size` must be at least CHIP8_SCREEN_WIDTH *
+/// CHIP8_SCREEN_HEIGHT.
+///
+/// # Safety
+///
+/// `machine` must be from `chip8_new`, and `out` must point to `size`
+/// writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(machine:*mut Chip8Machine,
+                                           out:*mut u8, size:usize)
+        -> Chip8Status {
+    if out.is_null() {
+        return Chip8Status::NullPointer;
+    }
+    with_machine(machine, |machine| {
+        let pixels = machine.chip8.display().pixels();
+        if size < pixels.len() {
+            return Chip8Status::BufferTooSmall;
+        }
+        let out = slice::from_raw_parts_mut(out, pixels.len());
+        for (byte, &pixel) in out.iter_mut().zip(pixels) {
+            *byte = (pixel == Pixel::On) as u8;
+        }
+        Chip8Status::Ok
+    })
+}
+
+/// Saves the machine's state to `out`, storing its length in `written`.
+/// If `size` is too small, only stores the length, so calling with a
+/// null `out` and a `size` of 0 asks how large a buffer to pass.
+///
+/// # Safety
+///
+/// `machine` must be from `chip8_new`, `out` must be null or point to
+/// `size` writable bytes, and `written` must point to a `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(machine:*mut Chip8Machine,
+                                          out:*mut u8, size:usize,
+                                          written:*mut usize)
+        -> Chip8Status {
+    if written.is_null() {
+        return Chip8Status::NullPointer;
+    }
+    with_machine(machine, |machine| {
+        let state = machine.chip8.save_state();
+        *written = state.len();
+        if size < state.len() {
+            return Chip8Status::BufferTooSmall;
+        }
+        if out.is_null() {
+            return Chip8Status::NullPointer;
+        }
+        ptr::copy_nonoverlapping(state.as_ptr(), out, state.len());
+        Chip8Status::Ok
+    })
+}
+
+/// Restores a state from `chip8_save_state`. An invalid state leaves the
+/// machine as it was.
+///
+/// # Safety
+///
+/// `machine` must be from `chip8_new`, and `state` must point to `size`
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_state(machine:*mut Chip8Machine,
+                                          state:*const u8, size:usize)
+        -> Chip8Status {
+    let state = match bytes(state, size) {
+        Some(state) => state,
+        None => return Chip8Status::NullPointer,
+    };
+    with_machine(machine, |machine| match machine.chip8.load_state(state) {
+        Ok(()) => Chip8Status::Ok,
+        Err(_) => Chip8Status::BadState,
+    })
+}
@@ -0,0 +1,214 @@
+use std::cell::Cell;
+use std::string::String;
+use std::vec::Vec;
+
+use super::io::{key_bit, Audio, Display, Input, Pixel};
+use super::io::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+// Headless IO Devices
+////////////////////////////////////////////////////////////////////////
+
+/// Audio device that only counts beeps.
+#[derive(Debug, Default)]
+pub struct NullAudio {
+    beeps:Cell<usize>,
+}
+
+impl NullAudio {
+    pub fn beeps(&self) -> usize {
+        self.beeps.get()
+    }
+}
+
+impl Audio for NullAudio {
+    fn beep(&self){
+        self.beeps.set(self.beeps.get() + 1);
+    }
+}
+
+/// Display that keeps the last refreshed frame in memory.
+pub struct FrameBuffer {
+    waiting:[Pixel;SCREEN_WIDTH*SCREEN_HEIGHT],
+    frame:[Pixel;SCREEN_WIDTH*SCREEN_HEIGHT],
+}
+
+impl Default for FrameBuffer {
+    fn default() -> FrameBuffer {
+        FrameBuffer{
+            waiting:[Pixel::Off;SCREEN_WIDTH*SCREEN_HEIGHT],
+            frame:[Pixel::Off;SCREEN_WIDTH*SCREEN_HEIGHT],
+        }
+    }
+}
+
+impl FrameBuffer {
+    /// The last refreshed frame, row by row.
+    pub fn pixels(&self) -> &[Pixel] {
+        &self.frame
+    }
+
+    pub fn get(&self, row:usize, col:usize) -> Pixel {
+        self.frame[row*SCREEN_WIDTH + col]
+    }
+
+    /// Renders the last refreshed frame with `#` for lit pixels and `.`
+    /// for dark ones, one line per row.
+    pub fn to_ascii(&self) -> String {
+        let mut out = String::with_capacity((SCREEN_WIDTH + 1)*SCREEN_HEIGHT);
+        for row in self.frame.chunks(SCREEN_WIDTH) {
+            for &pixel in row {
+                out.push(if pixel == Pixel::On {'#'} else {'.'});
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Renders the last refreshed frame as a plain PBM image, lit pixels
+    /// being black.
+    pub fn to_pbm(&self) -> String {
+        let mut out = format!("P1\n{} {}\n", SCREEN_WIDTH, SCREEN_HEIGHT);
+        for row in self.frame.chunks(SCREEN_WIDTH) {
+            for &pixel in row {
+                out.push(if pixel == Pixel::On {'1'} else {'0'});
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// The last refreshed frame as RGBA bytes, row by row, lit pixels
+    /// being white and dark ones black.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(SCREEN_WIDTH*SCREEN_HEIGHT*4);
+        for &pixel in self.frame.iter() {
+            let value = if pixel == Pixel::On {0xFF} else {0x0};
+            out.extend_from_slice(&[value, value, value, 0xFF]);
+        }
+        out
+    }
+}
+
+impl Display for FrameBuffer {
+    fn set(&mut self, row:usize, col:usize, state:Pixel) -> Result<(),()> {
+        if row >= SCREEN_HEIGHT || col >= SCREEN_WIDTH {
+            return Err(());
+        }
+        self.waiting[row*SCREEN_WIDTH + col] = state;
+        Ok(())
+    }
+
+    fn refresh(&mut self){
+        self.frame = self.waiting;
+    }
+}
+
+/// Input device with no keys pressed.
+#[derive(Debug, Default)]
+pub struct NoInput {}
+
+impl Input for NoInput {
+    fn get_keys(&self) -> u16 {
+        0x0
+    }
+}
+
+/// Input device pressing and releasing keys on given frames. Every poll
+/// is a frame, the first being frame 0.
+#[derive(Debug, Default)]
+pub struct ScriptedInput {
+    keys:Vec<(u64, u8)>,
+    releases:Vec<(u64, u8)>,
+    frame:Cell<u64>,
+}
+
+impl ScriptedInput {
+    pub fn new(keys:&[(u64, u8)]) -> ScriptedInput {
+        ScriptedInput{keys:keys.to_vec(), ..ScriptedInput::default()}
+    }
+
+    /// Presses `key` on `frame`.
+    pub fn press(&mut self, frame:u64, key:u8){
+        self.keys.push((frame, key));
+    }
+
+    /// Releases `key` on `frame`.
+    pub fn release(&mut self, frame:u64, key:u8){
+        self.releases.push((frame, key));
+    }
+}
+
+impl Input for ScriptedInput {
+    fn get_keys(&self) -> u16 {
+        let frame = self.frame.get();
+        self.frame.set(frame + 1);
+        self.keys.iter()
+            .filter(|&&(when, _)| when == frame)
+            .fold(0x0, |keys, &(_, key)| keys | key_bit(key))
+    }
+
+    fn get_released(&self) -> u16 {
+        // the frame get_keys just polled
+        let frame = self.frame.get().wrapping_sub(1);
+        self.releases.iter()
+            .filter(|&&(when, _)| when == frame)
+            .fold(0x0, |keys, &(_, key)| keys | key_bit(key))
+    }
+}
+
+/// Input device holding down the keys it's told are down, for frontends
+/// that are told when each key goes down and up, such as web pages. A
+/// key is pressed once when it goes down and stays down until it's let
+/// go, however long that is.
+#[derive(Debug, Default)]
+pub struct HeldKeys {
+    down:Cell<u16>,
+    /// Keys pressed since the last poll.
+    pressed:Cell<u16>,
+    /// Keys let go since the last poll.
+    released:Cell<u16>,
+    /// Keys the last poll released.
+    releasing:Cell<u16>,
+}
+
+impl HeldKeys {
+    /// Presses or lets go of `key`. Keys past F are ignored, as is
+    /// pressing a key that's already down, so frontends can report every
+    /// key down on every frame.
+    pub fn set(&self, key:u8, down:bool){
+        let bit = key_bit(key);
+        if down && self.down.get() & bit == 0 {
+            self.down.set(self.down.get() | bit);
+            self.pressed.set(self.pressed.get() | bit);
+            self.released.set(self.released.get() & !bit);
+        } else if !down && self.down.get() & bit != 0 {
+            self.down.set(self.down.get() & !bit);
+            self.released.set(self.released.get() | bit);
+        }
+    }
+
+    /// The keys down, bit `n` set for key `n`.
+    pub fn down(&self) -> u16 {
+        self.down.get()
+    }
+}
+
+impl Input for HeldKeys {
+    fn get_keys(&self) -> u16 {
+        let pressed = self.pressed.replace(0x0);
+        // keys pressed and let go since the last poll are released on the
+        // next, so the press isn't lost
+        let released = self.released.get();
+        self.released.set(released & pressed);
+        self.releasing.set(released & !pressed);
+        pressed
+    }
+
+    fn get_released(&self) -> u16 {
+        self.releasing.replace(0x0)
+    }
+
+    fn get_held(&self) -> u16 {
+        self.down.get()
+    }
+}
@@ -0,0 +1,70 @@
+use std::error;
+use std::fmt;
+use std::string::String;
+
+use super::sha1;
+
+// INI Files
+////////////////////////////////////////////////////////////////////////
+
+// The ROM database and the emulator's config file are both INI files
+// with a section per ROM, headed by the SHA-1 hash of its image.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line:usize,
+    pub message:String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for ParseError {}
+
+/// A line of an INI file keyed by ROM hashes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line<'a> {
+    /// A `[<sha1>]` section header, with the hash in lowercase.
+    Section(String),
+    /// A `key = value` line, both trimmed.
+    Field(&'a str, &'a str),
+}
+
+/// Parses `text`, passing each section header and field to `handle` in
+/// order. Blank lines and lines starting with `#` or `;` are skipped.
+/// Stops at the first line that is neither a SHA-1 hash section nor a
+/// field, or that `handle` fails on.
+pub fn parse<'a, F>(text:&'a str, mut handle:F) -> Result<(), ParseError>
+        where F: FnMut(Line<'a>) -> Result<(), String> {
+    for (number, line) in text.lines().enumerate() {
+        let error = |message:String| ParseError{line:number + 1, message};
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        let parsed = if line.starts_with('[') && line.ends_with(']') {
+            let hash = line[1..line.len() - 1].trim().to_lowercase();
+            if hash.len() != 2*sha1::DIGEST_SIZE
+                    || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(error(format!("section '{}' is not a SHA-1 \
+                                          hash", hash)));
+            }
+            Line::Section(hash)
+        } else {
+            match line.find('=') {
+                Some(split) => Line::Field(line[..split].trim(),
+                                           line[split + 1..].trim()),
+                None => return Err(error(format!("expected '<key> = \
+                                                  <value>', found '{}'",
+                                                 line))),
+            }
+        };
+        handle(parsed).map_err(error)?;
+    }
+    Ok(())
+}
@@ -11,13 +11,47 @@ pub trait Audio {
     fn beep(&self);
 }
 
+/// Brightness of a fully lit pixel, for displays that show levels.
+pub const MAX_LEVEL:u8 = 0xFF;
+
 pub trait Display {
+    #[allow(clippy::result_unit_err)]
     fn set(&mut self, row:usize, col:usize, state:Pixel)
             -> Result<(),()>;
+    /// Sets a pixel to a brightness from 0 to `MAX_LEVEL`. Displays that
+    /// only show on and off light pixels brighter than half of
+    /// `MAX_LEVEL`.
+    #[allow(clippy::result_unit_err)]
+    fn set_level(&mut self, row:usize, col:usize, level:u8)
+            -> Result<(),()> {
+        let state = if level > MAX_LEVEL / 2 {Pixel::On} else {Pixel::Off};
+        self.set(row, col, state)
+    }
     fn refresh(&mut self);
+    /// Called at the end of every frame, whether or not it was redrawn,
+    /// for displays that change over time.
+    fn end_frame(&mut self){}
+}
+
+/// The bit for `key` in the keys `Input` reports, which is none for keys
+/// past 0xF.
+pub fn key_bit(key:u8) -> u16 {
+    1u16.checked_shl(key as u32).unwrap_or(0x0)
 }
 
 pub trait Input {
-    fn get_keys(&self) -> Vec<u8>;
-    fn get_key(&self) -> u8;
+    /// The keys pressed since the last call, bit `n` set for key `n`.
+    fn get_keys(&self) -> u16;
+    /// The keys released since the last `get_keys`, for input devices
+    /// that can tell. Keys from devices that can't stay down for a while
+    /// after each press.
+    fn get_released(&self) -> u16 {
+        0x0
+    }
+    /// The keys down now, for input devices that report every release.
+    /// A key pressed while down here stays down until it's released,
+    /// instead of for a while.
+    fn get_held(&self) -> u16 {
+        0x0
+    }
 }
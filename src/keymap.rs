@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::Read;
+use std::path::Path;
+
+use screen;
+
+// Presets
+////////////////////////////////////////////////////////////////////////
+
+// Terminal keys for the CHIP-8 keys 0x0 through 0xF, by layout. Each
+// layout covers the same physical block of keys as the qwerty one.
+const QWERTY:&str = "1234qwerasdfzxcv";
+const AZERTY:&str = "&\u{E9}\"'azerqsdfwxcv";
+// AZERTY's top row sends digits only with Shift or caps lock, so those
+// are mapped as well
+const AZERTY_DIGITS:&str = "1234";
+const DVORAK:&str = "1234',.paoeu;qjk";
+
+// Numeric keypad with num lock on: digits map to themselves and the
+// operator keys fill in 0xA through 0xF.
+const KEYPAD:[(i32, u8);0x10] = [
+    (0x30, 0x0), (0x31, 0x1), (0x32, 0x2), (0x33, 0x3),
+    (0x34, 0x4), (0x35, 0x5), (0x36, 0x6), (0x37, 0x7),
+    (0x38, 0x8), (0x39, 0x9), (0x2F, 0xA), (0x2A, 0xB),
+    (0x2D, 0xC), (0x2B, 0xD), (0x0A, 0xE), (0x2E, 0xF),
+];
+
+// Arrow keys are layered onto every preset, using the 2/4/6/8 movement
+// keys most games expect.
+const ARROWS:[(i32, u8);0x4] = [
+    (screen::KEY_UP, 0x2),
+    (screen::KEY_LEFT, 0x4),
+    (screen::KEY_RIGHT, 0x6),
+    (screen::KEY_DOWN, 0x8),
+];
+
+pub const PRESETS:[&str;0x4] = ["qwerty", "azerty", "dvorak", "keypad"];
+
+// CHIP-8 keys in the order they sit on the COSMAC VIP hex keypad
+const HEX_PAD:[[u8;0x4];0x4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+const NAMED_KEYS:[(&str, i32);0xB] = [
+    ("up", screen::KEY_UP),
+    ("down", screen::KEY_DOWN),
+    ("left", screen::KEY_LEFT),
+    ("right", screen::KEY_RIGHT),
+    ("space", 0x20),
+    ("enter", 0x0A),
+    ("a1", screen::KEY_A1),
+    ("a3", screen::KEY_A3),
+    ("b2", screen::KEY_B2),
+    ("c1", screen::KEY_C1),
+    ("c3", screen::KEY_C3),
+];
+
+// Errors
+////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub enum KeymapError {
+    Io(io::Error),
+    UnknownPreset(String),
+    Parse{line:usize, message:String},
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeymapError::Io(ref err) => write!(f, "{}", err),
+            KeymapError::UnknownPreset(ref name) => write!(f,
+                "unknown keymap preset '{}' (expected one of: {})",
+                name, PRESETS.join(", ")),
+            KeymapError::Parse{line, ref message} =>
+                write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl From<io::Error> for KeymapError {
+    fn from(err:io::Error) -> KeymapError {
+        KeymapError::Io(err)
+    }
+}
+
+// Keymap
+////////////////////////////////////////////////////////////////////////
+
+/// Maps terminal key codes, as the screen reads them, to CHIP-8 keys.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    keys:BTreeMap<i32, u8>,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::preset("qwerty").unwrap()
+    }
+}
+
+impl Keymap {
+    pub fn preset(name:&str) -> Result<Keymap, KeymapError> {
+        let mut keys = BTreeMap::new();
+        let layout = match name {
+            "qwerty" => QWERTY,
+            "azerty" => AZERTY,
+            "dvorak" => DVORAK,
+            "keypad" => "",
+            _ => return Err(KeymapError::UnknownPreset(name.to_string())),
+        };
+
+        for (value, key) in layout.chars().enumerate() {
+            keys.insert(key as i32, value as u8);
+        }
+        if name == "azerty" {
+            for (value, key) in AZERTY_DIGITS.chars().enumerate() {
+                keys.insert(key as i32, value as u8);
+            }
+        }
+        if name == "keypad" {
+            keys.extend(KEYPAD.iter().cloned());
+        }
+        keys.extend(ARROWS.iter().cloned());
+
+        Ok(Keymap{keys})
+    }
+
+    /// Parses a keymap file. Each non-blank line is either
+    /// `preset = <name>`, which replaces every mapping so far, or
+    /// `<key> = <hex digit>`, where `<key>` is a single character or one
+    /// of `up`, `down`, `left`, `right`, `space`, `enter`, or the keypad
+    /// keys `a1`, `a3`, `b2`, `c1` and `c3`. Lines starting with `#` are
+    /// comments.
+    pub fn parse(text:&str) -> Result<Keymap, KeymapError> {
+        let mut keymap = Keymap::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message:String| KeymapError::Parse{
+                line:number + 1,
+                message,
+            };
+
+            let (name, value) = match line.rfind('=') {
+                Some(split) if split > 0 =>
+                    (line[..split].trim(), line[split + 1..].trim()),
+                _ => return Err(error(format!("expected '<key> = <value>', \
+                                               found '{}'", line))),
+            };
+
+            if name == "preset" {
+                keymap = Keymap::preset(value).map_err(|err| {
+                    error(err.to_string())
+                })?;
+                continue;
+            }
+
+            let code = Keymap::key_code(name).ok_or_else(|| {
+                error(format!("unknown key '{}'", name))
+            })?;
+            let target = match u8::from_str_radix(value, 16) {
+                Ok(target) if target < 0x10 => target,
+                _ => return Err(error(format!(
+                        "'{}' is not a CHIP-8 key (0-F)", value))),
+            };
+            keymap.keys.insert(code, target);
+        }
+
+        Ok(keymap)
+    }
+
+    pub fn load<P:AsRef<Path>>(path:P) -> Result<Keymap, KeymapError> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Keymap::parse(&text)
+    }
+
+    /// Resolves a `--keymap` argument: a preset name if it is one,
+    /// otherwise a path to a keymap file.
+    pub fn from_arg(arg:&str) -> Result<Keymap, KeymapError> {
+        if PRESETS.contains(&arg) {
+            Keymap::preset(arg)
+        } else {
+            Keymap::load(arg)
+        }
+    }
+
+    pub fn get(&self, code:i32) -> Option<u8> {
+        self.keys.get(&code).cloned()
+    }
+
+    /// Renders the mapping laid out like the COSMAC VIP keypad, one
+    /// string per row, for the `--show-keymap` overlay.
+    pub fn describe(&self) -> Vec<String> {
+        HEX_PAD.iter().map(|row| {
+            let cells:Vec<String> = row.iter().map(|&key| {
+                let names:Vec<String> = self.keys.iter()
+                    .filter(|&(_, &value)| value == key)
+                    .map(|(&code, _)| Keymap::key_name(code))
+                    .collect();
+                format!("{:X}:{:<6}", key, names.join("/"))
+            }).collect();
+            cells.join(" ")
+        }).collect()
+    }
+
+    fn key_code(name:&str) -> Option<i32> {
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if !c.is_whitespace() && !c.is_control() =>
+                Some(c as i32),
+            _ => NAMED_KEYS.iter()
+                    .find(|&&(key, _)| key == name)
+                    .map(|&(_, code)| code),
+        }
+    }
+
+    fn key_name(code:i32) -> String {
+        match NAMED_KEYS.iter().find(|&&(_, key)| key == code) {
+            Some(&(name, _)) => name.to_string(),
+            None => ::std::char::from_u32(code as u32)
+                        .map_or_else(|| format!("{:#x}", code),
+                                     |c| c.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qwerty_matches_original_layout(){
+        let keymap = Keymap::default();
+        for (value, key) in "1234qwerasdfzxcv".chars().enumerate() {
+            assert_eq!(keymap.get(key as i32), Some(value as u8));
+        }
+        assert_eq!(keymap.get(screen::KEY_UP), Some(0x2));
+        assert_eq!(keymap.get('p' as i32), None);
+    }
+
+    #[test]
+    fn test_presets(){
+        for name in &PRESETS {
+            assert!(Keymap::preset(name).is_ok());
+        }
+        let azerty = Keymap::preset("azerty").unwrap();
+        assert_eq!(azerty.get('a' as i32), Some(0x4));
+        assert_eq!(azerty.get('q' as i32), Some(0x8));
+        // the unshifted top row, and its digits with Shift
+        for (value, key) in "&\u{E9}\"'".chars().enumerate() {
+            assert_eq!(azerty.get(key as i32), Some(value as u8));
+        }
+        assert_eq!(azerty.get('2' as i32), Some(0x1));
+        let keypad = Keymap::preset("keypad").unwrap();
+        assert_eq!(keypad.get('7' as i32), Some(0x7));
+        assert_eq!(keypad.get('\n' as i32), Some(0xE));
+        assert!(Keymap::preset("colemak").is_err());
+    }
+
+    #[test]
+    fn test_parse(){
+        let keymap = Keymap::parse("\
+            # swap to dvorak and add a fire key\n\
+            preset = dvorak\n\
+            space = 5\n\
+            b2 = 5\n").unwrap();
+        assert_eq!(keymap.get('o' as i32), Some(0x9));
+        assert_eq!(keymap.get(' ' as i32), Some(0x5));
+        assert_eq!(keymap.get(screen::KEY_B2), Some(0x5));
+
+        let keymap = Keymap::parse("\u{E9} = 9\n").unwrap();
+        assert_eq!(keymap.get(0xE9), Some(0x9));
+        assert!(keymap.describe()[2].contains("9:s/\u{E9}"));
+    }
+
+    #[test]
+    fn test_parse_errors(){
+        match Keymap::parse("w = 4\nw 4\n") {
+            Err(KeymapError::Parse{line:2, ..}) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(Keymap::parse("w = 10").is_err());
+        assert!(Keymap::parse("home = 1").is_err());
+        assert!(Keymap::parse("preset = colemak").is_err());
+    }
+}
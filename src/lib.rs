@@ -1,8 +1,44 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+
+// Without std, the std paths the modules use come from core and alloc,
+// which is all of std that the core of the emulator needs.
+#[cfg(not(feature = "std"))]
+mod std {
+    pub use core::*;
+    pub use alloc::{boxed, collections, fmt, string, vec};
+}
+
 mod bus;
 mod chip8;
 mod memory;
 mod processor;
+mod reference;
+mod state;
 mod tests;
 
+pub mod blend;
+pub mod disasm;
+pub mod headless;
+pub mod ini;
 pub mod io;
-pub use chip8::Chip8;
+pub mod movie;
+pub mod quirks;
+pub mod random;
+#[cfg(feature = "std")]
+pub mod romdb;
+pub mod sha1;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub use chip8::{Chip8, LoadError, LoadWarning, Reset};
+pub use chip8::{DEFAULT_SPEED, PROGRAM_START};
+pub use processor::{ExecError, Registers};
+pub use quirks::Quirks;
+pub use state::StateError;
@@ -1,46 +1,446 @@
-extern crate ncurses;
 extern crate chip_8;
+extern crate libc;
 
+#[cfg(feature = "ansi")]
+mod ansi;
+mod cli;
+mod config;
+mod debugger;
+mod keymap;
+#[cfg(all(feature = "ncurses", not(feature = "ansi")))]
 mod ncursesio;
+mod render;
+mod screen;
+mod status;
+mod terminal;
+mod theme;
+mod watch;
 
 use std::fs::File;
-use std::io::prelude::Read;
+use std::io::prelude::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 
-fn main() {
+use chip_8::{sha1, Chip8, PROGRAM_START};
+use chip_8::blend::Blender;
+use chip_8::headless::{FrameBuffer, NoInput, NullAudio};
+use chip_8::io::{Audio, Display, Input, SCREEN_WIDTH, SCREEN_HEIGHT};
+use chip_8::movie::{Movie, Player};
+use chip_8::random::XorShift;
+use chip_8::romdb::{Platform, RomDatabase, RomInfo};
 
-    // Open file
-    let filename = std::env::args().nth(1).expect("missing file name");
-    let mut file = match File::open(&filename){
-        Ok(file) => file,
-        Err(_) => panic!("failed to open file {}", filename),
-    };
+use cli::{Command, Options};
+use config::{Config, Settings};
+use debugger::Debugger;
+use keymap::Keymap;
+use render::Renderer;
+use screen::Screen;
+use status::{overlay, status_line, Meter};
+use watch::FileWatcher;
+
+/// The terminal the frontend draws on: ANSI escape sequences with the
+/// `ansi` feature, or else ncurses.
+#[cfg(feature = "ansi")]
+type TerminalScreen = ansi::Screen;
+#[cfg(all(feature = "ncurses", not(feature = "ansi")))]
+type TerminalScreen = ncursesio::Screen;
+#[cfg(not(any(feature = "ncurses", feature = "ansi")))]
+compile_error!("the chip-8 binary needs the ncurses or ansi feature");
+
+/// Frames between checks for changes to the ROM file with --watch.
+const WATCH_INTERVAL:u64 = 30;
 
-    // Extract bytes from file;
+/// How often to check for the key that unpauses.
+const PAUSE_POLL:Duration = Duration::from_millis(1000/60);
+
+fn read_file(filename:&str) -> Result<Vec<u8>, String> {
     let mut data = Vec::<u8>::new();
-    match file.read_to_end(&mut data){
-        Ok(_) => {},
-        Err(_) => panic!("failed to read file"),
+    File::open(filename)
+        .and_then(|mut file| file.read_to_end(&mut data))
+        .map_err(|err| format!("failed to read {}: {}", filename, err))?;
+    Ok(data)
+}
+
+fn write_file(filename:&str, data:&[u8]) -> Result<(), String> {
+    File::create(filename)
+        .and_then(|mut file| file.write_all(data))
+        .map_err(|err| format!("failed to write {}: {}", filename, err))
+}
+
+/// Looks the ROM up in the built-in database, overlaid with the user's
+/// overrides.
+fn load_rom_info(options:&Options, rom:&[u8]) -> Result<RomInfo, String> {
+    let (path, required) = match options.romdb {
+        Some(ref path) => (PathBuf::from(path), true),
+        None => match config::config_dir() {
+            Some(dir) => (dir.join("romdb.ini"), false),
+            None => return Ok(RomDatabase::builtin().lookup(rom)
+                                  .cloned().unwrap_or_default()),
+        },
+    };
+
+    let mut database = RomDatabase::builtin();
+    if required || path.exists() {
+        let text = read_file(&path.to_string_lossy())?;
+        let overrides = RomDatabase::parse(&String::from_utf8_lossy(&text))
+            .map_err(|err| format!("bad ROM database {}: {}",
+                                   path.display(), err))?;
+        database.merge(&overrides);
+    }
+    Ok(database.lookup(rom).cloned().unwrap_or_default())
+}
+
+/// The settings a ROM database entry recommends.
+fn recommended_settings(info:&RomInfo) -> Settings {
+    Settings{
+        speed:info.speed,
+        quirks:if info.quirks.is_some() || info.platform.is_some() {
+            Some(info.profile().to_string())
+        } else {
+            None
+        },
+        keymap:info.keymap.clone(),
+        ..Settings::default()
+    }
+}
+
+/// Layers the command line settings over the config file's and ROM
+/// database's settings for this ROM.
+fn load_settings(options:&Options, info:&RomInfo, rom:&[u8])
+        -> Result<Settings, String> {
+    let config = match options.config {
+        Some(ref path) => Config::load(PathBuf::from(path), true),
+        None => match Config::default_path() {
+            Some(path) => Config::load(path, false),
+            None => Ok(Config::default()),
+        },
     };
+    let config = config.map_err(|err| format!("bad config file: {}", err))?;
+
+    let mut settings = config.settings(&sha1::hex_digest(rom),
+                                       &recommended_settings(info));
+    settings.merge(&options.settings);
+    Ok(settings)
+}
+
+/// Picks the keymap: the keymap setting, then a `<rom>.keymap` file, then
+/// qwerty.
+fn load_keymap(options:&Options, settings:&Settings) -> Result<Keymap, String> {
+    let rom_keymap = format!("{}.keymap", options.rom);
+    let keymap = match settings.keymap {
+        Some(ref arg) => Keymap::from_arg(arg),
+        None if Path::new(&rom_keymap).exists() => Keymap::load(&rom_keymap),
+        None => Ok(Keymap::default()),
+    };
+    keymap.map_err(|err| format!("failed to load keymap: {}", err))
+}
+
+fn load_movie(options:&Options) -> Result<Option<Movie>, String> {
+    match options.replay {
+        Some(ref filename) => {
+            let text = read_file(filename)?;
+            Movie::parse(&String::from_utf8_lossy(&text)).map(Some)
+                .map_err(|err| format!("bad movie {}: {}", filename, err))
+        },
+        None => Ok(None),
+    }
+}
+
+/// Applies the machine settings from the command line and loads the ROM
+/// and any save state or movie.
+fn setup<A, D, I>(machine:&mut Chip8<A, D, I>, options:&Options,
+                  settings:&Settings, rom:&[u8], movie:Option<&Movie>)
+        -> Result<(), String>
+        where
+            A: Audio,
+            D: Display,
+            I: Input {
+    machine.set_speed(settings.speed());
+    machine.set_quirks(settings.quirks());
+    if let Some(seed) = options.seed {
+        machine.seed(seed);
+    }
+    if options.trace {
+        machine.set_trace(Some(Box::new(std::io::stderr())));
+    }
+
+    let address = options.load_address.unwrap_or(PROGRAM_START);
+    let warnings = machine.load_rom_at(address, rom)
+        .map_err(|err| format!("failed to load {}: {}", options.rom, err))?;
+    for warning in warnings {
+        eprintln!("chip-8: warning: {}", warning);
+    }
+    if let Some(ref filename) = options.load_state {
+        machine.load_state(&read_file(filename)?)
+            .map_err(|err| format!("failed to load {}: {}", filename, err))?;
+    }
+    if let Some(movie) = movie {
+        machine.play(movie)
+            .map_err(|err| format!("failed to replay movie: {}", err))?;
+    }
+    if options.record.is_some() {
+        let seed = options.seed
+            .unwrap_or_else(|| XorShift::from_entropy().next_u64());
+        machine.start_recording(seed);
+    }
+    Ok(())
+}
 
-    let data = data;
-    /*
-    for (i,byte) in data.iter().enumerate(){
-        print!("{:02X}", byte);
-        if i & 1 == 1 {print!("\n")}
-    }*/
+/// Whether to run another frame after `frames`: until --frames runs out,
+/// or else the movie being replayed ends.
+fn more_frames<A, D, I>(machine:&Chip8<A, D, I>, options:&Options,
+                        frames:u64) -> bool
+        where
+            A: Audio,
+            D: Display,
+            I: Input {
+    match options.frames {
+        Some(limit) => frames < limit,
+        None => options.replay.is_none() || machine.playing(),
+    }
+}
+
+fn finish<A, D, I>(machine:&mut Chip8<A, D, I>, options:&Options)
+        -> Result<(), String>
+        where
+            A: Audio,
+            D: Display,
+            I: Input {
+    if let Some(ref filename) = options.save_state {
+        write_file(filename, &machine.save_state())?;
+    }
+    if let Some(ref filename) = options.record {
+        if let Some(movie) = machine.stop_recording() {
+            write_file(filename, movie.to_string().as_bytes())?;
+        }
+    }
+    match machine.desync() {
+        Some(frame) => Err(format!("replay desynced at frame {}", frame)),
+        None => Ok(()),
+    }
+}
 
+fn run_headless(options:&Options, settings:&Settings, rom:&[u8])
+        -> Result<(), String> {
+    fn run<I:Input>(mut machine:Chip8<NullAudio, FrameBuffer, I>,
+                    options:&Options, settings:&Settings, rom:&[u8],
+                    movie:Option<&Movie>) -> Result<(), String> {
+        setup(&mut machine, options, settings, rom, movie)?;
+
+        let mut frames = 0;
+        let mut result = Ok(());
+        while more_frames(&machine, options, frames) {
+            if let Err(err) = machine.run_frame() {
+                result = Err(err.to_string());
+                break;
+            }
+            frames += 1;
+        }
+
+        print!("{}", machine.display().to_ascii());
+        finish(&mut machine, options)?;
+        result
+    }
+
+    let (audio, display) = (NullAudio::default(), FrameBuffer::default());
+    match load_movie(options)? {
+        Some(ref movie) => run(
+            Chip8::new(audio, display, Player::new(movie, NoInput::default())),
+            options, settings, rom, Some(movie)),
+        None => run(Chip8::new(audio, display, NoInput::default()),
+                    options, settings, rom, None),
+    }
+}
+
+/// What the terminal shows beside the game screen.
+enum Panes<S:Screen> {
+    /// The status line and overlay, when the user asks for them.
+    Hud(terminal::Hud<S>),
+    Debugger(Debugger<S>),
+}
+
+/// Runs the machine until --frames runs out, the movie being replayed
+/// ends or the user quits. `terminal` finds the terminal's input in `I`.
+fn run_terminal_machine<S, I, T>(
+        mut machine:Chip8<terminal::Audio<S>, Blender<terminal::Display<S>>,
+                          I>,
+        options:&Options, settings:&Settings, rom:&[u8],
+        movie:Option<&Movie>, panes:Panes<S>, terminal:T)
+        -> Result<(), String>
+        where
+            S: Screen,
+            I: Input,
+            T: Fn(&I) -> &terminal::Input<S> {
+    setup(&mut machine, options, settings, rom, movie)?;
+
+    let mut hud = match panes {
+        Panes::Hud(hud) => hud,
+        Panes::Debugger(mut debugger) => {
+            let start = machine.frame();
+            debugger.run(&mut machine,
+                         |machine| more_frames(machine, options,
+                                               machine.frame() - start),
+                         terminal);
+            return finish(&mut machine, options);
+        },
+    };
+
+    let mut watcher = match options.watch {
+        true => Some(FileWatcher::new(&options.rom)),
+        false => None,
+    };
+    let name = Path::new(&options.rom).file_name()
+        .map_or(options.rom.clone(), |name| name.to_string_lossy()
+                                                .into_owned());
+    let mut meter = Meter::new(Instant::now(), machine.instructions());
+    terminal(machine.input()).set_status(settings.status());
+
+    let mut frames = 0;
+    let mut result = Ok(());
+    while more_frames(&machine, options, frames)
+            && !terminal(machine.input()).quit() {
+        let paused = terminal(machine.input()).paused();
+        if paused {
+            // keep reading keys, for the ones that unpause or quit
+            terminal(machine.input()).get_keys();
+            thread::sleep(PAUSE_POLL);
+            meter = Meter::new(Instant::now(), machine.instructions());
+        } else {
+            if let Err(err) = machine.run_frames(1) {
+                result = Err(err.to_string());
+                break;
+            }
+            frames += 1;
+            meter.frame(Instant::now(), machine.instructions());
+        }
+
+        let input = terminal(machine.input());
+        let registers = machine.registers();
+        hud.status(match input.status() {
+            true => Some(status_line(&name, settings.speed(), &meter, paused,
+                                     registers.sound_timer > 0)),
+            false => None,
+        }.as_deref());
+        hud.overlay(&match input.overlay() {
+            true => overlay(&registers, |address| machine.read_memory(address)),
+            false => Vec::new(),
+        });
+
+        // a half written or broken file keeps the old program running
+        if let Some(ref mut watcher) = watcher {
+            if frames % WATCH_INTERVAL == 0 && watcher.changed() {
+                if let Ok(rom) = read_file(&options.rom) {
+                    let address = options.load_address.unwrap_or(PROGRAM_START);
+                    let _ = machine.load_rom_at(address, &rom);
+                }
+            }
+        }
+    }
+
+    finish(&mut machine, options)?;
+    result
+}
+
+fn run_terminal(options:&Options, settings:&Settings, rom:&[u8])
+        -> Result<(), String> {
+    let keymap = load_keymap(options, settings)?;
+    let movie = load_movie(options)?;
+    let screen = TerminalScreen::start()?;
+
+    // the debugger's panes take part of the terminal
+    let (mut rows, mut columns) = screen.size();
+    if options.debug {
+        rows = rows.saturating_sub(debugger::PANES_HEIGHT);
+        columns = columns.saturating_sub(debugger::PANES_WIDTH);
+    }
+    let renderer = Renderer::choose(settings.display(), settings.scale(),
+                                    SCREEN_WIDTH, SCREEN_HEIGHT, columns,
+                                    rows);
+
+    let audio = terminal::Audio::new(screen.clone(), settings.mute());
+    let mut display = terminal::Display::new(screen.clone(), renderer,
+                                             settings.inverse(),
+                                             SCREEN_WIDTH, SCREEN_HEIGHT);
+    if let Some(theme) = settings.theme() {
+        display.set_theme(&theme);
+    }
+    let mut overlay_row = 0;
+    if options.show_keymap && !options.debug {
+        terminal::show_keymap(&screen, &keymap, display.columns());
+        overlay_row = keymap.describe().len() + 2;
+    }
+    let panes = match options.debug {
+        true => Panes::Debugger(Debugger::new(screen.clone(), display.rows(),
+                                              display.columns())),
+        false => Panes::Hud(terminal::Hud::new(screen.clone(), display.rows(),
+                                               display.columns(),
+                                               overlay_row)),
+    };
+    let display = Blender::new(display, settings.blend());
+    let input = terminal::Input::new(screen.clone(), keymap);
+
+    let result = match movie {
+        Some(ref movie) => run_terminal_machine(
+            Chip8::new(audio, display, Player::new(movie, input)),
+            options, settings, rom, Some(movie), panes,
+            |input| input.inner()),
+        None => run_terminal_machine(
+            Chip8::new(audio, display, input),
+            options, settings, rom, None, panes,
+            |input| input),
+    };
+
+    screen.stop();
+    result
+}
+
+fn main() {
+    let options = match cli::parse(std::env::args().skip(1)) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        },
+        Err(err) => {
+            eprintln!("chip-8: {}", err);
+            eprintln!("try 'chip-8 --help' for more information");
+            process::exit(2);
+        },
+    };
 
-    ncurses::initscr();
-    ncurses::noecho();
-    ncurses::cbreak();
+    let result = read_file(&options.rom).and_then(|rom| {
+        let info = load_rom_info(&options, &rom)?;
+        let settings = load_settings(&options, &info, &rom)?;
 
+        if info.platform() != Platform::Chip8 {
+            eprintln!("chip-8: warning: {} is a {} ROM; only CHIP-8 \
+                       instructions are supported",
+                      info.title.as_ref().unwrap_or(&options.rom),
+                      info.platform().name());
+        }
 
-    let mut machine = chip_8::Chip8::new(
-        ncursesio::Audio::default(),
-        ncursesio::Display::new(ncurses::stdscr()),
-        ncursesio::Input::new(ncurses::stdscr()),
-    );
+        if options.print_config {
+            println!("# {}", options.rom);
+            if let Some(ref title) = info.title {
+                match info.author {
+                    Some(ref author) => println!("# {} by {}", title, author),
+                    None => println!("# {}", title),
+                }
+            }
+            println!("[{}]", sha1::hex_digest(&rom));
+            print!("{}", settings);
+            Ok(())
+        } else if options.headless {
+            run_headless(&options, &settings, &rom)
+        } else {
+            run_terminal(&options, &settings, &rom)
+        }
+    });
 
-    machine.load_rom(&data);
-    machine.run();
+    if let Err(err) = result {
+        eprintln!("chip-8: {}", err);
+        process::exit(1);
+    }
 }
@@ -1,6 +1,9 @@
+use super::state::{Reader, StateError, Writer};
+
 pub const RAM_SIZE:usize = 0x1000;
+pub const FONT_SIZE:usize = 0x50;
 
-const C8_FONT:[u8;0x50] = [
+const C8_FONT:[u8;FONT_SIZE] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -19,6 +22,7 @@ const C8_FONT:[u8;0x50] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
 
+/// The 4 KiB of RAM. Addresses wrap at the end, as on a 12-bit bus.
 pub struct Memory {
     memory:[u8;RAM_SIZE],
 }
@@ -26,20 +30,34 @@ pub struct Memory {
 impl Default for Memory {
     fn default() -> Memory {
         let mut memory = [0x0;RAM_SIZE];
-        memory[..0x50].copy_from_slice(&C8_FONT);
-        Memory{memory:memory}
+        memory[..FONT_SIZE].copy_from_slice(&C8_FONT);
+        Memory{memory}
     }
 }
 
 impl Memory {
     pub fn read_memory(&self, pointer:u16) -> u8{
-        self.memory[pointer as usize]
+        self.memory[pointer as usize % RAM_SIZE]
     }
     pub fn write_memory(&mut self, pointer:u16, value:u8){
-        self.memory[pointer as usize] = value
+        self.memory[pointer as usize % RAM_SIZE] = value
     }
     pub fn set_range(&mut self, pointer:u16, values:&[u8]){
-        let pointer = pointer as usize;
-        self.memory[pointer..(pointer + values.len())].copy_from_slice(values)
+        for (offset, &value) in values.iter().enumerate() {
+            self.write_memory(pointer.wrapping_add(offset as u16), value);
+        }
+    }
+    /// Zeroes everything but the font.
+    pub fn clear_program(&mut self){
+        for byte in &mut self.memory[FONT_SIZE..] {
+            *byte = 0x0;
+        }
+    }
+    pub fn save_state(&self, state:&mut Writer){
+        state.bytes(&self.memory);
+    }
+    pub fn load_state(&mut self, state:&mut Reader) -> Result<(), StateError>{
+        self.memory.copy_from_slice(state.bytes(RAM_SIZE)?);
+        Ok(())
     }
 }
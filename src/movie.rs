@@ -0,0 +1,348 @@
+use std::cell::Cell;
+use std::error;
+use std::fmt;
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+use super::io::{key_bit, Input};
+use super::quirks::Quirks;
+use super::sha1;
+
+// Constants
+////////////////////////////////////////////////////////////////////////
+
+const HEADER:&str = "chip-8 movie 1";
+
+/// Frames between the state hashes recorded to detect desyncs.
+pub const CHECK_INTERVAL:u64 = 60;
+
+// Movies
+////////////////////////////////////////////////////////////////////////
+
+/// A key the input device reported pressed or released, and the frame
+/// it reported it on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEvent {
+    pub frame:u64,
+    pub key:u8,
+    /// Whether a pressed key stays down until it's released, rather than
+    /// for a while.
+    pub held:bool,
+    pub released:bool,
+}
+
+/// The SHA-1 hash of the machine's save state at the end of a frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub frame:u64,
+    pub hash:String,
+}
+
+/// A recording of a run: the settings it started with and every key
+/// pressed or released. Playing it back on the same ROM reproduces the
+/// run exactly.
+///
+/// Movies are saved as text, one item per line:
+///
+/// ```text
+/// chip-8 movie 1
+/// rom 0123456789abcdef0123456789abcdef01234567
+/// seed 42
+/// quirks shift_vy vf_reset
+/// speed 10
+/// start 0x200
+/// frames 600
+/// key 12 5
+/// hold 14 A
+/// release 15 5
+/// check 60 0123456789abcdef0123456789abcdef01234567
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    /// SHA-1 hash of the ROM, as from `Chip8::rom_hash`.
+    pub rom_hash:String,
+    pub seed:u64,
+    pub quirks:Quirks,
+    pub speed:usize,
+    /// Address the ROM was loaded at.
+    pub start:u16,
+    /// Length of the run in frames.
+    pub frames:u64,
+    pub events:Vec<KeyEvent>,
+    pub checks:Vec<Check>,
+}
+
+impl Movie {
+    pub fn parse(text:&str) -> Result<Movie, MovieError> {
+        let mut lines = text.lines().enumerate()
+            .map(|(number, line)| (number + 1, line.trim()))
+            .filter(|&(_, line)| !line.is_empty());
+
+        match lines.next() {
+            Some((_, HEADER)) => (),
+            _ => return Err(MovieError::Parse{line:1,
+                    message:"not a chip-8 movie".to_string()}),
+        }
+
+        let mut rom_hash = None;
+        let mut seed = None;
+        let mut quirks = None;
+        let mut speed = None;
+        let mut start = None;
+        let mut frames = None;
+        let mut events = Vec::new();
+        let mut checks = Vec::new();
+
+        for (number, line) in lines {
+            let error = |message:String| MovieError::Parse{line:number, message};
+            let mut words = line.split_whitespace();
+            let item = words.next().unwrap();
+            let args:Vec<&str> = words.collect();
+            let arg = |index:usize| args.get(index).cloned().ok_or_else(|| {
+                error(format!("'{}' is missing a value", item))
+            });
+            let number = |index:usize| arg(index).and_then(|text| {
+                text.parse::<u64>().map_err(|_| {
+                    error(format!("expected a number, found '{}'", text))
+                })
+            });
+
+            match item {
+                "rom" => rom_hash = Some(arg(0)?.to_lowercase()),
+                "seed" => seed = Some(number(0)?),
+                "quirks" => {
+                    let mut value = Quirks::profile("modern").unwrap();
+                    for name in &args {
+                        if !value.set(name, true) {
+                            return Err(error(format!("unknown quirk '{}'",
+                                                     name)));
+                        }
+                    }
+                    quirks = Some(value);
+                },
+                "speed" => speed = Some(number(0)? as usize),
+                "start" => {
+                    let text = arg(0)?;
+                    start = Some(u16::from_str_radix(
+                            text.trim_start_matches("0x"), 16).map_err(|_| {
+                        error(format!("expected an address, found '{}'", text))
+                    })?);
+                },
+                "frames" => frames = Some(number(0)?),
+                "key" | "hold" | "release" => {
+                    let frame = number(0)?;
+                    let key = u8::from_str_radix(arg(1)?, 16).ok()
+                        .filter(|&key| key < 0x10)
+                        .ok_or_else(|| error(format!("bad key '{}'", args[1])))?;
+                    let held = item == "hold";
+                    let released = item == "release";
+                    events.push(KeyEvent{frame, key, held, released});
+                },
+                "check" => checks.push(Check{
+                    frame:number(0)?,
+                    hash:arg(1)?.to_lowercase(),
+                }),
+                _ => return Err(error(format!("unknown item '{}'", item))),
+            }
+        }
+
+        let missing = |item:&str| MovieError::Parse{line:0,
+            message:format!("movie has no '{}' line", item)};
+        Ok(Movie{
+            rom_hash:rom_hash.ok_or_else(|| missing("rom"))?,
+            seed:seed.ok_or_else(|| missing("seed"))?,
+            quirks:quirks.ok_or_else(|| missing("quirks"))?,
+            speed:speed.ok_or_else(|| missing("speed"))?,
+            start:start.ok_or_else(|| missing("start"))?,
+            frames:frames.ok_or_else(|| missing("frames"))?,
+            events,
+            checks,
+        })
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "rom {}", self.rom_hash)?;
+        writeln!(f, "seed {}", self.seed)?;
+        let quirks = self.quirks.enabled();
+        if quirks.is_empty() {
+            writeln!(f, "quirks")?;
+        } else {
+            writeln!(f, "quirks {}", quirks.join(" "))?;
+        }
+        writeln!(f, "speed {}", self.speed)?;
+        writeln!(f, "start {:#05X}", self.start)?;
+        writeln!(f, "frames {}", self.frames)?;
+        for event in &self.events {
+            let item = match (event.released, event.held) {
+                (true, _) => "release",
+                (false, true) => "hold",
+                (false, false) => "key",
+            };
+            writeln!(f, "{} {} {:X}", item, event.frame, event.key)?;
+        }
+        for check in &self.checks {
+            writeln!(f, "check {} {}", check.frame, check.hash)?;
+        }
+        Ok(())
+    }
+}
+
+// Playback
+////////////////////////////////////////////////////////////////////////
+
+/// Input device that presses and releases the keys recorded in a movie,
+/// each on the frame it was recorded on. Every poll is a frame.
+///
+/// The wrapped device is still polled, so it can see keys such as a quit
+/// key, but the keys it reports are ignored.
+pub struct Player<I:Input> {
+    inner:I,
+    events:Vec<KeyEvent>,
+    next:Cell<usize>,
+    frame:Cell<u64>,
+    /// Keys released on the frame last polled.
+    released:Cell<u16>,
+    /// Keys held on the frame last polled.
+    held:Cell<u16>,
+}
+
+impl<I:Input> Player<I> {
+    pub fn new(movie:&Movie, inner:I) -> Player<I> {
+        Player{
+            inner,
+            events:movie.events.clone(),
+            next:Cell::new(0),
+            frame:Cell::new(0),
+            released:Cell::new(0x0),
+            held:Cell::new(0x0),
+        }
+    }
+
+    /// Starts the movie over from the first frame.
+    pub fn rewind(&self){
+        self.next.set(0);
+        self.frame.set(0);
+        self.released.set(0x0);
+        self.held.set(0x0);
+    }
+
+    pub fn inner(&self) -> &I {
+        &self.inner
+    }
+}
+
+impl<I:Input> Input for Player<I> {
+    fn get_keys(&self) -> u16 {
+        self.inner.get_keys();
+
+        let frame = self.frame.get();
+        let (mut keys, mut released, mut held) = (0x0, 0x0, 0x0);
+        let mut next = self.next.get();
+        while self.events.get(next).is_some_and(|event| event.frame <= frame) {
+            let event = self.events[next];
+            match (event.released, event.held) {
+                (true, _) => released |= key_bit(event.key),
+                (false, true) => {
+                    keys |= key_bit(event.key);
+                    held |= key_bit(event.key);
+                },
+                (false, false) => keys |= key_bit(event.key),
+            }
+            next += 1;
+        }
+        self.next.set(next);
+        self.frame.set(frame + 1);
+        self.released.set(released);
+        self.held.set(held);
+        keys
+    }
+
+    fn get_released(&self) -> u16 {
+        self.inner.get_released();
+        self.released.replace(0x0)
+    }
+
+    fn get_held(&self) -> u16 {
+        self.inner.get_held();
+        self.held.get()
+    }
+}
+
+/// Compares a replay's state hashes against a movie's.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Playback {
+    checks:Vec<Check>,
+    next:usize,
+    frames:u64,
+    desync:Option<u64>,
+}
+
+impl Playback {
+    pub fn new(movie:&Movie) -> Playback {
+        Playback{checks:movie.checks.clone(), next:0, frames:movie.frames,
+                 desync:None}
+    }
+
+    /// Checks the save state at the end of `frame` against the movie,
+    /// remembering the first frame they differ on.
+    pub fn check(&mut self, frame:u64, state:&[u8]){
+        while self.checks.get(self.next).is_some_and(|c| c.frame < frame) {
+            self.next += 1;
+        }
+        match self.checks.get(self.next) {
+            Some(check) if check.frame == frame => {
+                if self.desync.is_none()
+                        && check.hash != sha1::hex_digest(state) {
+                    self.desync = Some(frame);
+                }
+                self.next += 1;
+            },
+            _ => (),
+        }
+    }
+
+    /// The first frame checked that differed from the recording.
+    pub fn desync(&self) -> Option<u64> {
+        self.desync
+    }
+
+    /// The length of the recording in frames.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+// Errors
+////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MovieError {
+    /// The movie file is malformed at `line`, or 0 if the problem isn't
+    /// on any one line.
+    Parse{line:usize, message:String},
+    /// The movie was recorded with a different ROM.
+    WrongRom{expected:String, found:String},
+    /// The movie's load address doesn't fit the ROM.
+    BadStart(u16),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::Parse{line:0, ref message} => write!(f, "{}", message),
+            MovieError::Parse{line, ref message} =>
+                write!(f, "line {}: {}", line, message),
+            MovieError::WrongRom{ref expected, ref found} => write!(f,
+                "movie was recorded with ROM {} but ROM {} is loaded",
+                expected, found),
+            MovieError::BadStart(address) => write!(f,
+                "movie loads the ROM at {:#05X}, where it doesn't fit",
+                address),
+        }
+    }
+}
+
+impl error::Error for MovieError {}
@@ -1,106 +1,155 @@
 extern crate ncurses;
 
-use super::chip_8::io;
+use super::libc;
+use super::screen::{self, Key, Style};
+use super::theme::{Theme, SHADES};
 
-#[derive(Default)]
-pub struct Audio{}
+extern "C" {
+    // ncurses 6 sets up pairs of direct, 24-bit colors with this, which
+    // the ncurses crate doesn't wrap
+    fn init_extended_pair(pair: libc::c_int, foreground: libc::c_int,
+                          background: libc::c_int) -> libc::c_int;
+}
 
-impl io::Audio for Audio {
-    fn beep(&self){
-        ncurses::beep();
-    }
+/// The color pair drawing shade `foreground` on shade `background`.
+fn color_pair(foreground: usize, background: usize) -> ncurses::attr_t {
+    ncurses::COLOR_PAIR((1 + foreground*SHADES + background) as i16)
 }
 
-enum Key {
-    Key(u8),
-    Und(i32),
+fn attributes(style: Style) -> ncurses::attr_t {
+    let mut attributes = ncurses::A_NORMAL();
+    for &(flag, attribute) in &[(screen::BOLD, ncurses::A_BOLD()),
+                                (screen::DIM, ncurses::A_DIM()),
+                                (screen::REVERSE, ncurses::A_REVERSE()),
+                                (screen::UNDERLINE, ncurses::A_UNDERLINE())] {
+        if style.attributes & flag != 0 {
+            attributes |= attribute;
+        }
+    }
+    if let Some((foreground, background)) = style.colors {
+        attributes |= color_pair(foreground, background);
+    }
+    attributes
 }
 
-pub struct Input {
-    screen: ncurses::SCREEN,
+/// The terminal, through ncurses' standard screen.
+#[derive(Clone)]
+pub struct Screen {
+    window: ncurses::WINDOW,
 }
 
-impl<'a> Input {
-    fn map_key(key:i32) -> Key {
-        match key {
-            0x31 => Key::Key(0x0), // 1
-            0x32 => Key::Key(0x1), // 2
-            0x33 => Key::Key(0x2), // 3
-            0x34 => Key::Key(0x3), // 4
-            0x71 => Key::Key(0x4), // q
-            0x77 => Key::Key(0x5), // w
-            0x65 => Key::Key(0x6), // e
-            0x72 => Key::Key(0x7), // r
-            0x61 => Key::Key(0x8), // a
-            0x73 => Key::Key(0x9), // s
-            0x64 => Key::Key(0xA), // d
-            0x66 => Key::Key(0xB), // f
-            0x7A => Key::Key(0xC), // z
-            0x78 => Key::Key(0xD), // x
-            0x63 => Key::Key(0xE), // c
-            0x76 => Key::Key(0xF), // v
-            key => Key::Und(key),
+impl screen::Screen for Screen {
+    fn start() -> Result<Screen, String> {
+        // wait only briefly after escape for the rest of an escape sequence
+        if std::env::var_os("ESCDELAY").is_none() {
+            std::env::set_var("ESCDELAY", "25");
+        }
+        // the half-block and braille characters need the user's locale,
+        // for UTF-8; ncurses::setlocale passes a dangling pointer, so call
+        // libc
+        unsafe {
+            libc::setlocale(libc::LC_ALL,
+                            b"\0".as_ptr() as *const libc::c_char);
         }
+        let window = ncurses::initscr();
+        ncurses::noecho();
+        ncurses::cbreak();
+        ncurses::keypad(window, true);
+        ncurses::nodelay(window, true);
+        Ok(Screen{window})
     }
 
-    pub fn new(screen: ncurses::SCREEN) -> Input {
-        Input{screen:screen}
+    fn stop(&self){
+        ncurses::endwin();
     }
-}
 
-impl io::Input for Input {
-    fn get_keys(&self) -> Vec<u8> {
-        let mut keys = Vec::<u8>::new();
-        ncurses::nodelay(self.screen, true);
-        loop {
-            match Input::map_key(ncurses::wgetch(self.screen)) {
-                Key::Key(key) => keys.push(key),
-                Key::Und(ncurses::ERR) => break,
-                _ =>{},
-            }
-        }
-        ncurses::nodelay(self.screen, false);
-        keys
+    fn size(&self) -> (usize, usize) {
+        let (mut rows, mut columns) = (0, 0);
+        ncurses::getmaxyx(self.window, &mut rows, &mut columns);
+        (rows.max(0) as usize, columns.max(0) as usize)
     }
 
-    fn get_key(&self) -> u8 {
-        loop {
-            if let Key::Key(x) = Input::map_key(ncurses::wgetch(self.screen)){
-                return x;
-            }
-        }
+    fn put(&self, row: usize, col: usize, text: &str, style: Style) -> bool {
+        let attributes = attributes(style);
+        ncurses::wattron(self.window, attributes);
+        let result = ncurses::mvwaddstr(self.window, row as i32, col as i32,
+                                        text);
+        ncurses::wattroff(self.window, attributes);
+        result != ncurses::ERR
     }
-}
 
-pub struct Display {
-    screen: ncurses::SCREEN,
-}
+    fn put_stipple(&self, row: usize, col: usize, style: Style) -> bool {
+        let cell = ncurses::ACS_CKBOARD() | attributes(style);
+        ncurses::mvwaddch(self.window, row as i32, col as i32, cell)
+            != ncurses::ERR
+    }
 
-impl<'a> Display {
-    pub fn new(screen: ncurses::SCREEN) -> Display {
-        Display{screen:screen}
+    fn clear_line(&self, row: usize, col: usize) {
+        ncurses::wmove(self.window, row as i32, col as i32);
+        ncurses::wclrtoeol(self.window);
+    }
+
+    fn refresh(&self){
+        ncurses::wrefresh(self.window);
+    }
+
+    fn beep(&self){
+        ncurses::beep();
     }
-}
 
-impl<'a> io::Display for Display {
-    fn set(&mut self, row:usize, col:usize, state:io::Pixel) -> Result<(),()> {
-
-        let attr = match state {
-            io::Pixel::On => ncurses::A_NORMAL(),
-            io::Pixel::Off => ncurses::A_STANDOUT(),
-        };
-
-        match ncurses::mvwchgat(
-                self.screen,
-                row as i32,
-                col as i32,
-                1, attr, 0) {
-            ncurses::ERR => Err(()),
-            _ => Ok(()),
+    /// Sets up a color pair for each pair of shades of `theme`, in 24-bit
+    /// color on terminals with direct color, in redefined colors on those
+    /// that allow it, or else in the nearest of the terminal's colors.
+    fn start_colors(&self, theme: &Theme) -> bool {
+        if !ncurses::has_colors() {
+            return false;
         }
+        ncurses::start_color();
+        let count = ncurses::COLORS();
+        let direct = count >= 0x1000000;
+        let custom = !direct && ncurses::can_change_color()
+            && count >= (0x10 + SHADES) as i32;
+
+        let colors: Vec<i32> = (0..SHADES).map(|shade| {
+            let rgb = theme.shade(shade);
+            let scale = |value: u8| (value as i32*1000/0xFF) as i16;
+            if direct {
+                (rgb.0 as i32) << 16 | (rgb.1 as i32) << 8 | rgb.2 as i32
+            } else if custom {
+                let color = (0x10 + shade) as i16;
+                ncurses::init_color(color, scale(rgb.0), scale(rgb.1),
+                                    scale(rgb.2));
+                color as i32
+            } else if count >= 0x100 {
+                rgb.xterm256() as i32
+            } else {
+                rgb.basic() as i32
+            }
+        }).collect();
+
+        for foreground in 0..SHADES {
+            for background in 0..SHADES {
+                let pair = (1 + foreground*SHADES + background) as i16;
+                let (fg, bg) = (colors[foreground], colors[background]);
+                if direct {
+                    unsafe { init_extended_pair(pair as i32, fg, bg); }
+                } else {
+                    ncurses::init_pair(pair, fg as i16, bg as i16);
+                }
+            }
+        }
+        true
     }
 
-    fn refresh(&mut self){
-        ncurses::wrefresh(self.screen);
+    /// Reads keys as curses codes, which never report releases, and
+    /// characters as code points, so a key like AZERTY's `é` is one key
+    /// rather than its UTF-8 bytes.
+    fn read_key(&self) -> Option<Key> {
+        match ncurses::wget_wch(self.window) {
+            Some(ncurses::WchResult::KeyCode(code)) => Some(Key::Press(code)),
+            Some(ncurses::WchResult::Char(code)) =>
+                Some(Key::Press(code as i32)),
+            None => None,
+        }
     }
 }
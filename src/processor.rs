@@ -1,25 +1,76 @@
-extern crate rand;
-
-use super::std;
-use super::std::time::{Duration, Instant};
+use super::std::boxed::Box;
+use super::std::error;
+use super::std::fmt;
+use super::std::mem;
+use super::std::vec::Vec;
 
 use super::bus::Bus;
-use super::io::{Audio, Display, Input, Pixel};
+use super::io::{key_bit, Audio, Display, Input, Pixel};
 use super::io::{SCREEN_WIDTH, SCREEN_HEIGHT};
 use super::memory::Memory;
+use super::movie::KeyEvent;
+use super::quirks::Quirks;
+use super::random::{Random, XorShift};
+use super::state::{Reader, StateError, Writer};
+
+// Errors
+////////////////////////////////////////////////////////////////////////
+
+/// An instruction that can't be executed, and the address it's at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExecError {
+    /// `00EE` with nothing on the stack.
+    StackUnderflow(u16),
+    /// `2NNN` with all 16 stack levels in use.
+    StackOverflow(u16),
+    /// `0NNN` calls a machine code routine, which can't be emulated.
+    MachineCode{address:u16, opcode:u16},
+    InvalidOpcode{address:u16, opcode:u16},
+}
 
-// Constants
-///////////////////////////////////////////////////////////////////////
+impl fmt::Display for ExecError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExecError::StackUnderflow(address) => write!(f,
+                "return with an empty stack at {:#05X}", address),
+            ExecError::StackOverflow(address) => write!(f,
+                "call with a full stack at {:#05X}", address),
+            ExecError::MachineCode{address, opcode} => write!(f,
+                "machine code routine {:04X} at {:#05X} is not supported",
+                opcode, address),
+            ExecError::InvalidOpcode{address, opcode} => write!(f,
+                "unknown instruction {:04X} at {:#05X}", opcode, address),
+        }
+    }
+}
 
-const FRAME_RATE:u64 = 17;
+impl error::Error for ExecError {}
 
 // Processor
 ////////////////////////////////////////////////////////////////////////
 
+/// Frames a key stays down after the input device last reported it.
+const KEY_TIMEOUT:u8 = 60;
+
 #[derive(Clone, Copy)]
 enum Key {
     Up,
-    Down(Instant),
+    Down(u8), // frames until the key is released
+    Held, // down until the input device releases it
+}
+
+/// A copy of the registers, for debuggers and overlays.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Registers {
+    pub v:[u8;0x10],
+    pub index:u16,
+    pub pc:u16,
+    /// The number of calls on the stack, `stack[..sp]`.
+    pub sp:usize,
+    /// The addresses of the calls to return past.
+    pub stack:[u16;0x10],
+    pub delay_timer:u8,
+    pub sound_timer:u8,
 }
 
 pub struct Processor {
@@ -32,11 +83,33 @@ pub struct Processor {
     sound_timer:u8,
 
     draw_flag:bool,
+    waiting:Option<usize>, // register awaiting a key press (FX0A)
+    vblank:bool, // no instruction has run since the last tick
+    display_wait:bool, // DXYN is waiting for the next vblank
 
     v:[u8;0x10],
     stack:[u16;0x10],
     screen:[bool;SCREEN_WIDTH*SCREEN_HEIGHT],
     keys:[Key;0x10],
+
+    quirks:Quirks,
+    rng:Box<dyn Random>,
+
+    frame:u64, // frames ticked since reset
+    recording:Option<Vec<KeyEvent>>,
+}
+
+/// The generator a machine starts with, until it's seeded.
+#[cfg(feature = "std")]
+fn initial_rng() -> XorShift {
+    XorShift::from_entropy()
+}
+
+/// Without std there's no entropy to seed from, so every machine starts
+/// the same; embedded frontends seed from a timer or hardware source.
+#[cfg(not(feature = "std"))]
+fn initial_rng() -> XorShift {
+    XorShift::new(0)
 }
 
 impl Default for Processor {
@@ -51,11 +124,20 @@ impl Default for Processor {
             sound_timer:0x0,
 
             draw_flag:false,
+            waiting:None,
+            vblank:true,
+            display_wait:false,
 
             v:[0x0;0x10],
             stack:[0x0;0x10],
             screen:[false;SCREEN_WIDTH*SCREEN_HEIGHT],
             keys:[Key::Up;0x10],
+
+            quirks:Quirks::default(),
+            rng:Box::new(initial_rng()),
+
+            frame:0x0,
+            recording:None,
         }
     }
 }
@@ -66,21 +148,170 @@ impl Processor {
 
     fn read_address(pointer:u16, memory:&Memory) -> u16 {
         let top = (memory.read_memory(pointer) as u16) << 0x8;
-        let bot = (memory.read_memory(pointer + 0x1) & 0xFF) as u16;
+        let bot = memory.read_memory(pointer.wrapping_add(0x1)) as u16;
         top | bot
     }
 
     // &self functions
 
+    pub fn waiting_for_key(&self) -> Option<usize> {
+        self.waiting
+    }
 
+    /// Whether a `DXYN` is waiting for the next frame to draw, with the
+    /// `display_wait` quirk.
+    pub fn waiting_for_display(&self) -> bool {
+        self.display_wait
+    }
+
+    // only traces read it
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers{
+            v:self.v,
+            index:self.index,
+            pc:self.pc,
+            sp:self.sp as usize,
+            stack:self.stack,
+            delay_timer:self.delay_timer,
+            sound_timer:self.sound_timer,
+        }
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn save_state(&self, state:&mut Writer){
+        state.u16(self.oc);
+        state.u16(self.pc);
+        state.u16(self.sp);
+        state.u16(self.index);
+        state.u8(self.delay_timer);
+        state.u8(self.sound_timer);
+        state.u8(self.waiting.map_or(0xFF, |x| x as u8));
+        state.bytes(&self.v);
+        for &address in &self.stack {
+            state.u16(address);
+        }
+        state.bits(&self.screen);
+        for key in &self.keys {
+            state.u8(match *key {
+                Key::Up => 0x0,
+                Key::Down(frames) => frames,
+                Key::Held => 0xFF,
+            });
+        }
+        state.u64(self.frame);
+        state.bits(&[self.vblank, self.display_wait]);
+        let rng = self.rng.save();
+        state.u16(rng.len() as u16);
+        state.bytes(&rng);
+    }
 
     // &mut self functions
 
+    /// Returns to the power-on state with execution starting at `start`,
+    /// keeping the quirks and random number source. The screen is
+    /// cleared on the next tick.
+    pub fn reset(&mut self, start:u16){
+        let quirks = self.quirks;
+        let rng = mem::replace(&mut self.rng, Box::new(XorShift::new(0x0)));
+        *self = Processor{
+            pc:start,
+            draw_flag:true,
+            quirks,
+            rng,
+            ..Processor::default()
+        };
+    }
+
+    pub fn set_quirks(&mut self, quirks:Quirks){
+        self.quirks = quirks;
+    }
+
+    pub fn set_rng(&mut self, rng:Box<dyn Random>){
+        self.rng = rng;
+    }
+
+    /// Reads and checks the registers, timers, stack, screen, keys and
+    /// random number state written by `save_state`, returning a processor
+    /// with this one's quirks and the random number state for
+    /// `finish_load`. This one is left as it was.
+    pub fn read_state<'a>(&mut self, state:&mut Reader<'a>)
+            -> Result<(Processor, &'a [u8]), StateError> {
+        let mut loaded = Processor{quirks:self.quirks, ..Processor::default()};
+        loaded.oc = state.u16()?;
+        loaded.pc = state.u16()?;
+        loaded.sp = state.u16()?;
+        loaded.index = state.u16()?;
+        loaded.delay_timer = state.u8()?;
+        loaded.sound_timer = state.u8()?;
+        loaded.waiting = match state.u8()? {
+            0xFF => None,
+            x if x < 0x10 => Some(x as usize),
+            _ => return Err(StateError::Invalid("key wait register")),
+        };
+        loaded.v.copy_from_slice(state.bytes(0x10)?);
+        for address in &mut loaded.stack {
+            *address = state.u16()?;
+        }
+        state.bits(&mut loaded.screen)?;
+        for key in &mut loaded.keys {
+            *key = match state.u8()? {
+                0x0 => Key::Up,
+                0xFF => Key::Held,
+                frames if frames <= KEY_TIMEOUT => Key::Down(frames),
+                _ => return Err(StateError::Invalid("key")),
+            };
+        }
+        // any frame count is fine, as it wraps
+        loaded.frame = state.u64()?;
+        let mut flags = [false;2];
+        state.bits(&mut flags)?;
+        loaded.vblank = flags[0];
+        loaded.display_wait = flags[1];
+        let len = state.u16()? as usize;
+        let rng = state.bytes(len)?;
+
+        if loaded.sp as usize > loaded.stack.len() {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        // only the generator can check its state, so try it and go back
+        let current = self.rng.save();
+        if !self.rng.restore(rng) {
+            return Err(StateError::Invalid("random number state"));
+        }
+        self.rng.restore(&current);
+        loaded.draw_flag = true;
+        Ok((loaded, rng))
+    }
+
+    /// Replaces this processor with one from `read_state`, restoring the
+    /// random number state into this one's generator.
+    pub fn finish_load(&mut self, mut loaded:Processor, rng:&[u8]){
+        self.rng.restore(rng);
+        mem::swap(&mut loaded.rng, &mut self.rng);
+        loaded.recording = self.recording.take();
+        *self = loaded;
+    }
+
     fn load_opcode(&mut self, memory:&Memory){
+        // addresses are 12 bits, so execution wraps at the end of memory
+        self.pc &= 0x0FFF;
         self.oc = Processor::read_address(self.pc, memory);
     }
 
     fn run_opcode<A, D, I>(&mut self, bus:&mut Bus<A, D, I>)
+            -> Result<(), ExecError>
             where
                 A: Audio,
                 D: Display,
@@ -98,18 +329,23 @@ impl Processor {
                 self.pc += 2;
             },
             (0x0,0x0,0xE,0xE) => { // return from subroutine
+                if self.sp == 0x0 {
+                    return Err(ExecError::StackUnderflow(self.pc));
+                }
                 self.sp -= 1;
-                self.pc = self.stack[self.sp as usize];
-                self.pc += 2;
+                self.pc = (self.stack[self.sp as usize] & 0x0FFF) + 2;
             },
             (0x0,_,_,_) => { // call RCA 1802 program at address NNN
-                /* TODO */
-                panic!("TODO -- 0x0NNN");
+                return Err(ExecError::MachineCode{address:self.pc,
+                                                  opcode:self.oc});
             },
             (0x1,_,_,_) => { // jump to address NNN
                 self.pc = self.oc & 0x0FFF;
             },
             (0x2,_,_,_) => { // call subroutine at NNN
+                if self.sp as usize >= self.stack.len() {
+                    return Err(ExecError::StackOverflow(self.pc));
+                }
                 self.stack[self.sp as usize] = self.pc;
                 self.sp += 1;
                 self.pc = self.oc & 0x0FFF;
@@ -149,14 +385,17 @@ impl Processor {
             },
             (0x8,x,y,0x1) => { // or VX with VY
                 self.v[x] |= self.v[y];
+                if self.quirks.vf_reset {self.v[0xF] = 0};
                 self.pc += 2;
             },
             (0x8,x,y,0x2) => { // and VX with VY
                 self.v[x] &= self.v[y];
+                if self.quirks.vf_reset {self.v[0xF] = 0};
                 self.pc += 2;
             },
             (0x8,x,y,0x3) => { // xor VX with VY
                 self.v[x] ^= self.v[y];
+                if self.quirks.vf_reset {self.v[0xF] = 0};
                 self.pc += 2;
             },
             (0x8,x,y,0x4) => { // add VY to VX. VF = if carry {1} else {0}
@@ -171,7 +410,8 @@ impl Processor {
                 self.v[0xF] = if flag {0} else {1};
                 self.pc += 2;
             },
-            (0x8,x,_,0x6) => { // shift VX right. VF set to dropped bit.
+            (0x8,x,y,0x6) => { // shift VX right. VF set to dropped bit.
+                if self.quirks.shift_vy {self.v[x] = self.v[y]};
                 let bit = self.v[x] & 0x1;
                 self.v[x] >>= 1;
                 self.v[0xF] = bit;
@@ -183,7 +423,8 @@ impl Processor {
                 self.v[0xF] = if flag {0} else {1};
                 self.pc += 2;
             },
-            (0x8,x,_,0xE) => { // shift VX left. VF set to dropped bit.
+            (0x8,x,y,0xE) => { // shift VX left. VF set to dropped bit.
+               if self.quirks.shift_vy {self.v[x] = self.v[y]};
                let bit = (self.v[x] >> 0x7) & 0x1;
                self.v[x] <<= 1;
                self.v[0xF] = bit;
@@ -200,39 +441,53 @@ impl Processor {
                 self.index = self.oc & 0x0FFF;
                 self.pc += 2;
             },
-            (0xB,_,_,_) => { // jump to address NNN + V0
-                self.pc = (self.oc & 0x0FFF) + (self.v[0] as u16);
+            (0xB,x,_,_) => { // jump to address NNN + V0 (or VX)
+                let offset = if self.quirks.jump_vx {self.v[x]} else {self.v[0]};
+                self.pc = (self.oc & 0x0FFF) + (offset as u16);
             },
             (0xC,x,_,_) => { // set VX to random number & NN
-                self.v[x] = rand::random::<u8>() & (self.oc & 0xFF) as u8;
+                self.v[x] = self.rng.next_byte() & (self.oc & 0xFF) as u8;
                 self.pc += 2;
             },
             (0xD,x,y,n) => { // complicated
+                if self.quirks.display_wait && !self.vblank {
+                    // try again at the start of the next frame
+                    self.display_wait = true;
+                    return Ok(());
+                }
                 let x = self.v[x] as usize;
                 let y = self.v[y] as usize;
                 self.draw_sprite(x, y, n as usize, &bus.memory);
                 self.pc += 2;
             },
             (0xE,x,0x9,0xE) => { // skip next if key in VX is pressed;
-                match self.keys[self.v[x] as usize] {
-                    Key::Down(_) => self.pc += 4,
+                let key = (self.v[x] & 0xF) as usize;
+                match self.keys[key] {
+                    Key::Down(_) => {
+                        self.pc += 4;
+                        self.keys[key] = Key::Up;
+                    },
+                    Key::Held => self.pc += 4,
                     Key::Up => self.pc += 2,
                 }
-                self.keys[self.v[x] as usize] = Key::Up;
             },
             (0xE,x,0xA,0x1) => { // skip next if key in VX is not pressed;
-                match self.keys[self.v[x] as usize] {
-                    Key::Down(_)  => self.pc += 2,
+                let key = (self.v[x] & 0xF) as usize;
+                match self.keys[key] {
+                    Key::Down(_)  => {
+                        self.pc += 2;
+                        self.keys[key] = Key::Up;
+                    },
+                    Key::Held => self.pc += 2,
                     Key::Up => self.pc += 4,
                 }
-                self.keys[self.v[x] as usize] = Key::Up;
             },
             (0xF,x,0x0,0x7) => {
                 self.v[x] = self.delay_timer;
                 self.pc += 2;
             },
             (0xF,x,0x0,0xA) => { // a keypress is awaited, then stored in v[x]
-                self.v[x] = bus.input.get_key();
+                self.waiting = Some(x);
                 self.pc += 2;
             },
             (0xF,x,0x1,0x5) => { // set delay timer to VX
@@ -249,31 +504,40 @@ impl Processor {
                 self.v[0xF] = if flag {1} else {0};
                 self.pc += 2;
             },
-            (0xF,x,0x2,0x9) => {
-                self.index = (self.v[x] as u16)*5;
+            (0xF,x,0x2,0x9) => { // point I at the font digit in VX's low nibble
+                self.index = ((self.v[x] & 0xF) as u16)*5;
                 self.pc += 2;
             },
             (0xF,x,0x3,0x3) => {
                 let i = self.index;
                 let vx = self.v[x];
                 bus.memory.write_memory(i,vx/100);
-                bus.memory.write_memory(i+1,(vx/10)%100);
-                bus.memory.write_memory(i+2,(vx%100)%10);
+                bus.memory.write_memory(i.wrapping_add(1),(vx/10)%10);
+                bus.memory.write_memory(i.wrapping_add(2),(vx%100)%10);
                 self.pc += 2;
             },
             (0xF,x,0x5,0x5) => { // stores V0 to VX (inclusive) starting at I
                 let index = self.index;
                 bus.memory.set_range(index, &self.v[0..(x+1)]);
+                if self.quirks.load_store_increment {
+                    self.index = self.index.wrapping_add(x as u16 + 1);
+                }
                 self.pc += 2;
             },
             (0xF,x,0x6,0x5) => { // fills V0 to VX (inclusive) starting from I
                 for i in 0..(x+1) as u16{
-                    self.v[i as usize] = bus.memory.read_memory(self.index + i);
+                    self.v[i as usize] =
+                        bus.memory.read_memory(self.index.wrapping_add(i));
+                }
+                if self.quirks.load_store_increment {
+                    self.index = self.index.wrapping_add(x as u16 + 1);
                 }
                 self.pc += 2;
             },
-            _ => panic!("unknown instruction received"),
+            _ => return Err(ExecError::InvalidOpcode{address:self.pc,
+                                                     opcode:self.oc}),
         };
+        Ok(())
     }
 
     fn decrement_delay_timer(&mut self){
@@ -305,59 +569,123 @@ impl Processor {
         display.refresh();
     }
 
+    /// Draws an 8-pixel-wide sprite of `height` rows from I. The
+    /// position wraps onto the screen, and pixels past the edges are
+    /// clipped, or wrapped with the `wrap_sprites` quirk. VF is set if
+    /// any lit pixel is erased.
     fn draw_sprite(&mut self, x:usize, y:usize, height:usize, memory:&Memory){
+        let (x, y) = (x % SCREEN_WIDTH, y % SCREEN_HEIGHT);
         self.v[0xF] = 0x0;
-        let location = x + y*SCREEN_WIDTH;
-        let index = self.index as usize;
         for i in 0..height {
-            if y + i < SCREEN_HEIGHT {
-                let byte = memory.read_memory((index + i) as u16) as usize;
-                self.draw_byte(location + i*SCREEN_WIDTH, byte);
-            }
+            let row = match (y + i, self.quirks.wrap_sprites) {
+                (row, _) if row < SCREEN_HEIGHT => row,
+                (row, true) => row % SCREEN_HEIGHT,
+                (_, false) => break,
+            };
+            let byte = memory.read_memory(self.index.wrapping_add(i as u16));
+            self.draw_byte(x, row, byte);
         }
     }
 
-    fn draw_byte(&mut self, loc:usize, byte:usize){
+    fn draw_byte(&mut self, x:usize, row:usize, byte:u8){
         for i in 0..8 {
-            if (byte & (0x80 >> i)) != 0 {
-                self.draw_flag = true;
-                if self.screen[loc+i] {self.v[0xF] = 0x1};
-                self.screen[loc+i] ^= true;
+            if (byte & (0x80 >> i)) == 0 {
+                continue;
             }
+            let col = match (x + i, self.quirks.wrap_sprites) {
+                (col, _) if col < SCREEN_WIDTH => col,
+                (col, true) => col % SCREEN_WIDTH,
+                (_, false) => break,
+            };
+            let loc = row*SCREEN_WIDTH + col;
+            self.draw_flag = true;
+            if self.screen[loc] {self.v[0xF] = 0x1};
+            self.screen[loc] ^= true;
         }
     }
 
     fn set_pushed<I:Input>(&mut self, input:&I){
-        let timeout = Duration::new(1,0);
-
         for key in &mut self.keys {
             *key = match *key {
-                Key::Down(x) if x.elapsed() > timeout => Key::Up,
-                _ => *key,
+                Key::Down(0x1) => Key::Up,
+                Key::Down(x) => Key::Down(x - 0x1),
+                key => key,
             };
         }
 
-        for key in input.get_keys(){
-            self.keys[key as usize] = Key::Down(Instant::now());
+        let (pressed, released) = (input.get_keys(), input.get_released());
+        let held = input.get_held();
+        for key in (0x0..0x10).filter(|&key| pressed & key_bit(key) != 0){
+            let held = held & key_bit(key) != 0;
+            if let Some(ref mut events) = self.recording {
+                events.push(KeyEvent{frame:self.frame, key, held,
+                                     released:false});
+            }
+            self.keys[key as usize] = match held {
+                true => Key::Held,
+                false => Key::Down(KEY_TIMEOUT),
+            };
+            if let Some(x) = self.waiting.take() {
+                self.v[x] = key;
+            }
+        }
+        for key in (0x0..0x10).filter(|&key| released & key_bit(key) != 0){
+            if let Some(ref mut events) = self.recording {
+                events.push(KeyEvent{frame:self.frame, key, held:false,
+                                     released:true});
+            }
+            self.keys[key as usize] = Key::Up;
         }
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    /// Starts logging every key the input device reports pressed or
+    /// released.
+    pub fn start_recording(&mut self){
+        self.recording = Some(Vec::new());
+    }
+
+    /// Stops logging keys, returning those logged.
+    pub fn take_recording(&mut self) -> Option<Vec<KeyEvent>> {
+        self.recording.take()
     }
     // pub &mut self functions
 
-    pub fn cycle<A, D, I>(&mut self, bus:&mut Bus<A, D, I>)
+    /// Fetches and executes one instruction, unless blocked on `FX0A` or
+    /// a `DXYN` waiting for the display. An
+    /// instruction that fails leaves the machine as it was, so stepping
+    /// again fails the same way.
+    pub fn step<A, D, I>(&mut self, bus:&mut Bus<A, D, I>)
+            -> Result<(), ExecError>
+            where
+                A: Audio,
+                D: Display,
+                I: Input {
+        if self.waiting.is_none() && !self.display_wait {
+            self.load_opcode(&bus.memory);
+            self.run_opcode(bus)?;
+            self.vblank = false;
+        }
+        Ok(())
+    }
+
+    /// Advances the timers, redraws the screen if it changed and polls
+    /// input. Called once per frame.
+    pub fn tick<A, D, I>(&mut self, bus:&mut Bus<A, D, I>)
             where
                 A: Audio,
                 D: Display,
                 I: Input {
-        self.load_opcode(&bus.memory);
-        self.run_opcode(bus);
         self.decrement_delay_timer();
         self.decrement_sound_timer(&bus.audio);
+        self.vblank = true;
+        self.display_wait = false;
 
         if self.draw_flag {
             self.print_screen(&mut bus.display);
             self.draw_flag = false;
-            std::thread::sleep(Duration::from_millis(FRAME_RATE));
         }
+        bus.display.end_frame();
         self.set_pushed(&bus.input);
     }
 }
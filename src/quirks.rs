@@ -0,0 +1,90 @@
+use std::vec::Vec;
+
+// Quirks
+////////////////////////////////////////////////////////////////////////
+
+/// Behaviours that differ between CHIP-8 interpreters. ROMs written for
+/// one interpreter often misbehave under another's choices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift VY into VX rather than shifting VX in place.
+    pub shift_vy:bool,
+    /// `FX55`/`FX65` leave I pointing past the last register transferred.
+    pub load_store_increment:bool,
+    /// `BNNN` jumps to NNN + VX rather than NNN + V0.
+    pub jump_vx:bool,
+    /// `8XY1`/`8XY2`/`8XY3` clear VF.
+    pub vf_reset:bool,
+    /// `DXYN` wraps pixels past the screen edges around to the other
+    /// side rather than clipping them.
+    pub wrap_sprites:bool,
+    /// `DXYN` waits for the next vertical blank before drawing, as on the
+    /// COSMAC VIP, so at most one sprite is drawn each frame.
+    pub display_wait:bool,
+}
+
+pub const PROFILES:[&str;0x4] = ["modern", "vip", "schip", "xochip"];
+
+/// The names of the individual quirks, matching the field names.
+pub const NAMES:[&str;0x6] = [
+    "shift_vy", "load_store_increment", "jump_vx", "vf_reset", "wrap_sprites",
+    "display_wait",
+];
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::profile("modern").unwrap()
+    }
+}
+
+impl Quirks {
+    /// Looks up a named quirk profile:
+    ///
+    /// * `modern` -- the behaviour most current ROMs expect
+    /// * `vip` -- the original COSMAC VIP interpreter
+    /// * `schip` -- SUPER-CHIP 1.1 on the HP-48
+    /// * `xochip` -- XO-CHIP as implemented by Octo
+    pub fn profile(name:&str) -> Option<Quirks> {
+        let (shift_vy, load_store_increment, jump_vx, vf_reset, wrap_sprites,
+             display_wait) = match name {
+            "modern" => (false, false, false, false, false, false),
+            "vip"    => (true,  true,  false, true,  false, true),
+            "schip"  => (false, false, true,  false, false, false),
+            "xochip" => (true,  true,  false, false, true,  false),
+            _ => return None,
+        };
+        Some(Quirks{shift_vy, load_store_increment, jump_vx, vf_reset,
+                    wrap_sprites, display_wait})
+    }
+
+    fn flag(&mut self, name:&str) -> Option<&mut bool> {
+        match name {
+            "shift_vy" => Some(&mut self.shift_vy),
+            "load_store_increment" => Some(&mut self.load_store_increment),
+            "jump_vx" => Some(&mut self.jump_vx),
+            "vf_reset" => Some(&mut self.vf_reset),
+            "wrap_sprites" => Some(&mut self.wrap_sprites),
+            "display_wait" => Some(&mut self.display_wait),
+            _ => None,
+        }
+    }
+
+    /// The names of the quirks that are on.
+    pub fn enabled(&self) -> Vec<&'static str> {
+        let mut quirks = *self;
+        NAMES.iter().cloned().filter(|name| *quirks.flag(name).unwrap())
+            .collect()
+    }
+
+    /// Turns a quirk on or off by name, returning false if there is no
+    /// such quirk.
+    pub fn set(&mut self, name:&str, on:bool) -> bool {
+        match self.flag(name) {
+            Some(flag) => {
+                *flag = on;
+                true
+            },
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,80 @@
+#[cfg(feature = "std")]
+use std::collections::hash_map::RandomState;
+#[cfg(feature = "std")]
+use std::hash::{BuildHasher, Hasher};
+use std::vec::Vec;
+
+// Random Number Sources
+////////////////////////////////////////////////////////////////////////
+
+/// Source of the random numbers `CXNN` uses. Implementations must be
+/// deterministic given their state so runs can be replayed.
+pub trait Random {
+    fn next_byte(&mut self) -> u8;
+    /// The generator's state, stored in save states.
+    fn save(&self) -> Vec<u8>;
+    /// Restores a state from `save`, returning false and leaving the
+    /// generator as it was if the state is invalid.
+    fn restore(&mut self, state:&[u8]) -> bool;
+}
+
+/// The default source: xorshift64*, which is fast and passes the common
+/// statistical tests, though it is not cryptographically secure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XorShift {
+    state:u64,
+}
+
+impl XorShift {
+    /// Creates a generator from a seed. The same seed always produces the
+    /// same sequence.
+    pub fn new(seed:u64) -> XorShift {
+        // scramble the seed with the SplitMix64 finalizer so nearby seeds
+        // give unrelated sequences. xorshift is stuck at zero, and the one
+        // seed that scrambles to zero is bumped off it.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        XorShift{state:if z == 0 {0x1} else {z}}
+    }
+
+    /// Creates a generator with a seed that differs from run to run.
+    #[cfg(feature = "std")]
+    pub fn from_entropy() -> XorShift {
+        XorShift::new(RandomState::new().build_hasher().finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+impl Random for XorShift {
+    fn next_byte(&mut self) -> u8 {
+        // the high bits of xorshift64* are the strongest
+        (self.next_u64() >> 56) as u8
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.state.to_be_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state:&[u8]) -> bool {
+        let mut bytes = [0x0u8;8];
+        if state.len() != bytes.len() {
+            return false;
+        }
+        bytes.copy_from_slice(state);
+        match u64::from_be_bytes(bytes) {
+            0x0 => false,
+            value => {
+                self.state = value;
+                true
+            },
+        }
+    }
+}
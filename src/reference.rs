@@ -0,0 +1,354 @@
+#![cfg(all(test, feature = "std"))]
+//! A reference CHIP-8 interpreter, written straight from the instruction
+//! descriptions with no regard for speed, for differential tests against
+//! `Processor`. Keep it simple enough to check by eye: one flat match,
+//! addresses masked to 12 bits wherever they are used, and no IO.
+
+use super::io::{SCREEN_WIDTH, SCREEN_HEIGHT};
+use super::memory::{Memory, RAM_SIZE};
+use super::processor::ExecError;
+use super::quirks::Quirks;
+use super::random::{Random, XorShift};
+use super::state::{Reader, StateError, Writer};
+
+/// Frames a key reads as held in the processor's save state.
+const KEY_HELD:u8 = 60;
+
+#[derive(Clone, PartialEq)]
+pub struct Machine {
+    pub v:[u8;0x10],
+    pub i:u16,
+    pub pc:u16,
+    pub stack:Vec<u16>,
+    pub delay:u8,
+    pub sound:u8,
+    /// Register awaiting a key press, which stops execution.
+    pub waiting:Option<usize>,
+    /// Set at the start of a frame, until an instruction runs.
+    pub vblank:bool,
+    /// A `DXYN` is waiting for the next frame, which stops execution.
+    pub display_wait:bool,
+    pub keys:[bool;0x10],
+    pub screen:Vec<bool>,
+    pub memory:Vec<u8>,
+    pub rng:XorShift,
+    pub quirks:Quirks,
+}
+
+impl Machine {
+    /// A machine at 0x200 with `program` loaded there, the font at 0x0,
+    /// and everything else zeroed.
+    pub fn new(program:&[u8], quirks:Quirks, seed:u64) -> Machine {
+        let font = Memory::default();
+        let mut memory:Vec<u8> = (0..RAM_SIZE as u16)
+            .map(|address| font.read_memory(address))
+            .collect();
+        for (offset, &byte) in program.iter().enumerate() {
+            memory[(0x200 + offset) % RAM_SIZE] = byte;
+        }
+        Machine{
+            v:[0x0;0x10],
+            i:0x0,
+            pc:0x200,
+            stack:Vec::new(),
+            delay:0x0,
+            sound:0x0,
+            waiting:None,
+            vblank:true,
+            display_wait:false,
+            keys:[false;0x10],
+            screen:vec![false;SCREEN_WIDTH*SCREEN_HEIGHT],
+            memory,
+            rng:XorShift::new(seed),
+            quirks,
+        }
+    }
+
+    fn read(&self, address:u16) -> u8 {
+        self.memory[(address & 0xFFF) as usize]
+    }
+
+    fn write(&mut self, address:u16, value:u8){
+        self.memory[(address & 0xFFF) as usize] = value;
+    }
+
+    pub fn opcode(&self) -> u16 {
+        (self.read(self.pc) as u16) << 8 | self.read(self.pc + 1) as u16
+    }
+
+    /// Runs one instruction, or nothing while waiting for a key or the
+    /// display.
+    pub fn step(&mut self) -> Result<(), ExecError> {
+        if self.waiting.is_some() || self.display_wait {
+            return Ok(());
+        }
+        let op = self.opcode();
+        let x = (op >> 8 & 0xF) as usize;
+        let y = (op >> 4 & 0xF) as usize;
+        let n = op & 0xF;
+        let nn = (op & 0xFF) as u8;
+        let nnn = op & 0xFFF;
+        let (vx, vy) = (self.v[x], self.v[y]);
+        let mut next = self.pc + 2;
+        let invalid = ExecError::InvalidOpcode{address:self.pc, opcode:op};
+
+        match op >> 12 {
+            0x0 if op == 0x00E0 => {
+                for pixel in &mut self.screen {
+                    *pixel = false;
+                }
+            },
+            0x0 if op == 0x00EE => match self.stack.pop() {
+                Some(address) => next = address + 2,
+                None => return Err(ExecError::StackUnderflow(self.pc)),
+            },
+            0x0 => return Err(ExecError::MachineCode{address:self.pc,
+                                                     opcode:op}),
+            0x1 => next = nnn,
+            0x2 => {
+                if self.stack.len() == 16 {
+                    return Err(ExecError::StackOverflow(self.pc));
+                }
+                self.stack.push(self.pc);
+                next = nnn;
+            },
+            0x3 => if vx == nn {next += 2},
+            0x4 => if vx != nn {next += 2},
+            0x5 if n == 0x0 => if vx == vy {next += 2},
+            0x6 => self.v[x] = nn,
+            0x7 => self.v[x] = vx.wrapping_add(nn),
+            0x8 => {
+                let reset = if self.quirks.vf_reset {Some(0x0)} else {None};
+                let (result, flag) = match n {
+                    0x0 => (vy, None),
+                    0x1 => (vx | vy, reset),
+                    0x2 => (vx & vy, reset),
+                    0x3 => (vx ^ vy, reset),
+                    0x4 => (vx.wrapping_add(vy),
+                            Some((vx as u16 + vy as u16 > 0xFF) as u8)),
+                    0x5 => (vx.wrapping_sub(vy), Some((vx >= vy) as u8)),
+                    0x7 => (vy.wrapping_sub(vx), Some((vy >= vx) as u8)),
+                    0x6 | 0xE => {
+                        let source = if self.quirks.shift_vy {vy} else {vx};
+                        if n == 0x6 {
+                            (source >> 1, Some(source & 0x1))
+                        } else {
+                            (source << 1, Some(source >> 7))
+                        }
+                    },
+                    _ => return Err(invalid),
+                };
+                // the flag is written last, so it wins when X is F
+                self.v[x] = result;
+                if let Some(flag) = flag {
+                    self.v[0xF] = flag;
+                }
+            },
+            0x9 if n == 0x0 => if vx != vy {next += 2},
+            0xA => self.i = nnn,
+            0xB => {
+                let offset = if self.quirks.jump_vx {vx} else {self.v[0]};
+                next = nnn + offset as u16;
+            },
+            0xC => self.v[x] = self.rng.next_byte() & nn,
+            0xD if self.quirks.display_wait && !self.vblank => {
+                self.display_wait = true;
+                next = self.pc;
+            },
+            0xD => {
+                // the sprite starts wrapped onto the screen, then is clipped
+                // at its edges, or wrapped with the quirk
+                let (left, top) = (vx as usize % SCREEN_WIDTH,
+                                   vy as usize % SCREEN_HEIGHT);
+                self.v[0xF] = 0x0;
+                for row in 0..n as usize {
+                    let bits = self.read(self.i.wrapping_add(row as u16));
+                    for col in 0..8 {
+                        let (mut px, mut py) = (left + col, top + row);
+                        if self.quirks.wrap_sprites {
+                            px %= SCREEN_WIDTH;
+                            py %= SCREEN_HEIGHT;
+                        }
+                        if bits & (0x80 >> col) == 0 || px >= SCREEN_WIDTH
+                                || py >= SCREEN_HEIGHT {
+                            continue;
+                        }
+                        let pixel = &mut self.screen[py*SCREEN_WIDTH + px];
+                        if *pixel {
+                            self.v[0xF] = 0x1;
+                        }
+                        *pixel = !*pixel;
+                    }
+                }
+            },
+            // testing a key releases it, since terminals report presses
+            // but not releases
+            0xE if nn == 0x9E || nn == 0xA1 => {
+                let key = vx as usize & 0xF;
+                if self.keys[key] == (nn == 0x9E) {
+                    next += 2;
+                }
+                self.keys[key] = false;
+            },
+            0xF => match nn {
+                0x07 => self.v[x] = self.delay,
+                0x0A => self.waiting = Some(x),
+                0x15 => self.delay = vx,
+                0x18 => self.sound = vx,
+                0x1E => {
+                    // VF reports I passing 16 bits, as this interpreter
+                    // has always done
+                    let sum = self.i as u32 + vx as u32;
+                    self.i = sum as u16;
+                    self.v[0xF] = (sum > 0xFFFF) as u8;
+                },
+                0x29 => self.i = (vx & 0xF) as u16 * 5,
+                0x33 => {
+                    let i = self.i;
+                    self.write(i, vx / 100);
+                    self.write(i.wrapping_add(1), vx / 10 % 10);
+                    self.write(i.wrapping_add(2), vx % 10);
+                },
+                0x55 | 0x65 => {
+                    for r in 0..=x {
+                        let address = self.i.wrapping_add(r as u16);
+                        if nn == 0x55 {
+                            let value = self.v[r];
+                            self.write(address, value);
+                        } else {
+                            self.v[r] = self.read(address);
+                        }
+                    }
+                    if self.quirks.load_store_increment {
+                        self.i = self.i.wrapping_add(x as u16 + 1);
+                    }
+                },
+                _ => return Err(invalid),
+            },
+            _ => return Err(invalid),
+        }
+        self.pc = next & 0xFFF;
+        self.vblank = false;
+        Ok(())
+    }
+
+    /// Writes the registers, stack, screen, keys and random state in the
+    /// layout of `Processor::save_state`, so a processor can start from
+    /// this machine.
+    pub fn save_processor(&self, state:&mut Writer){
+        state.u16(self.opcode());
+        state.u16(self.pc);
+        state.u16(self.stack.len() as u16);
+        state.u16(self.i);
+        state.u8(self.delay);
+        state.u8(self.sound);
+        state.u8(self.waiting.map_or(0xFF, |x| x as u8));
+        state.bytes(&self.v);
+        for level in 0..0x10 {
+            state.u16(self.stack.get(level).cloned().unwrap_or(0x0));
+        }
+        state.bits(&self.screen);
+        for &key in &self.keys {
+            state.u8(if key {KEY_HELD} else {0x0});
+        }
+        state.u64(0x0);
+        state.bits(&[self.vblank, self.display_wait]);
+        let rng = self.rng.save();
+        state.u16(rng.len() as u16);
+        state.bytes(&rng);
+    }
+
+    /// Reads back the state written by `Processor::save_state`, with
+    /// `memory` as the machine's RAM. The program counter is masked to 12
+    /// bits, since the processor only masks it when fetching.
+    pub fn load_processor(state:&mut Reader, memory:Vec<u8>, quirks:Quirks)
+            -> Result<Machine, StateError> {
+        let _opcode = state.u16()?;
+        let pc = state.u16()? & 0xFFF;
+        let sp = state.u16()? as usize;
+        let i = state.u16()?;
+        let delay = state.u8()?;
+        let sound = state.u8()?;
+        let waiting = match state.u8()? {
+            0xFF => None,
+            x => Some(x as usize),
+        };
+        let mut v = [0x0;0x10];
+        v.copy_from_slice(state.bytes(0x10)?);
+        let mut stack = Vec::new();
+        for level in 0..0x10 {
+            let address = state.u16()?;
+            if level < sp {
+                stack.push(address);
+            }
+        }
+        let mut screen = vec![false;SCREEN_WIDTH*SCREEN_HEIGHT];
+        state.bits(&mut screen)?;
+        let mut keys = [false;0x10];
+        for key in &mut keys {
+            *key = state.u8()? != 0x0;
+        }
+        let _frame = state.u64()?;
+        let mut flags = [false;2];
+        state.bits(&mut flags)?;
+        let (vblank, display_wait) = (flags[0], flags[1]);
+        let len = state.u16()? as usize;
+        let mut rng = XorShift::new(0x0);
+        if !rng.restore(state.bytes(len)?) {
+            return Err(StateError::Invalid("random number state"));
+        }
+        Ok(Machine{v, i, pc, stack, delay, sound, waiting, vblank,
+                   display_wait, keys, screen, memory, rng, quirks})
+    }
+
+    /// Describes each way `other` differs from this machine, or nothing
+    /// if they're the same.
+    pub fn differences(&self, other:&Machine) -> Vec<String> {
+        let mut out = Vec::new();
+        if self == other {
+            return out;
+        }
+        {
+            let mut field = |name:String, want:String, got:String| {
+                if want != got {
+                    out.push(format!("{}: expected {}, found {}", name, want,
+                                     got));
+                }
+            };
+            for r in 0..0x10 {
+                field(format!("V{:X}", r), format!("{:02X}", self.v[r]),
+                      format!("{:02X}", other.v[r]));
+            }
+            field("I".to_string(), format!("{:04X}", self.i),
+                  format!("{:04X}", other.i));
+            field("PC".to_string(), format!("{:03X}", self.pc),
+                  format!("{:03X}", other.pc));
+            field("stack".to_string(), format!("{:03X?}", self.stack),
+                  format!("{:03X?}", other.stack));
+            field("delay".to_string(), self.delay.to_string(),
+                  other.delay.to_string());
+            field("sound".to_string(), self.sound.to_string(),
+                  other.sound.to_string());
+            field("waiting".to_string(), format!("{:?}", self.waiting),
+                  format!("{:?}", other.waiting));
+            field("display wait".to_string(), self.display_wait.to_string(),
+                  other.display_wait.to_string());
+            field("keys".to_string(), format!("{:?}", self.keys),
+                  format!("{:?}", other.keys));
+            field("rng".to_string(), format!("{:?}", self.rng),
+                  format!("{:?}", other.rng));
+        }
+        let pixels = self.screen.iter().zip(&other.screen)
+            .filter(|&(a, b)| a != b)
+            .count();
+        if pixels > 0 {
+            out.push(format!("screen: {} pixels differ", pixels));
+        }
+        for (address, (a, b)) in self.memory.iter().zip(&other.memory)
+                .enumerate().filter(|&(_, (a, b))| a != b) {
+            out.push(format!("memory {:03X}: expected {:02X}, found {:02X}",
+                             address, a, b));
+        }
+        out
+    }
+}
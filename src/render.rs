@@ -0,0 +1,173 @@
+use chip_8::io::MAX_LEVEL;
+
+use theme::shade;
+
+/// Level from which a pixel counts as lit in the text renderers.
+const LIT:u8 = 0x60;
+/// Level from which a lit pixel is drawn at full brightness rather than
+/// dimmed.
+const BRIGHT:u8 = 0xC0;
+
+/// Braille dot bits for the pixels of a cell, row by row.
+const BRAILLE_DOTS:[u32;0x8] = [
+    0x01, 0x08, 0x02, 0x10, 0x04, 0x20, 0x40, 0x80,
+];
+
+// Renderers
+////////////////////////////////////////////////////////////////////////
+
+/// How the screen is drawn in character cells.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Renderer {
+    /// Each pixel is a `scale` by `scale` block of cells, lit or not by
+    /// video attributes.
+    Block(usize),
+    /// Each cell shows two pixels, one above the other, with `▀`, `▄` and
+    /// `█`, which makes pixels about square.
+    HalfBlock,
+    /// Each cell shows two columns of four pixels as a braille pattern.
+    Braille,
+}
+
+impl Renderer {
+    /// The renderer for a display mode. `auto` picks the half-block
+    /// renderer if a `width` by `height` screen fits in the terminal's
+    /// `columns` and `rows`, and braille if not.
+    pub fn choose(mode:&str, scale:usize, width:usize, height:usize,
+                  columns:usize, rows:usize) -> Renderer {
+        match mode {
+            "block" => Renderer::Block(scale),
+            "halfblock" => Renderer::HalfBlock,
+            "braille" => Renderer::Braille,
+            _ => {
+                let (need_rows, need_columns) =
+                    Renderer::HalfBlock.size(width, height);
+                if need_rows <= rows && need_columns <= columns {
+                    Renderer::HalfBlock
+                } else {
+                    Renderer::Braille
+                }
+            },
+        }
+    }
+
+    /// The pixels in each cell, as rows and columns.
+    pub fn cell(&self) -> (usize, usize) {
+        match *self {
+            Renderer::Block(_) => (1, 1),
+            Renderer::HalfBlock => (2, 1),
+            Renderer::Braille => (4, 2),
+        }
+    }
+
+    /// The rows and columns of cells a `width` by `height` screen takes.
+    pub fn size(&self, width:usize, height:usize) -> (usize, usize) {
+        match *self {
+            Renderer::Block(scale) => (height*scale, width*scale),
+            _ => {
+                let (rows, columns) = self.cell();
+                (height.div_ceil(rows), width.div_ceil(columns))
+            },
+        }
+    }
+
+    /// The character for a cell of a text renderer, given the levels of
+    /// its pixels row by row, and whether to dim it because its lit
+    /// pixels are all only partly lit.
+    pub fn glyph(&self, levels:&[u8]) -> (char, bool) {
+        let lit = |level:u8| level >= LIT;
+        let dim = levels.iter().any(|&level| lit(level))
+            && levels.iter().all(|&level| level < BRIGHT);
+        let chr = match *self {
+            Renderer::Block(_) => if lit(levels[0]) {'█'} else {' '},
+            Renderer::HalfBlock => match (lit(levels[0]), lit(levels[1])) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            },
+            Renderer::Braille => {
+                let dots = levels.iter().zip(&BRAILLE_DOTS)
+                    .filter(|&(&level, _)| lit(level))
+                    .fold(0x0, |dots, (_, &dot)| dots | dot);
+                ::std::char::from_u32(0x2800 + dots).unwrap()
+            },
+        };
+        (chr, dim)
+    }
+
+    /// The character for a cell of a text renderer drawn in color, and
+    /// the shades of its foreground and background. Half blocks show the
+    /// top pixel in the foreground and the bottom one in the background.
+    pub fn color_glyph(&self, levels:&[u8]) -> (char, usize, usize) {
+        match *self {
+            Renderer::HalfBlock =>
+                ('▀', shade(levels[0]), shade(levels[1])),
+            _ => {
+                let (chr, _) = self.glyph(levels);
+                let foreground = levels.iter()
+                    .filter(|&&level| level >= LIT)
+                    .map(|&level| shade(level))
+                    .max()
+                    .unwrap_or(0x0);
+                (chr, foreground, 0x0)
+            },
+        }
+    }
+}
+
+/// The level shown for a pixel with inverse colors.
+pub fn invert(level:u8) -> u8 {
+    MAX_LEVEL - level
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose(){
+        let choose = |mode, columns, rows| {
+            Renderer::choose(mode, 2, 64, 32, columns, rows)
+        };
+        assert_eq!(choose("auto", 80, 24), Renderer::HalfBlock);
+        assert_eq!(choose("auto", 80, 15), Renderer::Braille);
+        assert_eq!(choose("auto", 40, 40), Renderer::Braille);
+        assert_eq!(choose("block", 10, 10), Renderer::Block(2));
+        assert_eq!(choose("braille", 200, 100), Renderer::Braille);
+        // a 128x64 screen needs a bigger terminal for half blocks
+        assert_eq!(Renderer::choose("auto", 1, 128, 64, 120, 40),
+                   Renderer::Braille);
+        assert_eq!(Renderer::Braille.size(128, 64), (16, 64));
+    }
+
+    #[test]
+    fn test_glyphs(){
+        let (on, off, faint) = (MAX_LEVEL, 0x0, 0x80);
+        assert_eq!(Renderer::HalfBlock.glyph(&[on, off]), ('▀', false));
+        assert_eq!(Renderer::HalfBlock.glyph(&[off, on]), ('▄', false));
+        assert_eq!(Renderer::HalfBlock.glyph(&[on, faint]), ('█', false));
+        assert_eq!(Renderer::HalfBlock.glyph(&[faint, off]), ('▀', true));
+        assert_eq!(Renderer::HalfBlock.glyph(&[off, 0x20]), (' ', false));
+
+        assert_eq!(Renderer::Braille.glyph(&[off;8]), ('\u{2800}', false));
+        assert_eq!(Renderer::Braille.glyph(&[on;8]), ('\u{28FF}', false));
+        // the left column then the bottom right dot
+        let levels = [on, off, on, off, on, off, on, on];
+        assert_eq!(Renderer::Braille.glyph(&levels), ('\u{28C7}', false));
+    }
+
+    #[test]
+    fn test_color_glyphs(){
+        let (on, off, faint) = (MAX_LEVEL, 0x0, 0x80);
+        assert_eq!(Renderer::HalfBlock.color_glyph(&[on, faint]),
+                   ('▀', 0x3, 0x2));
+        assert_eq!(Renderer::HalfBlock.color_glyph(&[off, off]),
+                   ('▀', 0x0, 0x0));
+        let levels = [faint, off, off, off, off, off, off, 0x20];
+        assert_eq!(Renderer::Braille.color_glyph(&levels),
+                   ('\u{2801}', 0x2, 0x0));
+        assert_eq!(Renderer::Braille.color_glyph(&[off;8]),
+                   ('\u{2800}', 0x0, 0x0));
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use super::ini;
+use super::quirks::{Quirks, PROFILES};
+use super::sha1;
+
+pub use super::ini::ParseError;
+
+// Built-in Database
+////////////////////////////////////////////////////////////////////////
+
+const BUILTIN:&str = include_str!("romdb.ini");
+
+// Platforms
+////////////////////////////////////////////////////////////////////////
+
+/// The machine a ROM was written for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn from_name(name:&str) -> Option<Platform> {
+        match name {
+            "chip8" => Some(Platform::Chip8),
+            "schip" => Some(Platform::SuperChip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    /// The quirk profile ROMs for this platform expect unless their
+    /// database entry says otherwise. CHIP-8 ROMs were written for the
+    /// COSMAC VIP unless they say they are modern.
+    pub fn profile(&self) -> &'static str {
+        match *self {
+            Platform::Chip8 => "vip",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+}
+
+// ROM Information
+////////////////////////////////////////////////////////////////////////
+
+/// What the database knows about one ROM. Every field is optional so an
+/// override file can change just the fields it sets.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RomInfo {
+    pub title:Option<String>,
+    pub author:Option<String>,
+    pub platform:Option<Platform>,
+    /// Name of the quirk profile the ROM needs.
+    pub quirks:Option<String>,
+    /// Recommended instructions per frame.
+    pub speed:Option<usize>,
+    /// Recommended keymap preset or file.
+    pub keymap:Option<String>,
+}
+
+impl RomInfo {
+    pub fn platform(&self) -> Platform {
+        self.platform.unwrap_or(Platform::Chip8)
+    }
+
+    /// The quirk profile name: the entry's own, or its platform's.
+    pub fn profile(&self) -> &str {
+        match self.quirks {
+            Some(ref name) => name,
+            None => self.platform().profile(),
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        Quirks::profile(self.profile()).unwrap_or_default()
+    }
+
+    fn set(&mut self, key:&str, value:&str) -> Result<(), String> {
+        match key {
+            "title" => self.title = Some(value.to_string()),
+            "author" => self.author = Some(value.to_string()),
+            "platform" => self.platform = Some(Platform::from_name(value)
+                .ok_or_else(|| format!("unknown platform '{}'", value))?),
+            "quirks" if PROFILES.contains(&value) =>
+                self.quirks = Some(value.to_string()),
+            "quirks" => return Err(format!("unknown quirk profile '{}'", value)),
+            "speed" => self.speed = Some(value.parse().map_err(|_| {
+                format!("speed expects a number, found '{}'", value)
+            })?),
+            "keymap" => self.keymap = Some(value.to_string()),
+            _ => return Err(format!("unknown field '{}'", key)),
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other:&RomInfo){
+        macro_rules! merge {
+            ($($field:ident),*) => {$(
+                if other.$field.is_some() {
+                    self.$field = other.$field.clone();
+                }
+            )*}
+        }
+        merge!(title, author, platform, quirks, speed, keymap);
+    }
+}
+
+// Database
+////////////////////////////////////////////////////////////////////////
+
+/// ROM information keyed by the SHA-1 hash of the ROM image.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RomDatabase {
+    entries:HashMap<String, RomInfo>,
+}
+
+impl RomDatabase {
+    /// The database shipped with the emulator.
+    pub fn builtin() -> RomDatabase {
+        RomDatabase::parse(BUILTIN).expect("built-in ROM database is invalid")
+    }
+
+    /// Parses a database file: a section headed `[<sha1>]` per ROM, each
+    /// holding `field = value` lines for the `RomInfo` fields. Lines
+    /// starting with `#` are comments.
+    pub fn parse(text:&str) -> Result<RomDatabase, ParseError> {
+        let mut database = RomDatabase::default();
+        let mut section:Option<String> = None;
+
+        ini::parse(text, |line| match line {
+            ini::Line::Section(hash) => {
+                database.entries.entry(hash.clone()).or_default();
+                section = Some(hash);
+                Ok(())
+            },
+            ini::Line::Field(key, value) => match section {
+                Some(ref hash) =>
+                    database.entries.get_mut(hash).unwrap().set(key, value),
+                None => Err("field outside of a ROM section".to_string()),
+            },
+        })?;
+
+        Ok(database)
+    }
+
+    /// Overlays `other` onto this database. Fields set in `other` replace
+    /// the ones here; entries only in `other` are added.
+    pub fn merge(&mut self, other:&RomDatabase){
+        for (hash, info) in &other.entries {
+            self.entries.entry(hash.clone()).or_default().merge(info);
+        }
+    }
+
+    /// Looks up a ROM by the SHA-1 hash of its image, as hex digits.
+    pub fn get(&self, hash:&str) -> Option<&RomInfo> {
+        self.entries.get(&hash.to_lowercase())
+    }
+
+    /// Looks up a ROM by its image.
+    pub fn lookup(&self, rom:&[u8]) -> Option<&RomInfo> {
+        self.get(&sha1::hex_digest(rom))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
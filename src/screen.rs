@@ -0,0 +1,99 @@
+use theme::Theme;
+
+// Keys
+////////////////////////////////////////////////////////////////////////
+
+// Codes for keys that aren't characters, numbered the way curses numbers
+// them. Characters are their own code points.
+pub const KEY_ESCAPE:i32 = 0x1B;
+pub const KEY_DOWN:i32 = 0x102;
+pub const KEY_UP:i32 = 0x103;
+pub const KEY_LEFT:i32 = 0x104;
+pub const KEY_RIGHT:i32 = 0x105;
+pub const KEY_HOME:i32 = 0x106;
+pub const KEY_NPAGE:i32 = 0x152;
+pub const KEY_PPAGE:i32 = 0x153;
+pub const KEY_A1:i32 = 0x15C;
+pub const KEY_A3:i32 = 0x15D;
+pub const KEY_B2:i32 = 0x15E;
+pub const KEY_C1:i32 = 0x15F;
+pub const KEY_C3:i32 = 0x160;
+
+/// The code for function key `n`.
+pub fn key_f(n:u8) -> i32 {
+    0x108 + n as i32
+}
+
+/// A key read from the terminal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    /// A key was pressed, or is repeating as it's held.
+    Press(i32),
+    /// A key was released, on terminals that report it.
+    #[cfg_attr(not(feature = "ansi"), allow(dead_code))]
+    Release(i32),
+}
+
+// Styles
+////////////////////////////////////////////////////////////////////////
+
+pub const BOLD:u8 = 0x1;
+pub const DIM:u8 = 0x2;
+pub const REVERSE:u8 = 0x4;
+pub const UNDERLINE:u8 = 0x8;
+
+/// How text is drawn: video attributes, and the shades of the theme to
+/// draw it in, if colors are on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Style {
+    pub attributes:u8,
+    /// The foreground and background shades.
+    pub colors:Option<(usize, usize)>,
+}
+
+impl Style {
+    pub const NORMAL:Style = Style{attributes:0x0, colors:None};
+
+    pub fn attributes(attributes:u8) -> Style {
+        Style{attributes, colors:None}
+    }
+
+    pub fn colors(foreground:usize, background:usize) -> Style {
+        Style{attributes:0x0, colors:Some((foreground, background))}
+    }
+}
+
+// Screens
+////////////////////////////////////////////////////////////////////////
+
+/// A terminal the frontend draws on and reads keys from. Handles are
+/// cheap to clone and all share the one terminal.
+pub trait Screen: Clone {
+    /// Takes over the terminal.
+    fn start() -> Result<Self, String>;
+    /// Gives the terminal back as it was.
+    fn stop(&self);
+    /// The terminal's rows and columns.
+    fn size(&self) -> (usize, usize);
+    /// Draws `text` from `row` and `col`, cut off at the right edge.
+    /// False if it starts off the terminal.
+    fn put(&self, row:usize, col:usize, text:&str, style:Style) -> bool;
+    /// Draws a checkerboard cell, which the block renderer shows partly
+    /// lit pixels with.
+    fn put_stipple(&self, row:usize, col:usize, style:Style) -> bool;
+    /// Clears from `row` and `col` to the end of the row.
+    fn clear_line(&self, row:usize, col:usize);
+    /// Shows what was drawn since the last refresh.
+    fn refresh(&self);
+    fn beep(&self);
+    /// Sets up the shades of `theme` for styles with colors. False if
+    /// the terminal has no colors.
+    fn start_colors(&self, theme:&Theme) -> bool;
+    /// Whether `read_key` reports every key's release. Without them, keys
+    /// are let go after a while instead.
+    fn reports_releases(&self) -> bool {
+        false
+    }
+    /// The next key read, or `None` if no key is waiting.
+    fn read_key(&self) -> Option<Key>;
+}
@@ -0,0 +1,71 @@
+use std::string::String;
+
+// SHA-1
+////////////////////////////////////////////////////////////////////////
+
+// SHA-1 is only used to identify ROMs, matching the hashes published by
+// other emulators' ROM databases. It is not used for anything security
+// sensitive.
+
+pub const DIGEST_SIZE:usize = 0x14;
+
+/// Hashes `data` with SHA-1 (FIPS 180-4).
+pub fn digest(data:&[u8]) -> [u8;DIGEST_SIZE] {
+    let mut state:[u32;5] = [
+        0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0,
+    ];
+
+    // pad with a one bit, zeros, then the message length in bits
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 0x40 != 0x38 {
+        message.push(0x0);
+    }
+    let bits = (data.len() as u64).wrapping_mul(8);
+    message.extend_from_slice(&bits.to_be_bytes());
+
+    for block in message.chunks(0x40) {
+        let mut w = [0x0u32;0x50];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 0x10..0x50 {
+            w[i] = (w[i-3] ^ w[i-8] ^ w[i-14] ^ w[i-16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0x00..=0x13 => ((b & c) | (!b & d), 0x5A82_7999),
+                0x14..=0x27 => (b ^ c ^ d, 0x6ED9_EBA1),
+                0x28..=0x3B => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a.rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (value, add) in state.iter_mut().zip(&[a, b, c, d, e]) {
+            *value = value.wrapping_add(*add);
+        }
+    }
+
+    let mut out = [0x0u8;DIGEST_SIZE];
+    for (bytes, value) in out.chunks_mut(4).zip(&state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    out
+}
+
+/// Hashes `data` with SHA-1, as 40 lowercase hex digits.
+pub fn hex_digest(data:&[u8]) -> String {
+    digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
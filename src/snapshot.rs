@@ -0,0 +1,309 @@
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use super::chip8::{Chip8, LoadError, DEFAULT_SPEED};
+use super::processor::ExecError;
+use super::headless::{FrameBuffer, NullAudio, ScriptedInput};
+use super::io::{Pixel, SCREEN_WIDTH, SCREEN_HEIGHT};
+use super::quirks::Quirks;
+
+// Constants
+////////////////////////////////////////////////////////////////////////
+
+/// Environment variable that, set to anything but `0`, makes
+/// `SnapshotTest::run` rewrite snapshots instead of comparing them.
+pub const UPDATE_VAR:&str = "UPDATE_SNAPSHOTS";
+
+// Errors
+////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Load(LoadError),
+    /// An instruction failed before `frame` finished.
+    Exec{frame:u64, error:ExecError},
+    Io{path:PathBuf, message:String},
+    /// A snapshot file isn't a 64x32 ASCII-art or PBM image.
+    Invalid{path:PathBuf, message:String},
+    /// There is no snapshot file, and snapshots aren't being updated.
+    Missing(PathBuf),
+    /// The screen on `frame` differs from the snapshot. `diff` shows the
+    /// rows that differ.
+    Mismatch{path:PathBuf, frame:u64, diff:String},
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::Load(ref err) => write!(f, "{}", err),
+            SnapshotError::Exec{frame, ref error} =>
+                write!(f, "{} during frame {}", error, frame),
+            SnapshotError::Io{ref path, ref message} =>
+                write!(f, "{}: {}", path.display(), message),
+            SnapshotError::Invalid{ref path, ref message} =>
+                write!(f, "{} is not a valid snapshot: {}", path.display(),
+                       message),
+            SnapshotError::Missing(ref path) => write!(f,
+                "{} does not exist; run with {}=1 to create it",
+                path.display(), UPDATE_VAR),
+            SnapshotError::Mismatch{ref path, frame, ref diff} => write!(f,
+                "screen on frame {} differs from {}; run with {}=1 to \
+                 update it if the change is intended:\n{}",
+                frame, path.display(), UPDATE_VAR, diff),
+        }
+    }
+}
+
+impl error::Error for SnapshotError {}
+
+// Snapshot Files
+////////////////////////////////////////////////////////////////////////
+
+/// Parses a snapshot: ASCII art as from `FrameBuffer::to_ascii`, or a PBM
+/// image, plain (P1) or raw (P4), where black is lit.
+pub fn parse(data:&[u8]) -> Result<Vec<Pixel>, String> {
+    match data.get(0..2) {
+        Some(b"P1") | Some(b"P4") => parse_pbm(data),
+        _ => parse_ascii(&String::from_utf8_lossy(data)),
+    }
+}
+
+fn parse_ascii(text:&str) -> Result<Vec<Pixel>, String> {
+    let rows:Vec<&str> = text.lines().collect();
+    if rows.len() != SCREEN_HEIGHT {
+        return Err(format!("expected {} rows, found {}", SCREEN_HEIGHT,
+                           rows.len()));
+    }
+
+    let mut pixels = Vec::with_capacity(SCREEN_WIDTH*SCREEN_HEIGHT);
+    for (number, row) in rows.iter().enumerate() {
+        if row.chars().count() != SCREEN_WIDTH {
+            return Err(format!("row {} is not {} pixels wide", number,
+                               SCREEN_WIDTH));
+        }
+        for chr in row.chars() {
+            pixels.push(match chr {
+                '#' => Pixel::On,
+                '.' => Pixel::Off,
+                _ => return Err(format!("unexpected '{}' in row {}", chr,
+                                        number)),
+            });
+        }
+    }
+    Ok(pixels)
+}
+
+fn parse_pbm(data:&[u8]) -> Result<Vec<Pixel>, String> {
+    // the header is the magic number, width and height, separated by
+    // whitespace and comments running to the end of the line
+    let mut pos = 2;
+    let mut fields = Vec::new();
+    while fields.len() < 2 {
+        match data.get(pos) {
+            Some(b'#') => while data.get(pos).is_some_and(|&b| b != b'\n') {
+                pos += 1;
+            },
+            Some(byte) if byte.is_ascii_whitespace() => pos += 1,
+            Some(byte) if byte.is_ascii_digit() => {
+                let start = pos;
+                while data.get(pos).is_some_and(|b| b.is_ascii_digit()) {
+                    pos += 1;
+                }
+                let text = String::from_utf8_lossy(&data[start..pos]);
+                fields.push(text.parse::<usize>().unwrap_or(0));
+            },
+            _ => return Err("truncated PBM header".to_string()),
+        }
+    }
+    if fields != [SCREEN_WIDTH, SCREEN_HEIGHT] {
+        return Err(format!("image is {}x{}, not {}x{}", fields[0], fields[1],
+                           SCREEN_WIDTH, SCREEN_HEIGHT));
+    }
+
+    let size = SCREEN_WIDTH*SCREEN_HEIGHT;
+    let mut pixels = Vec::with_capacity(size);
+    if &data[..2] == b"P1" {
+        for &byte in &data[pos..] {
+            match byte {
+                b'1' => pixels.push(Pixel::On),
+                b'0' => pixels.push(Pixel::Off),
+                _ if byte.is_ascii_whitespace() => (),
+                _ => return Err(format!("unexpected '{}' in image data",
+                                        byte as char)),
+            }
+        }
+    } else {
+        // a single whitespace byte separates the header from raw data
+        for &byte in data.iter().skip(pos + 1) {
+            for bit in 0..8 {
+                pixels.push(match byte & (0x80 >> bit) {
+                    0 => Pixel::Off,
+                    _ => Pixel::On,
+                });
+            }
+        }
+    }
+
+    if pixels.len() != size {
+        return Err(format!("expected {} pixels, found {}", size,
+                           pixels.len()));
+    }
+    Ok(pixels)
+}
+
+fn to_ascii(pixels:&[Pixel]) -> Vec<String> {
+    pixels.chunks(SCREEN_WIDTH)
+        .map(|row| row.iter()
+             .map(|&pixel| if pixel == Pixel::On {'#'} else {'.'})
+             .collect())
+        .collect()
+}
+
+/// Describes how `actual` differs from `expected` row by row, with a
+/// caret under each differing pixel. Empty if they're the same.
+pub fn diff(expected:&[Pixel], actual:&[Pixel]) -> String {
+    let mut out = String::new();
+    let expected = to_ascii(expected);
+    let actual = to_ascii(actual);
+    for (row, (want, got)) in expected.iter().zip(&actual).enumerate() {
+        if want == got {
+            continue;
+        }
+        let carets:String = want.chars().zip(got.chars())
+            .map(|(a, b)| if a == b {' '} else {'^'})
+            .collect();
+        out += &format!("  row {:2} expected {}\n", row, want);
+        out += &format!("         actual   {}\n", got);
+        out += &format!("                  {}\n", carets.trim_end());
+    }
+    out
+}
+
+// Snapshot Tests
+////////////////////////////////////////////////////////////////////////
+
+/// Runs a ROM headlessly with scripted key presses and compares the screen
+/// on chosen frames to snapshot files.
+///
+/// Snapshots ending in `.pbm` are PBM images; any others are ASCII art.
+/// When `UPDATE_SNAPSHOTS` is set, or after `set_update(true)`, `run`
+/// writes the screens to the snapshot files instead of comparing them, for
+/// when the behaviour has changed on purpose.
+pub struct SnapshotTest {
+    rom:Vec<u8>,
+    quirks:Quirks,
+    speed:usize,
+    seed:u64,
+    keys:Vec<(u64, u8)>,
+    snapshots:Vec<(u64, PathBuf)>,
+    update:bool,
+}
+
+impl SnapshotTest {
+    pub fn new(rom:&[u8]) -> SnapshotTest {
+        SnapshotTest{
+            rom:rom.to_vec(),
+            quirks:Quirks::default(),
+            speed:DEFAULT_SPEED,
+            seed:0,
+            keys:Vec::new(),
+            snapshots:Vec::new(),
+            update:env::var(UPDATE_VAR).map(|value| value != "0")
+                .unwrap_or(false),
+        }
+    }
+
+    pub fn set_quirks(&mut self, quirks:Quirks){
+        self.quirks = quirks;
+    }
+
+    pub fn set_speed(&mut self, speed:usize){
+        self.speed = speed;
+    }
+
+    /// Seeds the random number generator. The default seed is 0.
+    pub fn set_seed(&mut self, seed:u64){
+        self.seed = seed;
+    }
+
+    pub fn set_update(&mut self, update:bool){
+        self.update = update;
+    }
+
+    /// Presses `key` on `frame`, frame 0 being the first run.
+    pub fn press(&mut self, frame:u64, key:u8){
+        self.keys.push((frame, key));
+    }
+
+    /// Compares the screen after `frame` frames to the file at `path`.
+    pub fn snapshot<P:Into<PathBuf>>(&mut self, frame:u64, path:P){
+        self.snapshots.push((frame, path.into()));
+    }
+
+    /// Runs until the last snapshot's frame, checking or updating every
+    /// snapshot. Stops at the first that doesn't match.
+    pub fn run(&self) -> Result<(), SnapshotError> {
+        let mut machine = Chip8::new(
+            NullAudio::default(),
+            FrameBuffer::default(),
+            ScriptedInput::new(&self.keys),
+        );
+        machine.set_speed(self.speed);
+        machine.set_quirks(self.quirks);
+        machine.seed(self.seed);
+        machine.load_rom(&self.rom).map_err(SnapshotError::Load)?;
+
+        let mut snapshots:Vec<&(u64, PathBuf)> = self.snapshots.iter()
+            .collect();
+        snapshots.sort_by_key(|&&(frame, _)| frame);
+
+        let mut frame = 0;
+        for &&(at, ref path) in &snapshots {
+            while frame < at {
+                machine.run_frame()
+                    .map_err(|error| SnapshotError::Exec{frame, error})?;
+                frame += 1;
+            }
+            self.check(machine.display(), frame, path)?;
+        }
+        Ok(())
+    }
+
+    fn check(&self, screen:&FrameBuffer, frame:u64, path:&PathBuf)
+            -> Result<(), SnapshotError> {
+        let io_error = |err:io::Error| SnapshotError::Io{
+            path:path.clone(),
+            message:err.to_string(),
+        };
+
+        if self.update {
+            let data = match path.extension() {
+                Some(ext) if ext == "pbm" => screen.to_pbm(),
+                _ => screen.to_ascii(),
+            };
+            return fs::write(path, data).map_err(io_error);
+        }
+
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound =>
+                return Err(SnapshotError::Missing(path.clone())),
+            Err(err) => return Err(io_error(err)),
+        };
+        let expected = parse(&data).map_err(|message| {
+            SnapshotError::Invalid{path:path.clone(), message}
+        })?;
+        if expected[..] != *screen.pixels() {
+            return Err(SnapshotError::Mismatch{
+                path:path.clone(),
+                frame,
+                diff:diff(&expected, screen.pixels()),
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::error;
+use std::fmt;
+use std::vec::Vec;
+
+// Save State Format
+////////////////////////////////////////////////////////////////////////
+
+// A save state is the magic bytes, a version byte, then each component's
+// fields in a fixed order, all multi-byte values big endian.
+pub const MAGIC:&[u8;4] = b"C8ST";
+pub const VERSION:u8 = 0x4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "not a chip-8 save state"),
+            StateError::UnsupportedVersion(version) =>
+                write!(f, "unsupported save state version {}", version),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(field) =>
+                write!(f, "save state has an invalid {}", field),
+        }
+    }
+}
+
+impl error::Error for StateError {}
+
+pub struct Writer {
+    buff:Vec<u8>,
+}
+
+impl Default for Writer {
+    fn default() -> Writer {
+        let mut buff = MAGIC.to_vec();
+        buff.push(VERSION);
+        Writer{buff}
+    }
+}
+
+impl Writer {
+    pub fn u8(&mut self, value:u8){
+        self.buff.push(value);
+    }
+
+    pub fn u16(&mut self, value:u16){
+        self.buff.push((value >> 0x8) as u8);
+        self.buff.push(value as u8);
+    }
+
+    pub fn u64(&mut self, value:u64){
+        self.buff.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn bytes(&mut self, values:&[u8]){
+        self.buff.extend_from_slice(values);
+    }
+
+    /// Packs the flags eight to a byte, most significant bit first.
+    pub fn bits(&mut self, values:&[bool]){
+        for chunk in values.chunks(8) {
+            let byte = chunk.iter().enumerate().fold(0x0, |byte, (i, &bit)| {
+                if bit {byte | (0x80 >> i)} else {byte}
+            });
+            self.buff.push(byte);
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buff
+    }
+}
+
+pub struct Reader<'a> {
+    buff:&'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buff:&'a [u8]) -> Result<Reader<'a>, StateError> {
+        if buff.len() < MAGIC.len() || &buff[..MAGIC.len()] != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let mut reader = Reader{buff:&buff[MAGIC.len()..]};
+        match reader.u8()? {
+            VERSION => Ok(reader),
+            version => Err(StateError::UnsupportedVersion(version)),
+        }
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.bytes(2)?;
+        Ok((bytes[0] as u16) << 0x8 | bytes[1] as u16)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0x0u8;8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    pub fn bytes(&mut self, len:usize) -> Result<&'a [u8], StateError> {
+        if self.buff.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.buff.split_at(len);
+        self.buff = tail;
+        Ok(head)
+    }
+
+    /// Unpacks flags written by `Writer::bits`.
+    pub fn bits(&mut self, values:&mut [bool]) -> Result<(), StateError> {
+        let bytes = self.bytes(values.len().div_ceil(8))?;
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = bytes[i / 8] & (0x80 >> (i % 8)) != 0;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.buff.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::Invalid("length"))
+        }
+    }
+}
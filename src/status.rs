@@ -0,0 +1,145 @@
+use std::time::{Duration, Instant};
+
+use chip_8::Registers;
+use chip_8::disasm::disassemble;
+
+/// Instructions the overlay disassembles from the program counter on.
+pub const OVERLAY_INSTRUCTIONS:usize = 0x8;
+
+// Rates
+////////////////////////////////////////////////////////////////////////
+
+/// Measures frames and instructions per second, over the last second.
+pub struct Meter {
+    start:Instant,
+    frames:u64,
+    instructions:u64,
+    fps:f64,
+    ips:f64,
+}
+
+impl Meter {
+    pub fn new(now:Instant, instructions:u64) -> Meter {
+        Meter{start:now, frames:0, instructions, fps:0.0, ips:0.0}
+    }
+
+    /// Counts a frame, run by the time `now`, with `instructions` run in
+    /// all. The rates change once a second has passed.
+    pub fn frame(&mut self, now:Instant, instructions:u64){
+        self.frames += 1;
+        let elapsed = now.duration_since(self.start);
+        if elapsed >= Duration::from_secs(1) {
+            let seconds = elapsed.as_secs_f64();
+            self.fps = self.frames as f64/seconds;
+            self.ips = (instructions - self.instructions) as f64/seconds;
+            *self = Meter{start:now, frames:0, instructions, ..*self};
+        }
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.fps
+    }
+
+    pub fn ips(&self) -> f64 {
+        self.ips
+    }
+}
+
+// Text
+////////////////////////////////////////////////////////////////////////
+
+/// The status line: the ROM name, speed, rates, and whether the machine
+/// is paused or beeping.
+pub fn status_line(name:&str, speed:usize, meter:&Meter, paused:bool,
+                   sound:bool) -> String {
+    let mut line = format!("{} | speed {} | {:.0} fps | {:.0} ips", name,
+                           speed, meter.fps(), meter.ips());
+    if paused {
+        line += " | paused";
+    }
+    if sound {
+        line += " | beep";
+    }
+    line
+}
+
+/// The overlay: the registers, then the instructions from the program
+/// counter on, read with `read`.
+pub fn overlay<F:Fn(u16) -> u8>(registers:&Registers, read:F) -> Vec<String> {
+    let mut lines = vec![
+        format!("pc {:03X}  i {:03X}  sp {:X}", registers.pc, registers.index,
+                registers.sp),
+        format!("dt {:02X}   st {:02X}", registers.delay_timer,
+                registers.sound_timer),
+    ];
+    for (row, values) in registers.v.chunks(0x4).enumerate() {
+        let cells:Vec<String> = values.iter().enumerate()
+            .map(|(col, value)| format!("v{:x} {:02X}", row*0x4 + col, value))
+            .collect();
+        lines.push(cells.join("  "));
+    }
+
+    lines.push(String::new());
+    for step in 0..OVERLAY_INSTRUCTIONS {
+        let address = registers.pc.wrapping_add(2*step as u16) & 0x0FFF;
+        let opcode = (read(address) as u16) << 0x8
+            | read(address.wrapping_add(1)) as u16;
+        let marker = if step == 0 {'>'} else {' '};
+        lines.push(format!("{} {:03X}: {:04X}  {}", marker, address, opcode,
+                           disassemble(opcode)));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meter(){
+        let start = Instant::now();
+        let mut meter = Meter::new(start, 100);
+        for frame in 1..=30 {
+            meter.frame(start + Duration::from_millis(frame*20), 100 + frame*5);
+        }
+        // the first second is 50 frames
+        assert_eq!(meter.fps(), 0.0);
+        for frame in 31..=50 {
+            meter.frame(start + Duration::from_millis(frame*20), 100 + frame*5);
+        }
+        assert_eq!((meter.fps(), meter.ips()), (50.0, 250.0));
+    }
+
+    #[test]
+    fn test_status_line(){
+        let meter = Meter::new(Instant::now(), 0);
+        assert_eq!(status_line("pong.ch8", 10, &meter, false, false),
+                   "pong.ch8 | speed 10 | 0 fps | 0 ips");
+        assert_eq!(status_line("pong.ch8", 10, &meter, true, true),
+                   "pong.ch8 | speed 10 | 0 fps | 0 ips | paused | beep");
+    }
+
+    #[test]
+    fn test_overlay(){
+        let registers = Registers{
+            v:[0x11;0x10],
+            index:0x2F0,
+            pc:0x200,
+            sp:1,
+            stack:[0x0;0x10],
+            delay_timer:0x3C,
+            sound_timer:0x0,
+        };
+        let program = [0x60, 0x05, 0xD0, 0x15];
+        let lines = overlay(&registers, |address| {
+            program.get(address as usize - 0x200).cloned().unwrap_or(0x0)
+        });
+
+        assert_eq!(lines[0], "pc 200  i 2F0  sp 1");
+        assert_eq!(lines[1], "dt 3C   st 00");
+        assert_eq!(lines[5], "vc 11  vd 11  ve 11  vf 11");
+        assert_eq!(lines[7], "> 200: 6005  ld v0, 0x05");
+        assert_eq!(lines[8], "  202: D015  drw v0, v1, 5");
+        assert_eq!(lines.len(), 7 + OVERLAY_INSTRUCTIONS);
+    }
+}
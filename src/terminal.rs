@@ -0,0 +1,527 @@
+use std::cell::{Cell, RefCell};
+
+use super::chip_8::io;
+use super::keymap::Keymap;
+use super::render::{invert, Renderer};
+use super::screen::{self, key_f, Key, Screen, Style, KEY_ESCAPE};
+use super::theme::{shade, Theme};
+
+pub struct Audio<S:Screen> {
+    screen: S,
+    muted: bool,
+}
+
+impl<S:Screen> Audio<S> {
+    pub fn new(screen: S, muted: bool) -> Audio<S> {
+        Audio{screen, muted}
+    }
+}
+
+impl<S:Screen> io::Audio for Audio<S> {
+    fn beep(&self){
+        if !self.muted {
+            self.screen.beep();
+        }
+    }
+}
+
+/// Function keys toggling the overlay, the status line and pausing.
+const KEY_OVERLAY: u8 = 1;
+const KEY_STATUS: u8 = 2;
+const KEY_PAUSE: u8 = 3;
+/// Unmapped keys kept for `take_unmapped`, beyond which they're dropped.
+const MAX_UNMAPPED: usize = 0x10;
+
+pub struct Input<S:Screen> {
+    screen: S,
+    keymap: Keymap,
+    quit: Cell<bool>,
+    overlay: Cell<bool>,
+    status: Cell<bool>,
+    paused: Cell<bool>,
+    unmapped: RefCell<Vec<i32>>,
+    /// Keys released during the last poll.
+    released: Cell<u16>,
+    /// Keys released after being pressed in the same poll, which are
+    /// released on the next so the press isn't lost.
+    deferred: Cell<u16>,
+    /// Keys pressed and not yet released, when the screen reports
+    /// releases.
+    down: Cell<u16>,
+}
+
+impl<S:Screen> Input<S> {
+    pub fn new(screen: S, keymap: Keymap) -> Input<S> {
+        Input{
+            screen,
+            keymap,
+            quit: Cell::new(false),
+            overlay: Cell::new(false),
+            status: Cell::new(false),
+            paused: Cell::new(false),
+            unmapped: RefCell::new(Vec::new()),
+            released: Cell::new(0x0),
+            deferred: Cell::new(0x0),
+            down: Cell::new(0x0),
+        }
+    }
+
+    /// True once the escape key has been pressed.
+    pub fn quit(&self) -> bool {
+        self.quit.get()
+    }
+
+    /// Whether to show the registers and upcoming instructions, toggled
+    /// with F1.
+    pub fn overlay(&self) -> bool {
+        self.overlay.get()
+    }
+
+    /// Whether to show the status line, toggled with F2.
+    pub fn status(&self) -> bool {
+        self.status.get()
+    }
+
+    pub fn set_status(&self, status: bool) {
+        self.status.set(status);
+    }
+
+    /// Whether the machine is paused, toggled with F3.
+    pub fn paused(&self) -> bool {
+        self.paused.get()
+    }
+
+    /// The codes of keys read since the last call that aren't in the
+    /// keymap, for commands.
+    pub fn take_unmapped(&self) -> Vec<i32> {
+        self.unmapped.replace(Vec::new())
+    }
+}
+
+fn toggle(flag: &Cell<bool>) {
+    flag.set(!flag.get());
+}
+
+impl<S:Screen> io::Input for Input<S> {
+    fn get_keys(&self) -> u16 {
+        let releases = self.screen.reports_releases();
+        let mut keys = 0x0;
+        let mut released = self.deferred.replace(0x0);
+        let mut down = self.down.get() & !released;
+        while let Some(event) = self.screen.read_key() {
+            match event {
+                Key::Press(KEY_ESCAPE) => self.quit.set(true),
+                Key::Press(code) if code == key_f(KEY_OVERLAY) =>
+                    toggle(&self.overlay),
+                Key::Press(code) if code == key_f(KEY_STATUS) =>
+                    toggle(&self.status),
+                Key::Press(code) if code == key_f(KEY_PAUSE) =>
+                    toggle(&self.paused),
+                Key::Press(code) => match self.keymap.get(code) {
+                    Some(key) => {
+                        let bit = io::key_bit(key);
+                        released &= !bit;
+                        // a key repeating while it's down isn't pressed
+                        // again, when its release will come
+                        if !releases || down & bit == 0 {
+                            keys |= bit;
+                        }
+                        down |= bit;
+                    },
+                    None => {
+                        let mut unmapped = self.unmapped.borrow_mut();
+                        if unmapped.len() < MAX_UNMAPPED {
+                            unmapped.push(code);
+                        }
+                    },
+                },
+                Key::Release(code) => match self.keymap.get(code) {
+                    Some(key) if keys & io::key_bit(key) != 0 =>
+                        self.deferred.set(self.deferred.get()
+                                          | io::key_bit(key)),
+                    Some(key) => {
+                        released |= io::key_bit(key);
+                        down &= !io::key_bit(key);
+                    },
+                    None => (),
+                },
+            }
+        }
+        self.released.set(released);
+        self.down.set(down);
+        keys
+    }
+
+    fn get_released(&self) -> u16 {
+        self.released.replace(0x0)
+    }
+
+    fn get_held(&self) -> u16 {
+        match self.screen.reports_releases() {
+            true => self.down.get(),
+            false => 0x0,
+        }
+    }
+}
+
+pub struct Display<S:Screen> {
+    screen: S,
+    renderer: Renderer,
+    inverse: bool,
+    colored: bool,
+    width: usize,
+    height: usize,
+    levels: Vec<u8>,
+    dirty: Vec<bool>,
+}
+
+impl<S:Screen> Display<S> {
+    /// Creates a display showing a `width` by `height` screen with
+    /// `renderer`. `inverse` swaps the on and off colors.
+    pub fn new(screen: S, renderer: Renderer, inverse: bool, width: usize,
+               height: usize) -> Display<S> {
+        Display{
+            screen,
+            renderer,
+            inverse,
+            colored: false,
+            width,
+            height,
+            levels: vec![0x0; width*height],
+            dirty: vec![true; width*height],
+        }
+    }
+
+    /// Draws in the colors of `theme`, rather than with video attributes,
+    /// if the terminal has colors.
+    pub fn set_theme(&mut self, theme: &Theme) {
+        let mut theme = theme.clone();
+        if self.inverse {
+            theme.invert();
+        }
+        self.colored = self.screen.start_colors(&theme);
+        for dirty in &mut self.dirty {
+            *dirty = true;
+        }
+    }
+
+    /// The terminal rows the screen takes.
+    pub fn rows(&self) -> usize {
+        self.renderer.size(self.width, self.height).0
+    }
+
+    /// The terminal columns the screen takes.
+    pub fn columns(&self) -> usize {
+        self.renderer.size(self.width, self.height).1
+    }
+
+    /// Shows partly lit pixels, from blending, with a stipple pattern, dim
+    /// for the faintest, or in color with a shade between the theme's off
+    /// and on colors.
+    fn draw_block(&self, row:usize, col:usize, scale:usize)
+            -> Result<(),()> {
+        let (lit, unlit) = match self.inverse {
+            false => (Style::NORMAL, Style::attributes(screen::REVERSE)),
+            true => (Style::attributes(screen::REVERSE), Style::NORMAL),
+        };
+        let dim = Style::attributes(unlit.attributes | screen::DIM);
+        let level = self.levels[row*self.width + col];
+        let (stipple, style) = match level {
+            _ if self.colored => {
+                let shade = shade(level);
+                (false, Style::colors(shade, shade))
+            },
+            0xC0..=0xFF => (false, lit),
+            0x60..=0xBF => (true, unlit),
+            0x01..=0x5F => (true, dim),
+            _ => (false, unlit),
+        };
+
+        for line in 0..scale {
+            for offset in 0..scale {
+                let (y, x) = (row*scale + line, col*scale + offset);
+                let drawn = match stipple {
+                    true => self.screen.put_stipple(y, x, style),
+                    false => self.screen.put(y, x, " ", style),
+                };
+                if !drawn {
+                    return Err(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// True if any pixel in the cell at `row` and `col` changed.
+    fn cell_dirty(&self, row:usize, col:usize) -> bool {
+        let (rows, cols) = self.renderer.cell();
+        let ys = row*rows..((row + 1)*rows).min(self.height);
+        ys.flat_map(|y| {
+            let xs = col*cols..((col + 1)*cols).min(self.width);
+            xs.map(move |x| y*self.width + x)
+        }).any(|pos| self.dirty[pos])
+    }
+
+    /// Draws the cell of a text renderer at `row` and `col`, in cells, as
+    /// one character.
+    fn draw_glyph(&self, row:usize, col:usize) -> Result<(),()> {
+        let (rows, cols) = self.renderer.cell();
+        let mut levels = Vec::with_capacity(rows*cols);
+        for y in row*rows..(row + 1)*rows {
+            for x in col*cols..(col + 1)*cols {
+                let level = match y < self.height && x < self.width {
+                    true => self.levels[y*self.width + x],
+                    false => 0x0,
+                };
+                levels.push(match self.inverse && !self.colored {
+                    true => invert(level),
+                    false => level,
+                });
+            }
+        }
+
+        let (chr, style) = match self.colored {
+            true => {
+                let (chr, foreground, background) =
+                    self.renderer.color_glyph(&levels);
+                (chr, Style::colors(foreground, background))
+            },
+            false => match self.renderer.glyph(&levels) {
+                (chr, true) => (chr, Style::attributes(screen::DIM)),
+                (chr, false) => (chr, Style::NORMAL),
+            },
+        };
+        match self.screen.put(row, col, &chr.to_string(), style) {
+            true => Ok(()),
+            false => Err(()),
+        }
+    }
+}
+
+impl<S:Screen> io::Display for Display<S> {
+    fn set(&mut self, row:usize, col:usize, state:io::Pixel) -> Result<(),()> {
+        let level = match state {
+            io::Pixel::On => io::MAX_LEVEL,
+            io::Pixel::Off => 0x0,
+        };
+        self.set_level(row, col, level)
+    }
+
+    /// Records the level of a pixel, to be drawn on the next refresh.
+    fn set_level(&mut self, row:usize, col:usize, level:u8) -> Result<(),()> {
+        if row >= self.height || col >= self.width {
+            return Err(());
+        }
+        let pos = row*self.width + col;
+        if self.levels[pos] != level {
+            self.levels[pos] = level;
+            self.dirty[pos] = true;
+        }
+        Ok(())
+    }
+
+    /// Redraws the cells holding pixels that changed since the last
+    /// refresh. Cells that don't fit in the terminal are left out.
+    fn refresh(&mut self){
+        // errors from drawing only mean a cell is off the terminal
+        match self.renderer {
+            Renderer::Block(scale) => {
+                for row in 0..self.height {
+                    for col in 0..self.width {
+                        if self.dirty[row*self.width + col] {
+                            let _ = self.draw_block(row, col, scale);
+                        }
+                    }
+                }
+            },
+            renderer => {
+                let (height, width) = renderer.size(self.width, self.height);
+                for row in 0..height {
+                    for col in 0..width {
+                        if self.cell_dirty(row, col) {
+                            let _ = self.draw_glyph(row, col);
+                        }
+                    }
+                }
+            },
+        }
+        for dirty in &mut self.dirty {
+            *dirty = false;
+        }
+        self.screen.refresh();
+    }
+}
+
+/// Prints the keymap to the right of the game screen, which takes `columns`
+/// terminal columns.
+pub fn show_keymap<S:Screen>(screen: &S, keymap: &Keymap, columns: usize) {
+    let col = columns + 2;
+    screen.put(0, col, "keymap", Style::NORMAL);
+    for (row, line) in keymap.describe().iter().enumerate() {
+        screen.put(row + 1, col, line, Style::NORMAL);
+    }
+    screen.refresh();
+}
+
+/// Text drawn around the game screen: a status line below it and an
+/// overlay to its right.
+pub struct Hud<S:Screen> {
+    screen: S,
+    status_row: usize,
+    overlay_row: usize,
+    overlay_col: usize,
+    /// Lines of overlay on the terminal, to be cleared when it shrinks.
+    overlay_lines: usize,
+    status_shown: bool,
+}
+
+impl<S:Screen> Hud<S> {
+    /// Creates a hud for a game screen taking `rows` and `columns`, with
+    /// the overlay starting on `overlay_row`, below anything else to the
+    /// right of the screen.
+    pub fn new(screen: S, rows: usize, columns: usize, overlay_row: usize)
+            -> Hud<S> {
+        Hud{
+            screen,
+            status_row: rows,
+            overlay_row,
+            overlay_col: columns + 2,
+            overlay_lines: 0,
+            status_shown: false,
+        }
+    }
+
+    /// Shows `line` as the status line, or clears it if `None`.
+    pub fn status(&mut self, line: Option<&str>) {
+        if line.is_none() && !self.status_shown {
+            return;
+        }
+        self.screen.clear_line(self.status_row, 0);
+        if let Some(line) = line {
+            self.screen.put(self.status_row, 0, line,
+                            Style::attributes(screen::REVERSE));
+        }
+        self.status_shown = line.is_some();
+        self.screen.refresh();
+    }
+
+    /// Shows `lines` as the overlay, clearing any lines it no longer
+    /// covers.
+    pub fn overlay(&mut self, lines: &[String]) {
+        if lines.is_empty() && self.overlay_lines == 0 {
+            return;
+        }
+        for row in 0..lines.len().max(self.overlay_lines) {
+            let row_on_screen = self.overlay_row + row;
+            self.screen.clear_line(row_on_screen, self.overlay_col);
+            if let Some(line) = lines.get(row) {
+                self.screen.put(row_on_screen, self.overlay_col, line,
+                                Style::NORMAL);
+            }
+        }
+        self.overlay_lines = lines.len();
+        self.screen.refresh();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    use chip_8::io::Input as InputDevice;
+
+    /// A screen that only has keys to read.
+    #[derive(Clone, Default)]
+    struct KeyScreen {
+        keys:Rc<RefCell<VecDeque<Key>>>,
+        releases:bool,
+    }
+
+    impl KeyScreen {
+        fn send(&self, keys:&[Key]){
+            self.keys.borrow_mut().extend(keys.iter().cloned());
+        }
+    }
+
+    impl Screen for KeyScreen {
+        fn start() -> Result<KeyScreen, String> {
+            Ok(KeyScreen::default())
+        }
+        fn stop(&self){}
+        fn size(&self) -> (usize, usize) {
+            (24, 80)
+        }
+        fn put(&self, _:usize, _:usize, _:&str, _:Style) -> bool {
+            true
+        }
+        fn put_stipple(&self, _:usize, _:usize, _:Style) -> bool {
+            true
+        }
+        fn clear_line(&self, _:usize, _:usize){}
+        fn refresh(&self){}
+        fn beep(&self){}
+        fn start_colors(&self, _:&Theme) -> bool {
+            false
+        }
+        fn reports_releases(&self) -> bool {
+            self.releases
+        }
+        fn read_key(&self) -> Option<Key> {
+            self.keys.borrow_mut().pop_front()
+        }
+    }
+
+    #[test]
+    fn test_input_keys(){
+        let screen = KeyScreen::default();
+        let input = Input::new(screen.clone(), Keymap::default());
+        screen.send(&[Key::Press('w' as i32), Key::Press(key_f(2)),
+                      Key::Press('p' as i32), Key::Release('w' as i32)]);
+        assert_eq!(input.get_keys(), 0x0020);
+        assert!(input.status() && !input.quit());
+        assert_eq!(input.take_unmapped(), ['p' as i32]);
+        // released in the same poll it was pressed, so on the next
+        assert_eq!(input.get_released(), 0x0);
+
+        screen.send(&[Key::Release('q' as i32), Key::Press(KEY_ESCAPE)]);
+        assert_eq!(input.get_keys(), 0x0);
+        assert_eq!(input.get_released(), 0x0030);
+        assert!(input.quit());
+
+        // a press after a release in the same poll leaves the key down
+        screen.send(&[Key::Release('q' as i32), Key::Press('q' as i32)]);
+        assert_eq!(input.get_keys(), 0x0010);
+        assert_eq!(input.get_released(), 0x0);
+    }
+
+    #[test]
+    fn test_input_held_keys(){
+        let screen = KeyScreen{releases:true, ..KeyScreen::default()};
+        let input = Input::new(screen.clone(), Keymap::default());
+        screen.send(&[Key::Press('w' as i32)]);
+        assert_eq!((input.get_keys(), input.get_held()), (0x0020, 0x0020));
+        // the terminal repeating the key doesn't press it again
+        screen.send(&[Key::Press('w' as i32), Key::Press('w' as i32)]);
+        assert_eq!((input.get_keys(), input.get_held()), (0x0, 0x0020));
+        screen.send(&[Key::Release('w' as i32)]);
+        assert_eq!(input.get_keys(), 0x0);
+        assert_eq!((input.get_released(), input.get_held()), (0x0020, 0x0));
+
+        // pressed and released in one poll, it's down until the next
+        screen.send(&[Key::Press('q' as i32), Key::Release('q' as i32)]);
+        assert_eq!((input.get_keys(), input.get_held()), (0x0010, 0x0010));
+        assert_eq!(input.get_keys(), 0x0);
+        assert_eq!((input.get_released(), input.get_held()), (0x0010, 0x0));
+
+        // without releases, nothing is held and every press counts
+        let screen = KeyScreen::default();
+        let input = Input::new(screen.clone(), Keymap::default());
+        screen.send(&[Key::Press('w' as i32)]);
+        assert_eq!((input.get_keys(), input.get_held()), (0x0020, 0x0));
+        screen.send(&[Key::Press('w' as i32)]);
+        assert_eq!(input.get_keys(), 0x0020);
+    }
+}
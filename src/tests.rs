@@ -1,14 +1,21 @@
-#![cfg(test)]
+#![cfg(all(test, feature = "std"))]
+#![allow(clippy::needless_range_loop, clippy::identity_op)]
+#![allow(clippy::assertions_on_constants)]
+extern crate proptest;
 extern crate rand;
 
 use super::*;
 use super::std;
-use self::rand::Rng;
+use self::proptest::prelude::*;
+use self::rand::{Rng, SeedableRng, StdRng};
 
 use super::bus;
 use super::io::{Audio, Display, Input};
+use super::random::Random;
 use super::memory;
 use super::processor;
+use super::reference;
+use super::state::{Reader, Writer};
 
 // Constants
 ////////////////////////////////////////////////////////////////////////
@@ -101,11 +108,8 @@ impl MockInput {
 }
 
 impl Input for MockInput {
-    fn get_keys(&self) -> Vec<u8> {
-        self.pressed.clone()
-    }
-    fn get_key(&self) -> u8 {
-        self.pressed[0]
+    fn get_keys(&self) -> u16 {
+        self.pressed.iter().fold(0x0, |keys, &key| keys | io::key_bit(key))
     }
 }
 
@@ -118,6 +122,43 @@ fn new_mock_bus() -> bus::Bus<MockAudio, MockDisplay, MockInput>{
         MockInput::default())
 }
 
+/// A generator for the randomized tests, seeded from `CHIP8_TEST_SEED`
+/// or else at random. The seed is printed, so a failure shows how to
+/// rerun it.
+fn test_rng() -> StdRng {
+    let seed = match std::env::var("CHIP8_TEST_SEED") {
+        Ok(seed) => seed.parse().expect("CHIP8_TEST_SEED isn't a number"),
+        Err(_) => rand::random::<usize>(),
+    };
+    println!("CHIP8_TEST_SEED={}", seed);
+    StdRng::from_seed(&[seed][..])
+}
+
+/// Drives a processor directly, the way `Chip8` does, to test it without
+/// a whole machine.
+trait Drive {
+    /// Runs one instruction and one frame tick, panicking if the
+    /// instruction fails.
+    fn cycle<A:Audio, D:Display, I:Input>(&mut self,
+                                         bus:&mut bus::Bus<A, D, I>);
+    /// Restores a processor state written by `save_state`.
+    fn load_state(&mut self, state:&mut Reader) -> Result<(), StateError>;
+}
+
+impl Drive for processor::Processor {
+    fn cycle<A:Audio, D:Display, I:Input>(&mut self,
+                                         bus:&mut bus::Bus<A, D, I>){
+        self.step(bus).unwrap();
+        self.tick(bus);
+    }
+
+    fn load_state(&mut self, state:&mut Reader) -> Result<(), StateError> {
+        let (loaded, rng) = self.read_state(state)?;
+        self.finish_load(loaded, rng);
+        Ok(())
+    }
+}
+
 // Mock Device Tests
 ////////////////////////////////////////////////////////////////////////
 
@@ -165,19 +206,17 @@ fn test_mock_input_get_keys(){
     let keys = mock.get_keys();
 
     for i in 0..0x10 {
-        assert_eq!(i as u8,keys[i]);
+        assert_eq!(keys & 1 << i, 1 << i);
     }
 }
 
 #[test]
-fn test_mock_input_get_key(){
-    let mut mock = MockInput::default();
-
-    for i in 0..0x10 {
-        mock.set(i);
-        assert_eq!(mock.get_key(),i);
-        mock.clear();
-    }
+fn test_key_bit(){
+    assert_eq!(io::key_bit(0x0), 0x0001);
+    assert_eq!(io::key_bit(0xF), 0x8000);
+    // keys past F would be a broken input device, and press nothing
+    assert_eq!(io::key_bit(0x10), 0x0);
+    assert_eq!(io::key_bit(0xFF), 0x0);
 }
 
 #[test]
@@ -236,7 +275,7 @@ fn test_memory_write(){
 
     for i in 0x0..memory::RAM_SIZE {
         for j in 0x0..0x100 {
-            let index = i as u16;;
+            let index = i as u16;
             let value = j as u8;
 
             values[i] = value;
@@ -266,11 +305,14 @@ fn test_memory_set_range(){
 ////////////////////////////////////////////////////////////////////////
 
 #[test]
-#[should_panic(expected="TODO -- 0x0NNN")]
 fn test_0nnn(){
     let mut bus = new_mock_bus();
+    bus.memory.set_range(0x200, &[0x01, 0x23]);
     let mut processor = processor::Processor::default();
-    processor.cycle(&mut bus);
+    let error = ExecError::MachineCode{address:0x200, opcode:0x0123};
+    assert_eq!(processor.step(&mut bus), Err(error));
+    // the machine stays on the failed instruction
+    assert_eq!(processor.step(&mut bus), Err(error));
 }
 
 #[test]
@@ -297,7 +339,7 @@ fn test_00e0(){
 
 #[test]
 fn test_1nnn(){
-    let mut rng = rand::thread_rng();
+    let mut rng = test_rng();
     for _ in 0..1000 {
         let nnn = rng.gen_range(0x204, 0xFFC);
         let zn = ((nnn >> 0x8) | 0x10) as u8;
@@ -394,8 +436,7 @@ fn test_2nnn_00ee_depth_and_back(){
 }
 
 #[test]
-#[should_panic(expected="subtract with overflow")]
-fn test_2nnn_00ee_panic_stack_overflow_bottom(){
+fn test_2nnn_00ee_stack_underflow(){
     let mut memory = [0x0u8;0x200];
 
     let mut base = 0x0;
@@ -417,16 +458,17 @@ fn test_2nnn_00ee_panic_stack_overflow_bottom(){
     let mut bus = new_mock_bus();
     bus.memory.set_range(0x200, &memory);
 
-    // the 0x11th 00ee call panics due to popping too much off the stack
+    // the 0x11th 00ee call fails due to popping too much off the stack
     let mut processor = processor::Processor::default();
-    for _ in 0x0..0x21 {
+    for _ in 0x0..0x20 {
         processor.cycle(&mut bus);
     }
+    assert_eq!(processor.step(&mut bus),
+               Err(ExecError::StackUnderflow(0x202)));
 }
 
 #[test]
-#[should_panic(expected="index out of bounds")]
-fn test_2nnn_00ee_panic_stack_overflow_top(){
+fn test_2nnn_00ee_stack_overflow(){
     let mut memory = [0x0u8;0x200];
 
     let mut base = 0x0;
@@ -441,11 +483,13 @@ fn test_2nnn_00ee_panic_stack_overflow_top(){
     let mut bus = new_mock_bus();
     bus.memory.set_range(0x200, &memory);
 
-    // the 0x11th 2nnn call panics due to exceeding stack size
+    // the 0x11th 2nnn call fails due to exceeding stack size
     let mut processor = processor::Processor::default();
-    for _ in 0x0..0x11 {
+    for _ in 0x0..0x10 {
         processor.cycle(&mut bus);
     }
+    assert_eq!(processor.step(&mut bus),
+               Err(ExecError::StackOverflow(0x300)));
 }
 
 #[test]
@@ -1129,7 +1173,7 @@ fn test_annn(){
 
 #[test]
 fn test_bnnn(){
-    let mut rng = rand::thread_rng();
+    let mut rng = test_rng();
     for _ in 0..1000 {
 
         let nnn:u16 = rng.gen_range(0x206, 0xEFD);
@@ -1168,10 +1212,10 @@ fn test_bnnn(){
 
 #[test]
 fn test_cxnn(){
-    let mut difference = false;
     for x in 0x0u8..0x10 {
         for nn in 0x0..0x100 {
             let nn = nn as u8;
+            let seed = (x as u64) << 0x8 | nn as u64;
             let memory = [
                 0xA3, 0x00,
                 0xC0 | x, nn,
@@ -1182,23 +1226,97 @@ fn test_cxnn(){
             bus.memory.set_range(0x200, &memory);
 
             let mut processor = processor::Processor::default();
+            processor.set_rng(Box::new(random::XorShift::new(seed)));
             processor.cycle(&mut bus);
             processor.cycle(&mut bus);
             processor.cycle(&mut bus);
 
-            let value = bus.memory.read_memory(0x300 | x as u16);
-            let test  = value & nn;
-            assert_eq!(value, test);
-            difference = value != nn;
+            let expected = random::XorShift::new(seed).next_byte() & nn;
+            assert_eq!(bus.memory.read_memory(0x300 | x as u16), expected);
         };
     }
-    assert!(difference);
 }
 
 #[test]
-#[ignore]
 fn test_dxyn(){
-    assert!(false);
+    let memory = [
+        0x60, 0x05,
+        0x61, 0x03,
+        0xA2, 0x14,
+        0xD0, 0x13,
+        0xA3, 0x00,
+        0xFF, 0x55,
+        0xA2, 0x14,
+        0xD0, 0x11,
+        0xA3, 0x10,
+        0xFF, 0x55,
+        0xF0, 0x90, 0xF0,
+    ];
+    let sprite = [0xF0, 0x90, 0xF0];
+
+    let mut bus = new_mock_bus();
+    bus.memory.set_range(0x200, &memory);
+
+    let mut processor = processor::Processor::default();
+    for _ in 0..6 {
+        processor.cycle(&mut bus);
+    }
+
+    // drawing onto a blank screen lights the sprite's bits and clears VF
+    assert_eq!(bus.memory.read_memory(0x30F), 0x0);
+    for row in 0x0..io::SCREEN_HEIGHT {
+        for col in 0x0..io::SCREEN_WIDTH {
+            let lit = (3..6).contains(&row) && (5..13).contains(&col)
+                && sprite[row - 3] & (0x80 >> (col - 5)) != 0;
+            let pixel = if lit {io::Pixel::On} else {io::Pixel::Off};
+            assert_eq!(bus.display.drawn[row*io::SCREEN_WIDTH + col], pixel);
+        }
+    }
+
+    // drawing the top row again erases it and sets VF
+    for _ in 0..4 {
+        processor.cycle(&mut bus);
+    }
+    assert_eq!(bus.memory.read_memory(0x31F), 0x1);
+    for col in 0x0..io::SCREEN_WIDTH {
+        assert_eq!(bus.display.drawn[3*io::SCREEN_WIDTH + col],
+                   io::Pixel::Off);
+    }
+    assert_eq!(bus.display.drawn[4*io::SCREEN_WIDTH + 5], io::Pixel::On);
+}
+
+#[test]
+fn test_dxyn_edges(){
+    // a 2x8 block at (124, 63), which wraps to (60, 31) before drawing
+    let memory = [
+        0x60, 0x7C,
+        0x61, 0x3F,
+        0xA2, 0x0A,
+        0xD0, 0x12,
+        0x00, 0x00,
+        0xFF, 0xFF,
+    ];
+    for &(profile, wrapped) in &[("modern", false), ("xochip", true)] {
+        let mut bus = new_mock_bus();
+        bus.memory.set_range(0x200, &memory);
+
+        let mut processor = new_quirk_processor(profile);
+        for _ in 0..4 {
+            processor.cycle(&mut bus);
+        }
+
+        // pixels past the right and bottom edges are clipped, or wrap
+        // around to the left and top with the quirk
+        for row in 0x0..io::SCREEN_HEIGHT {
+            for col in 0x0..io::SCREEN_WIDTH {
+                let lit = (row == 31 || (wrapped && row == 0))
+                    && (col >= 60 || (wrapped && col < 4));
+                let pixel = if lit {io::Pixel::On} else {io::Pixel::Off};
+                assert_eq!(bus.display.drawn[row*io::SCREEN_WIDTH + col],
+                           pixel, "{} at ({}, {})", profile, col, row);
+            }
+        }
+    }
 }
 
 #[test]
@@ -1330,6 +1448,56 @@ fn test_fx0a(){
     }
 }
 
+#[test]
+fn test_fx0a_waits_for_key(){
+    let memory = [
+        0xA3, 0x00,
+        0xF5, 0x0A,
+        0xFF, 0x55,
+    ];
+
+    let mut bus = new_mock_bus();
+    bus.memory.set_range(0x200, &memory);
+
+    let mut processor = processor::Processor::default();
+    processor.cycle(&mut bus);
+    processor.cycle(&mut bus);
+    assert_eq!(processor.waiting_for_key(), Some(0x5));
+
+    // no key pressed, so the store at 0x204 must not run
+    processor.cycle(&mut bus);
+    processor.cycle(&mut bus);
+    assert_eq!(processor.waiting_for_key(), Some(0x5));
+    assert_eq!(bus.memory.read_memory(0x300), 0x00);
+
+    bus.input.set(0xB);
+    processor.cycle(&mut bus);
+    assert_eq!(processor.waiting_for_key(), None);
+    processor.cycle(&mut bus);
+    assert_eq!(bus.memory.read_memory(0x305), 0xB);
+}
+
+#[test]
+fn test_fx0a_timers_tick_while_waiting(){
+    let memory = [
+        0x60, 0x05,
+        0xF0, 0x15,
+        0xF0, 0x18,
+        0xF1, 0x0A,
+    ];
+
+    let mut bus = new_mock_bus();
+    bus.memory.set_range(0x200, &memory);
+
+    let mut processor = processor::Processor::default();
+    for _ in 0x0..0x8 {
+        processor.cycle(&mut bus);
+    }
+
+    assert_eq!(processor.waiting_for_key(), Some(0x1));
+    assert!(bus.audio.beeped.get());
+}
+
 #[test]
 #[ignore]
 fn test_fx15(){
@@ -1400,7 +1568,7 @@ fn test_fx33(){
             processor.cycle(&mut bus);
 
             let hundreds = xval / 100;
-            let tens = (xval / 10) % 100;
+            let tens = (xval / 10) % 10;
             let ones = xval % 10;
 
             assert_eq!(bus.memory.read_memory(0x300), hundreds);
@@ -1488,3 +1656,1400 @@ fn test_fx65(){
         }
     }
 }
+
+// Quirk Tests
+////////////////////////////////////////////////////////////////////////
+
+fn new_quirk_processor(profile:&str) -> processor::Processor {
+    let mut processor = processor::Processor::default();
+    processor.set_quirks(Quirks::profile(profile).unwrap());
+    processor
+}
+
+#[test]
+fn test_quirk_profiles(){
+    for name in &quirks::PROFILES {
+        assert!(Quirks::profile(name).is_some());
+    }
+    assert!(Quirks::profile("cosmac").is_none());
+    assert_eq!(Quirks::default(), Quirks::profile("modern").unwrap());
+}
+
+#[test]
+fn test_quirk_shift_vy(){
+    for &(profile, expected) in &[("modern", 0x02), ("vip", 0x40)] {
+        let memory = [
+            0x60, 0x04,
+            0x61, 0x81,
+            0x80, 0x16,
+            0xA3, 0x00,
+            0xF0, 0x55,
+        ];
+
+        let mut bus = new_mock_bus();
+        bus.memory.set_range(0x200, &memory);
+
+        let mut processor = new_quirk_processor(profile);
+        for _ in 0x0..0x5 {
+            processor.cycle(&mut bus);
+        }
+        assert_eq!(bus.memory.read_memory(0x300), expected);
+    }
+}
+
+#[test]
+fn test_quirk_vf_reset(){
+    for &(profile, expected) in &[("modern", 0x0F), ("vip", 0x00)] {
+        let memory = [
+            0x6F, 0x0F,
+            0x60, 0x01,
+            0x80, 0x01,
+            0xA3, 0x00,
+            0xFF, 0x55,
+        ];
+
+        let mut bus = new_mock_bus();
+        bus.memory.set_range(0x200, &memory);
+
+        let mut processor = new_quirk_processor(profile);
+        for _ in 0x0..0x5 {
+            processor.cycle(&mut bus);
+        }
+        assert_eq!(bus.memory.read_memory(0x30F), expected);
+    }
+}
+
+#[test]
+fn test_quirk_jump_vx(){
+    for &(profile, expected) in &[("modern", 0x11), ("schip", 0x22)] {
+        let mut memory = [0x0u8;0x134];
+        memory[..0x6].copy_from_slice(&[
+            0x60, 0x10,
+            0x63, 0x20,
+            0xB3, 0x00,
+        ]);
+        // landing pads for B300 offset by V0 or V3
+        memory[0x110..0x114].copy_from_slice(&[0x65, 0x11, 0x13, 0x30]);
+        memory[0x120..0x124].copy_from_slice(&[0x65, 0x22, 0x13, 0x30]);
+        memory[0x130..0x134].copy_from_slice(&[0xA3, 0x80, 0xF5, 0x55]);
+
+        let mut bus = new_mock_bus();
+        bus.memory.set_range(0x200, &memory);
+
+        let mut processor = new_quirk_processor(profile);
+        for _ in 0x0..0x7 {
+            processor.cycle(&mut bus);
+        }
+        assert_eq!(bus.memory.read_memory(0x385), expected);
+    }
+}
+
+#[test]
+fn test_quirk_display_wait(){
+    // three draws then a loop, with the display_wait quirk putting off
+    // the second draw to the next frame
+    let cases = [("modern", 0x206, 0x206), ("vip", 0x202, 0x204)];
+    for &(profile, first_frame, second_frame) in &cases {
+        let memory = [0xD0, 0x01, 0xD0, 0x01, 0xD0, 0x01, 0x12, 0x06];
+
+        let mut bus = new_mock_bus();
+        bus.memory.set_range(0x200, &memory);
+
+        let mut processor = new_quirk_processor(profile);
+        for _ in 0x0..0x3 {
+            processor.step(&mut bus).unwrap();
+        }
+        assert_eq!(processor.pc(), first_frame);
+        assert_eq!(processor.waiting_for_display(), profile == "vip");
+
+        processor.tick(&mut bus);
+        processor.step(&mut bus).unwrap();
+        processor.step(&mut bus).unwrap();
+        assert_eq!(processor.pc(), second_frame);
+    }
+}
+
+#[test]
+fn test_quirk_load_store_increment(){
+    for &(profile, expected) in &[("modern", 0x01), ("vip", 0x00)] {
+        let memory = [
+            0x60, 0x01,
+            0x61, 0x02,
+            0xA3, 0x00,
+            0xF1, 0x55,
+            0xF0, 0x65,     // reads 0x300, or 0x302 if I moved
+            0xA3, 0x10,
+            0xF0, 0x55,
+        ];
+
+        let mut bus = new_mock_bus();
+        bus.memory.set_range(0x200, &memory);
+
+        let mut processor = new_quirk_processor(profile);
+        for _ in 0x0..0x7 {
+            processor.cycle(&mut bus);
+        }
+        assert_eq!(bus.memory.read_memory(0x310), expected);
+    }
+}
+
+// Chip8 Tests
+////////////////////////////////////////////////////////////////////////
+
+fn new_mock_chip8() -> Chip8<MockAudio, MockDisplay, MockInput> {
+    Chip8::new(
+        MockAudio::default(),
+        MockDisplay::default(),
+        MockInput::default())
+}
+
+// offsets into a save state: magic, version, then oc, pc, sp and index
+const STATE_DELAY_TIMER:usize = 0xD;
+const STATE_V0:usize = 0x10;
+
+#[test]
+fn test_chip8_run_frame_speed(){
+    // V0 counts loop iterations, the delay timer counts frames
+    let memory = [
+        0x61, 0x10,
+        0xF1, 0x15,
+        0x70, 0x01,
+        0x12, 0x04,
+    ];
+
+    for &(speed, v0, delay) in &[(1, 0x1, 0xD), (10, 0x13, 0xC)] {
+        let mut machine = new_mock_chip8();
+        machine.set_speed(speed);
+        machine.load_rom(&memory).unwrap();
+        for _ in 0x0..0x4 {
+            machine.run_frame().unwrap();
+        }
+
+        let state = machine.save_state();
+        assert_eq!(state[STATE_V0], v0);
+        assert_eq!(state[STATE_DELAY_TIMER], delay);
+    }
+}
+
+fn headless_chip8() -> Chip8<headless::NullAudio, headless::FrameBuffer,
+                             headless::NoInput> {
+    Chip8::new(
+        headless::NullAudio::default(),
+        headless::FrameBuffer::default(),
+        headless::NoInput::default())
+}
+
+#[test]
+fn test_chip8_state_round_trip(){
+    let memory = [
+        0x60, 0x05,
+        0xF0, 0x29,
+        0xD0, 0x05,
+        0x22, 0x0A,
+        0x12, 0x08,
+        0xF0, 0x0A,
+    ];
+
+    let mut machine = headless_chip8();
+    machine.load_rom(&memory).unwrap();
+    for _ in 0x0..0x2 {
+        machine.run_frame().unwrap();
+    }
+    assert_eq!(machine.waiting_for_key(), Some(0x0));
+    let state = machine.save_state();
+
+    let mut restored = headless_chip8();
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.waiting_for_key(), Some(0x0));
+    assert_eq!(restored.save_state(), state);
+
+    restored.run_frame().unwrap();
+    assert_eq!(restored.display().to_ascii(), machine.display().to_ascii());
+    assert!(restored.display().to_ascii().contains("####"));
+}
+
+#[test]
+fn test_chip8_state_errors(){
+    let mut machine = headless_chip8();
+    let mut state = machine.save_state();
+
+    assert_eq!(machine.load_state(b"C8"), Err(StateError::BadMagic));
+    assert_eq!(machine.load_state(&state[..0x100]),
+               Err(StateError::Truncated));
+
+    state[0x4] = 0x7F;
+    assert_eq!(machine.load_state(&state),
+               Err(StateError::UnsupportedVersion(0x7F)));
+}
+
+#[test]
+fn test_chip8_bad_state_untouched(){
+    // calls a subroutine that returns, over and over
+    let memory = [0x22, 0x04, 0x12, 0x00, 0x00, 0xEE];
+    let mut machine = headless_chip8();
+    machine.load_rom(&memory).unwrap();
+    machine.start_recording(0x0);
+    machine.run_frame().unwrap();
+    let state = machine.save_state();
+
+    // offsets past the header of the stack pointer, key 0 and the random
+    // number state
+    let keys = 0x5 + 0x8 + 0x2 + 0x1 + 0x10 + 0x20 + 0x100;
+    let rng = keys + 0x10 + 0x8 + 0x1 + 0x2;
+    for &(at, bytes, error) in &[
+        (0x9, &[0x0, 0x20][..], "stack pointer"),
+        (keys, &[0x80][..], "key"),
+        (rng, &[0x0;8][..], "random number state"),
+    ] {
+        let mut corrupt = state.clone();
+        corrupt[at..at + bytes.len()].copy_from_slice(bytes);
+        assert_eq!(machine.load_state(&corrupt),
+                   Err(StateError::Invalid(error)));
+        assert_eq!(machine.save_state(), state);
+    }
+    machine.run_frame().unwrap();
+    assert!(machine.stop_recording().is_some());
+}
+
+#[test]
+fn test_chip8_state_last_frame(){
+    let mut machine = headless_chip8();
+    machine.load_rom(&[0x12, 0x00]).unwrap();
+    let mut state = machine.save_state();
+
+    // the frame count follows the header, registers, timers, key wait,
+    // stack, screen and keys
+    let frame = 0x5 + 0x8 + 0x2 + 0x1 + 0x10 + 0x20 + 0x100 + 0x10;
+    for byte in &mut state[frame..frame + 0x8] {
+        *byte = 0xFF;
+    }
+    machine.load_state(&state).unwrap();
+    assert_eq!(machine.frame(), u64::MAX);
+    machine.run_frame().unwrap();
+    assert_eq!(machine.frame(), 0x0);
+}
+
+#[test]
+fn test_chip8_seed(){
+    let memory = [
+        0xC0, 0xFF,
+        0x70, 0x00,
+        0x12, 0x00,
+    ];
+
+    let run = |seed| {
+        let mut machine = headless_chip8();
+        machine.seed(seed);
+        machine.load_rom(&memory).unwrap();
+        machine.run_frame().unwrap();
+        machine.save_state()
+    };
+
+    assert_eq!(run(0x1234), run(0x1234));
+    assert!(run(0x1234) != run(0x4321));
+}
+
+#[test]
+fn test_chip8_registers(){
+    let memory = [
+        0x60, 0x2A,
+        0xA3, 0x00,
+        0xF0, 0x18,
+        0x22, 0x0A,
+        0xF0, 0x0A,
+        0x12, 0x0A,
+    ];
+
+    let mut machine = headless_chip8();
+    machine.load_rom(&memory).unwrap();
+    assert_eq!(machine.read_memory(0x201), 0x2A);
+    machine.run_frame().unwrap();
+
+    let registers = machine.registers();
+    assert_eq!(registers.v[0x0], 0x2A);
+    assert_eq!(registers.index, 0x300);
+    assert_eq!(registers.pc, 0x20A);
+    assert_eq!((registers.sp, registers.stack[0x0]), (1, 0x206));
+    assert_eq!(registers.sound_timer, 0x29);
+    // the jump loops for the rest of the frame
+    assert_eq!(machine.instructions(), 10);
+}
+
+/// A trace output the test can still read after the machine takes it.
+#[derive(Clone, Default)]
+struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, data:&[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(data)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_chip8_display_wait_counted_once(){
+    // the second draw waits for the next frame before it runs
+    let memory = [0xD0, 0x01, 0xD0, 0x01, 0x12, 0x04];
+
+    let trace = SharedBuffer::default();
+    let mut machine = headless_chip8();
+    machine.set_speed(4);
+    machine.set_quirks(Quirks::profile("vip").unwrap());
+    machine.set_trace(Some(Box::new(trace.clone())));
+    machine.load_rom(&memory).unwrap();
+
+    machine.run_frame().unwrap();
+    assert_eq!(machine.registers().pc, 0x202);
+    assert_eq!(machine.instructions(), 1);
+    machine.run_frame().unwrap();
+    assert_eq!(machine.registers().pc, 0x204);
+    assert_eq!(machine.instructions(), 5);
+    assert_eq!(String::from_utf8(trace.0.borrow().clone()).unwrap(),
+               "200: D001\n202: D001\n204: 1204\n204: 1204\n204: 1204\n");
+}
+
+#[test]
+fn test_chip8_step(){
+    // counts frames in V1 by waiting on the delay timer
+    let memory = [
+        0x60, 0x01,
+        0xF0, 0x15,
+        0xF0, 0x07,
+        0x30, 0x00,
+        0x12, 0x04,
+        0x71, 0x01,
+        0x12, 0x00,
+    ];
+
+    let mut machine = headless_chip8();
+    machine.set_speed(4);
+    machine.seed(0x0);
+    machine.load_rom(&memory).unwrap();
+    machine.step().unwrap();
+    assert_eq!(machine.registers().pc, 0x202);
+    assert_eq!((machine.frame(), machine.registers().v[0x0]), (0, 0x1));
+
+    // the rest of the frame, then all of the next
+    machine.run_frame().unwrap();
+    assert_eq!(machine.frame(), 1);
+    assert_eq!(machine.registers().pc, 0x208);
+    for _ in 0x0..0x4 {
+        machine.step().unwrap();
+    }
+    assert_eq!(machine.frame(), 2);
+    assert_eq!(machine.registers().v[0x1], 0x1);
+    assert_eq!(machine.instructions(), 8);
+
+    let mut frames = headless_chip8();
+    frames.set_speed(4);
+    frames.seed(0x0);
+    frames.load_rom(&memory).unwrap();
+    frames.run_frame().unwrap();
+    frames.run_frame().unwrap();
+    assert_eq!(frames.save_state(), machine.save_state());
+}
+
+#[test]
+fn test_chip8_key_release(){
+    let run = |input:headless::ScriptedInput| {
+        let mut machine = Chip8::new(
+            headless::NullAudio::default(),
+            headless::FrameBuffer::default(),
+            input);
+        machine.seed(0x0);
+        machine.load_rom(&[0x12, 0x00]).unwrap();
+        machine.run_frames(3).unwrap();
+        machine.save_state()
+    };
+    let idle = run(headless::ScriptedInput::new(&[]));
+
+    // a key stays down after the press until it times out
+    let pressed = run(headless::ScriptedInput::new(&[(0, 0x5)]));
+    assert_ne!(pressed, idle);
+
+    // or until the input device reports it released
+    let mut input = headless::ScriptedInput::new(&[(0, 0x5)]);
+    input.release(2, 0x5);
+    assert_eq!(run(input), idle);
+}
+
+#[test]
+fn test_held_keys(){
+    let keys = headless::HeldKeys::default();
+    let poll = || (keys.get_keys(), keys.get_released(), keys.get_held());
+    keys.set(0x5, true);
+    keys.set(0x10, true);
+    assert_eq!(poll(), (0x0020, 0x0, 0x0020));
+    // held keys are pressed once, then only held, even if set again
+    keys.set(0x5, true);
+    assert_eq!(poll(), (0x0, 0x0, 0x0020));
+    keys.set(0x5, false);
+    assert_eq!(poll(), (0x0, 0x0020, 0x0));
+
+    // a key tapped between polls is pressed, then released on the next
+    keys.set(0x3, true);
+    keys.set(0x3, false);
+    assert_eq!(poll(), (0x0008, 0x0, 0x0));
+    assert_eq!(poll(), (0x0, 0x0008, 0x0));
+    assert_eq!(keys.down(), 0x0);
+}
+
+fn held_keys_chip8(memory:&[u8])
+        -> Chip8<headless::NullAudio, headless::FrameBuffer,
+                 headless::HeldKeys> {
+    let mut machine = Chip8::new(
+        headless::NullAudio::default(),
+        headless::FrameBuffer::default(),
+        headless::HeldKeys::default());
+    machine.load_rom(memory).unwrap();
+    machine
+}
+
+#[test]
+fn test_held_key_waits(){
+    // waits for two keys, then spins
+    let mut machine = held_keys_chip8(&[0xF1, 0x0A, 0xF2, 0x0A, 0x12, 0x04]);
+    // as frontends that report the keys down every frame do
+    for _ in 0..100 {
+        machine.input().set(0x5, true);
+        machine.run_frame().unwrap();
+    }
+    // holding a key is one press, so the second wait goes on
+    assert_eq!(machine.registers().v[0x1], 0x5);
+    assert_eq!(machine.waiting_for_key(), Some(0x2));
+
+    machine.input().set(0x5, false);
+    machine.run_frame().unwrap();
+    machine.input().set(0x5, true);
+    machine.run_frame().unwrap();
+    assert_eq!(machine.registers().v[0x2], 0x5);
+    assert_eq!(machine.waiting_for_key(), None);
+}
+
+#[test]
+fn test_held_key_stays_down(){
+    // counts the instructions that see key 0 down
+    let memory = [0xE0, 0x9E, 0x12, 0x00, 0x71, 0x01, 0x12, 0x00];
+    let mut machine = held_keys_chip8(&memory);
+    machine.input().set(0x0, true);
+    for _ in 0..100 {
+        machine.run_frame().unwrap();
+    }
+    // still down long after the press, though every check saw it
+    let count = machine.registers().v[0x1];
+    machine.run_frame().unwrap();
+    assert!(machine.registers().v[0x1] != count);
+
+    machine.input().set(0x0, false);
+    machine.run_frame().unwrap();
+    let count = machine.registers().v[0x1];
+    machine.run_frame().unwrap();
+    assert_eq!(machine.registers().v[0x1], count);
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn test_ffi_screen_size(){
+    // the header's constants are literals, so keep them in step
+    assert_eq!((ffi::CHIP8_SCREEN_WIDTH, ffi::CHIP8_SCREEN_HEIGHT),
+               (io::SCREEN_WIDTH, io::SCREEN_HEIGHT));
+}
+
+// Disassembler Tests
+////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn test_disassemble(){
+    let cases = [
+        (0x00E0, "cls"),
+        (0x00EE, "ret"),
+        (0x0123, "sys 0x123"),
+        (0x1ABC, "jp 0xABC"),
+        (0x2208, "call 0x208"),
+        (0x3A05, "se va, 0x05"),
+        (0x4B3C, "sne vb, 0x3C"),
+        (0x5120, "se v1, v2"),
+        (0x5121, "db 0x51, 0x21"),
+        (0x6FFF, "ld vf, 0xFF"),
+        (0x7001, "add v0, 0x01"),
+        (0x8010, "ld v0, v1"),
+        (0x8EF6, "shr ve, vf"),
+        (0x801E, "shl v0, v1"),
+        (0x8018, "db 0x80, 0x18"),
+        (0x9340, "sne v3, v4"),
+        (0xA2F0, "ld i, 0x2F0"),
+        (0xB300, "jp v0, 0x300"),
+        (0xC10F, "rnd v1, 0x0F"),
+        (0xD01F, "drw v0, v1, 15"),
+        (0xE59E, "skp v5"),
+        (0xE5A1, "sknp v5"),
+        (0xE500, "db 0xE5, 0x00"),
+        (0xF107, "ld v1, dt"),
+        (0xF20A, "ld v2, k"),
+        (0xF315, "ld dt, v3"),
+        (0xF418, "ld st, v4"),
+        (0xF51E, "add i, v5"),
+        (0xF629, "ld f, v6"),
+        (0xF733, "ld b, v7"),
+        (0xF855, "ld [i], v8"),
+        (0xF965, "ld v9, [i]"),
+        (0xFF00, "db 0xFF, 0x00"),
+    ];
+    for &(opcode, text) in &cases {
+        assert_eq!(disasm::disassemble(opcode), text, "{:04X}", opcode);
+    }
+}
+
+// SHA-1 Tests
+////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn test_sha1_vectors(){
+    assert_eq!(sha1::hex_digest(b""),
+               "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    assert_eq!(sha1::hex_digest(b"abc"),
+               "a9993e364706816aba3e25717850c26c9cd0d89d");
+    assert_eq!(sha1::hex_digest(
+                   b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+               "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+    assert_eq!(sha1::hex_digest(&[0x61;1000000]),
+               "34aa973cd4c4daa4f61eeb2bdbad27316534016f");
+}
+
+// ROM Database Tests
+////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn test_romdb_builtin_parses(){
+    let _ = romdb::RomDatabase::builtin();
+}
+
+#[test]
+fn test_romdb_builtin_entry(){
+    let database = romdb::RomDatabase::builtin();
+    // IBM Logo.ch8, the ROM most emulators are first tested on
+    let info = database.get("1ba58656810b67fd131eb9af3e3987863bf26c90")
+        .unwrap();
+    assert_eq!(info.title, Some("IBM Logo".to_string()));
+    assert_eq!(info.platform(), romdb::Platform::Chip8);
+    // from the platform, as the entry names no quirks
+    assert_eq!(info.quirks, None);
+    assert_eq!(info.profile(), "vip");
+    assert_eq!(info.quirks(), Quirks::profile("vip").unwrap());
+    assert_eq!(info.speed, Some(15));
+
+    let info = database.get("ea9af3c09b0d9e265fcd92bcc5d51a2939fdf27a")
+        .unwrap();
+    assert_eq!(info.author, Some("Roger Ivie".to_string()));
+    assert_eq!(info.keymap, Some("keypad".to_string()));
+}
+
+#[test]
+fn test_romdb_lookup_and_merge(){
+    let rom = [0x12, 0x00];
+    let hash = sha1::hex_digest(&rom);
+
+    let mut database = romdb::RomDatabase::parse(&format!("\
+        # test entries\n\
+        [{}]\n\
+        title = Spin\n\
+        author = Nobody\n\
+        platform = schip\n\
+        speed = 30\n", hash.to_uppercase())).unwrap();
+
+    {
+        let info = database.lookup(&rom).unwrap();
+        assert_eq!(info.title, Some("Spin".to_string()));
+        assert_eq!(info.platform(), romdb::Platform::SuperChip);
+        assert_eq!(info.profile(), "schip");
+        assert_eq!(info.quirks(), Quirks::profile("schip").unwrap());
+    }
+
+    let overrides = romdb::RomDatabase::parse(&format!("\
+        [{}]\n\
+        quirks = modern\n\
+        [{}]\n\
+        title = Other\n", hash, "f".repeat(40))).unwrap();
+    database.merge(&overrides);
+
+    assert_eq!(database.len(), 2);
+    let info = database.get(&hash).unwrap();
+    assert_eq!(info.title, Some("Spin".to_string()));
+    assert_eq!(info.speed, Some(30));
+    assert_eq!(info.profile(), "modern");
+    assert!(database.lookup(&[0x00, 0xE0]).is_none());
+
+    let mut machine = headless_chip8();
+    machine.load_rom(&rom).unwrap();
+    assert_eq!(machine.rom_hash(), hash);
+}
+
+#[test]
+fn test_romdb_parse_errors(){
+    for text in &[
+        "title = Orphan\n",
+        "[pong]\n",
+        "[da39a3ee5e6b4b0d3255bfef95601890afd80709]\nplatform = nes\n",
+        "[da39a3ee5e6b4b0d3255bfef95601890afd80709]\nquirks = cosmac\n",
+        "[da39a3ee5e6b4b0d3255bfef95601890afd80709]\nspeed = fast\n",
+        "[da39a3ee5e6b4b0d3255bfef95601890afd80709]\ncolor = red\n",
+    ] {
+        assert!(romdb::RomDatabase::parse(text).is_err(), "{}", text);
+    }
+}
+
+#[test]
+fn test_ini_parse(){
+    let mut lines = vec![];
+    ini::parse("\
+        # comment\n\
+        ; comment\n\
+        speed = 12\n\
+        \n\
+        [ DA39A3EE5E6B4B0D3255BFEF95601890AFD80709 ]\n\
+        title = a = b\n", |line| {
+        lines.push(line);
+        Ok(())
+    }).unwrap();
+    assert_eq!(lines, vec![
+        ini::Line::Field("speed", "12"),
+        ini::Line::Section(
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709".to_string()),
+        ini::Line::Field("title", "a = b"),
+    ]);
+
+    // hashes must be the full 40 hex digits, and fields need a value
+    for text in &[
+        "[da39a3ee]\n",
+        "[da39a3ee5e6b4b0d3255bfef95601890afd8070g]\n",
+        "\nspeed\n",
+    ] {
+        let err = ini::parse(text, |_| Ok(())).unwrap_err();
+        assert_eq!(err.line, text.trim_end().lines().count());
+    }
+    let err = ini::parse("a = 1\nb = 2\n", |line| match line {
+        ini::Line::Field("b", _) => Err("no b".to_string()),
+        _ => Ok(()),
+    }).unwrap_err();
+    assert_eq!(err, ini::ParseError{line:2, message:"no b".to_string()});
+}
+
+#[test]
+fn test_chip8_load_rom_errors(){
+    let mut machine = headless_chip8();
+    let max = memory::RAM_SIZE - 0x200;
+
+    assert_eq!(machine.load_rom(&[]), Err(LoadError::Empty));
+    assert_eq!(machine.load_rom(&vec![0x0;max + 1]),
+               Err(LoadError::TooLarge{size:max + 1, max}));
+    assert_eq!(machine.load_rom(&vec![0x0;max]), Ok(vec![]));
+    assert_eq!(machine.load_rom(&[0x12, 0x00, 0x00]),
+               Ok(vec![LoadWarning::OddLength(0x3)]));
+
+    assert_eq!(machine.load_rom_at(0x600, &vec![0x0;max]),
+               Err(LoadError::TooLarge{size:max, max:max - 0x400}));
+    assert_eq!(machine.load_rom_at(0x100, &[0x12, 0x00]),
+               Err(LoadError::BadAddress(0x100)));
+    assert_eq!(machine.load_rom_at(0x1000, &[0x12, 0x00]),
+               Err(LoadError::BadAddress(0x1000)));
+}
+
+#[test]
+fn test_chip8_load_rom_at(){
+    // draws the digit 6 from 0x600, then spins
+    let memory = [
+        0x60, 0x06,
+        0xF0, 0x29,
+        0xD1, 0x15,
+        0x16, 0x06,
+    ];
+
+    let mut machine = headless_chip8();
+    machine.load_rom(&[0xFF;0x800]).unwrap();
+    machine.load_rom_at(0x600, &memory).unwrap();
+    machine.run_frame().unwrap();
+
+    assert!(machine.display().to_ascii().starts_with("####...."));
+
+    // everything the larger ROM wrote outside the new one was cleared
+    let state = machine.save_state();
+    let ram = &state[state.len() - memory::RAM_SIZE..];
+    assert!(ram[0x200..0x600].iter().all(|&byte| byte == 0x0));
+    assert_eq!(&ram[0x600..0x608], &memory);
+    assert!(ram[0x608..].iter().all(|&byte| byte == 0x0));
+}
+
+#[test]
+fn test_chip8_reset(){
+    // draws a 0 and stores V0 over the first instruction, then spins
+    let memory = [
+        0x60, 0x00,
+        0xF0, 0x29,
+        0xD0, 0x05,
+        0x60, 0xAB,
+        0xA2, 0x00,
+        0xF0, 0x55,
+        0x12, 0x0C,
+    ];
+
+    for &reset in &[Reset::Soft, Reset::Hard] {
+        let mut machine = headless_chip8();
+        machine.load_rom(&memory).unwrap();
+        machine.run_frame().unwrap();
+        assert!(machine.display().to_ascii().starts_with("####"));
+
+        let before = machine.save_state();
+        assert_eq!(before[STATE_V0], 0xAB);
+
+        machine.reset(reset);
+        let after = machine.save_state();
+        assert_eq!(after[STATE_V0], 0x00);
+        assert_eq!(after[STATE_DELAY_TIMER], 0x00);
+
+        // a soft reset keeps the program's write over its first opcode
+        let ram = &after[after.len() - memory::RAM_SIZE..];
+        match reset {
+            Reset::Soft => assert_eq!(ram[0x200], 0xAB),
+            Reset::Hard => assert_eq!(&ram[0x200..0x20E], &memory),
+        }
+
+        machine.set_speed(0);
+        machine.run_frame().unwrap();
+        assert!(!machine.display().to_ascii().contains('#'));
+    }
+}
+
+// Random Number Tests
+////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn test_xorshift_deterministic(){
+    let mut a = random::XorShift::new(0x0);
+    let mut b = random::XorShift::new(0x0);
+    let mut c = random::XorShift::new(0x1);
+
+    let a:Vec<u8> = (0..0x100).map(|_| a.next_byte()).collect();
+    let b:Vec<u8> = (0..0x100).map(|_| b.next_byte()).collect();
+    let c:Vec<u8> = (0..0x100).map(|_| c.next_byte()).collect();
+    assert_eq!(a, b);
+    assert!(a != c);
+
+    // every byte value turns up in a short run
+    let mut rng = random::XorShift::new(0x2A);
+    let mut seen = [false;0x100];
+    for _ in 0..0x2000 {
+        seen[rng.next_byte() as usize] = true;
+    }
+    assert!(seen.iter().all(|&seen| seen));
+}
+
+#[test]
+fn test_xorshift_save_restore(){
+    let mut rng = random::XorShift::new(0x1234);
+    rng.next_byte();
+    let state = rng.save();
+
+    let mut other = random::XorShift::new(0x0);
+    assert!(other.restore(&state));
+    for _ in 0..0x10 {
+        assert_eq!(rng.next_byte(), other.next_byte());
+    }
+
+    assert!(!other.restore(&[0x0;8]));
+    assert!(!other.restore(&[0x1;4]));
+}
+
+#[test]
+fn test_chip8_state_includes_rng(){
+    let memory = [
+        0xC0, 0xFF,
+        0xA3, 0x00,
+        0xF0, 0x55,
+        0x12, 0x00,
+    ];
+
+    let mut machine = headless_chip8();
+    machine.seed(0x99);
+    machine.load_rom(&memory).unwrap();
+    machine.run_frame().unwrap();
+    let state = machine.save_state();
+
+    let mut restored = headless_chip8();
+    restored.load_state(&state).unwrap();
+
+    machine.run_frame().unwrap();
+    restored.run_frame().unwrap();
+    assert_eq!(machine.save_state(), restored.save_state());
+}
+
+// Blending Tests
+////////////////////////////////////////////////////////////////////////
+
+/// Display that keeps the brightness levels it's given.
+struct LevelDisplay {
+    levels:Vec<u8>,
+    refreshes:usize,
+}
+
+impl Default for LevelDisplay {
+    fn default() -> LevelDisplay {
+        LevelDisplay{levels:vec![0x0;SCREEN_SIZE], refreshes:0}
+    }
+}
+
+impl Display for LevelDisplay {
+    fn set(&mut self, row:usize, col:usize, state:io::Pixel) -> Result<(),()>{
+        let level = if state == io::Pixel::On {io::MAX_LEVEL} else {0x0};
+        self.set_level(row, col, level)
+    }
+    fn set_level(&mut self, row:usize, col:usize, level:u8) -> Result<(),()>{
+        self.levels[row*io::SCREEN_WIDTH + col] = level;
+        Ok(())
+    }
+    fn refresh(&mut self){
+        self.refreshes += 1;
+    }
+}
+
+/// Draws frames with only the pixel at (`col`, 0) lit, one per entry,
+/// returning the level of pixel (0, 0) shown after each.
+fn blend_frames(blender:&mut blend::Blender<LevelDisplay>, lit:&[usize])
+        -> Vec<u8> {
+    lit.iter().map(|&lit| {
+        for col in 0x0..io::SCREEN_WIDTH {
+            let pixel = if col == lit {io::Pixel::On} else {io::Pixel::Off};
+            blender.set(0, col, pixel).unwrap();
+        }
+        blender.refresh();
+        blender.end_frame();
+        blender.inner().levels[0]
+    }).collect()
+}
+
+#[test]
+fn test_blend_none(){
+    let mut blender = blend::Blender::new(LevelDisplay::default(),
+                                          blend::Blend::None);
+    assert_eq!(blend_frames(&mut blender, &[0, 1, 0, 1]), [0xFF, 0, 0xFF, 0]);
+    assert_eq!(blender.inner().refreshes, 4);
+}
+
+#[test]
+fn test_blend_or(){
+    let mut blender = blend::Blender::new(LevelDisplay::default(),
+                                          blend::Blend::Or(3));
+    // a pixel flickering on and off stays lit, then goes out three
+    // frames after it was last drawn
+    assert_eq!(blend_frames(&mut blender, &[0, 1, 0, 1, 1, 1, 1]),
+               [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0]);
+    // only frames that change are sent: the first, pixel 1 lighting and
+    // pixel 0 going out
+    assert_eq!(blender.inner().refreshes, 3);
+}
+
+#[test]
+fn test_blend_decay(){
+    let mut blender = blend::Blender::new(LevelDisplay::default(),
+                                          blend::Blend::Decay(50));
+    assert_eq!(blend_frames(&mut blender, &[0, 1, 1, 0, 1]),
+               [0xFF, 0x7F, 0x3F, 0xFF, 0x7F]);
+    // a display that only shows on and off sees the pixel go out as soon
+    // as it fades below half
+    let mut blender = blend::Blender::new(headless::FrameBuffer::default(),
+                                          blend::Blend::Decay(25));
+    let mut shown = Vec::new();
+    for frame in 0..4 {
+        blender.set(0, 0, if frame == 0 {io::Pixel::On} else {io::Pixel::Off})
+            .unwrap();
+        blender.end_frame();
+        shown.push(blender.inner().get(0, 0));
+    }
+    assert_eq!(shown, [io::Pixel::On, io::Pixel::On, io::Pixel::On,
+                       io::Pixel::Off]);
+}
+
+#[test]
+fn test_blend_parse(){
+    for &(text, mode) in &[("none", blend::Blend::None),
+                           ("or:2", blend::Blend::Or(2)),
+                           ("decay:30", blend::Blend::Decay(30))] {
+        assert_eq!(blend::Blend::parse(text), Ok(mode));
+        assert_eq!(mode.to_string(), text);
+    }
+    for text in &["or", "or:0", "or:16", "decay:101", "none:1", "fade"] {
+        assert!(blend::Blend::parse(text).is_err(), "{}", text);
+    }
+}
+
+// Movie Tests
+////////////////////////////////////////////////////////////////////////
+
+// waits for a key, then draws its digit at a random position
+const MOVIE_ROM:[u8;10] = [
+    0xF0, 0x0A,
+    0xC1, 0xFF,
+    0xF0, 0x29,
+    0xD1, 0x15,
+    0x12, 0x00,
+];
+
+fn record_movie() -> (movie::Movie, Vec<u8>) {
+    let keys = [(3, 0x5), (70, 0xA), (71, 0xA), (71, 0x3), (150, 0xF)];
+    let mut machine = Chip8::new(
+        headless::NullAudio::default(),
+        headless::FrameBuffer::default(),
+        headless::ScriptedInput::new(&keys));
+    machine.load_rom(&MOVIE_ROM).unwrap();
+    machine.set_speed(0x7);
+    machine.set_quirks(Quirks::profile("vip").unwrap());
+    machine.start_recording(0x2A);
+    for _ in 0..200 {
+        machine.run_frame().unwrap();
+    }
+    (machine.stop_recording().unwrap(), machine.save_state())
+}
+
+#[test]
+fn test_movie_record(){
+    let (movie, _) = record_movie();
+    assert_eq!(movie.rom_hash, sha1::hex_digest(&MOVIE_ROM));
+    assert_eq!((movie.seed, movie.speed, movie.frames), (0x2A, 0x7, 200));
+    assert_eq!(movie.quirks, Quirks::profile("vip").unwrap());
+    // keys pressed on the same frame are recorded in key order
+    assert_eq!(movie.events, vec![
+        movie::KeyEvent{frame:3, key:0x5, held:false, released:false},
+        movie::KeyEvent{frame:70, key:0xA, held:false, released:false},
+        movie::KeyEvent{frame:71, key:0x3, held:false, released:false},
+        movie::KeyEvent{frame:71, key:0xA, held:false, released:false},
+        movie::KeyEvent{frame:150, key:0xF, held:false, released:false},
+    ]);
+    let frames:Vec<u64> = movie.checks.iter().map(|check| check.frame)
+        .collect();
+    assert_eq!(frames, vec![60, 120, 180]);
+}
+
+#[test]
+fn test_movie_replay(){
+    let (movie, state) = record_movie();
+
+    let mut machine = Chip8::new(
+        headless::NullAudio::default(),
+        headless::FrameBuffer::default(),
+        movie::Player::new(&movie, headless::NoInput::default()));
+    machine.load_rom(&MOVIE_ROM).unwrap();
+    machine.play(&movie).unwrap();
+    while machine.playing() {
+        machine.run_frame().unwrap();
+    }
+    assert_eq!(machine.frame(), 200);
+    assert_eq!(machine.desync(), None);
+    assert_eq!(machine.save_state(), state);
+
+    // replaying again from the start gives the same run
+    machine.play(&movie).unwrap();
+    machine.input().rewind();
+    while machine.playing() {
+        machine.run_frame().unwrap();
+    }
+    assert_eq!(machine.save_state(), state);
+}
+
+#[test]
+fn test_movie_desync(){
+    let (mut movie, _) = record_movie();
+    movie.events.retain(|event| event.key != 0xA);
+
+    let mut machine = Chip8::new(
+        headless::NullAudio::default(),
+        headless::FrameBuffer::default(),
+        movie::Player::new(&movie, headless::NoInput::default()));
+    machine.load_rom(&MOVIE_ROM).unwrap();
+    machine.play(&movie).unwrap();
+    while machine.playing() {
+        machine.run_frame().unwrap();
+    }
+    assert_eq!(machine.desync(), Some(120));
+}
+
+#[test]
+fn test_movie_wrong_rom(){
+    let (movie, _) = record_movie();
+    let mut machine = headless_chip8();
+    machine.load_rom(&[0x12, 0x00]).unwrap();
+    match machine.play(&movie) {
+        Err(movie::MovieError::WrongRom{..}) => (),
+        result => panic!("expected WrongRom, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_movie_releases(){
+    let mut input = headless::ScriptedInput::new(&[(3, 0x5)]);
+    input.release(5, 0x5);
+    let mut machine = Chip8::new(
+        headless::NullAudio::default(),
+        headless::FrameBuffer::default(),
+        input);
+    machine.load_rom(&MOVIE_ROM).unwrap();
+    machine.start_recording(0x2A);
+    for _ in 0..10 {
+        machine.run_frame().unwrap();
+    }
+    let (movie, state) = (machine.stop_recording().unwrap(),
+                          machine.save_state());
+    assert_eq!(movie.events[1],
+               movie::KeyEvent{frame:5, key:0x5, held:false, released:true});
+    assert!(movie.to_string().contains("\nrelease 5 5\n"));
+
+    let mut machine = Chip8::new(
+        headless::NullAudio::default(),
+        headless::FrameBuffer::default(),
+        movie::Player::new(&movie, headless::NoInput::default()));
+    machine.load_rom(&MOVIE_ROM).unwrap();
+    machine.play(&movie).unwrap();
+    while machine.playing() {
+        machine.run_frame().unwrap();
+    }
+    assert_eq!(machine.save_state(), state);
+}
+
+#[test]
+fn test_movie_holds(){
+    // counts the instructions that see key 0 down
+    let memory = [0xE0, 0x9E, 0x12, 0x00, 0x71, 0x01, 0x12, 0x00];
+    let mut machine = held_keys_chip8(&memory);
+    machine.start_recording(0x2A);
+    machine.input().set(0x0, true);
+    for _ in 0..130 {
+        machine.run_frame().unwrap();
+    }
+    machine.input().set(0x0, false);
+    for _ in 0..10 {
+        machine.run_frame().unwrap();
+    }
+    let (movie, state) = (machine.stop_recording().unwrap(),
+                          machine.save_state());
+    assert_eq!(movie.events, vec![
+        movie::KeyEvent{frame:0, key:0x0, held:true, released:false},
+        movie::KeyEvent{frame:130, key:0x0, held:false, released:true},
+    ]);
+    assert!(movie.to_string().contains("\nhold 0 0\n"));
+    let movie = movie::Movie::parse(&movie.to_string()).unwrap();
+
+    let mut machine = Chip8::new(
+        headless::NullAudio::default(),
+        headless::FrameBuffer::default(),
+        movie::Player::new(&movie, headless::NoInput::default()));
+    machine.load_rom(&memory).unwrap();
+    machine.play(&movie).unwrap();
+    while machine.playing() {
+        machine.run_frame().unwrap();
+    }
+    assert_eq!(machine.desync(), None);
+    assert_eq!(machine.save_state(), state);
+}
+
+#[test]
+fn test_movie_stopped_by_settings(){
+    let (movie, _) = record_movie();
+    let changes:[&dyn Fn(&mut Chip8<_, _, _>);3] = [
+        &|machine| machine.set_speed(0x8),
+        &|machine| machine.set_quirks(Quirks::profile("modern").unwrap()),
+        &|machine| machine.seed(0x2A),
+    ];
+    for change in &changes {
+        let mut machine = Chip8::new(
+            headless::NullAudio::default(),
+            headless::FrameBuffer::default(),
+            movie::Player::new(&movie, headless::NoInput::default()));
+        machine.load_rom(&MOVIE_ROM).unwrap();
+        machine.set_speed(0x7);
+        machine.set_quirks(Quirks::profile("vip").unwrap());
+
+        // setting what's already set changes nothing
+        machine.start_recording(0x2A);
+        machine.set_speed(0x7);
+        machine.set_quirks(Quirks::profile("vip").unwrap());
+        change(&mut machine);
+        assert!(machine.stop_recording().is_none());
+
+        machine.play(&movie).unwrap();
+        machine.run_frame().unwrap();
+        change(&mut machine);
+        assert!(!machine.playing());
+    }
+}
+
+#[test]
+fn test_movie_text_round_trip(){
+    let (movie, _) = record_movie();
+    assert_eq!(movie::Movie::parse(&movie.to_string()), Ok(movie));
+}
+
+#[test]
+fn test_movie_parse_errors(){
+    let header = "chip-8 movie 1\nrom 00\nseed 1\nquirks\nspeed 10\n\
+                  start 0x200\nframes 5\n";
+    assert!(movie::Movie::parse(header).is_ok());
+    assert!(movie::Movie::parse("chip-8 movie 2\n").is_err());
+    assert!(movie::Movie::parse(&header.replace("seed 1\n", "")).is_err());
+    assert!(movie::Movie::parse(&format!("{}key 1 10\n", header)).is_err());
+    assert!(movie::Movie::parse(&format!("{}key x 1\n", header)).is_err());
+    assert!(movie::Movie::parse(&header.replace("quirks", "quirks fast"))
+            .is_err());
+}
+
+// Snapshot Tests
+////////////////////////////////////////////////////////////////////////
+
+fn snapshot_dir(name:&str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("chip-8-snapshot-{}-{}",
+                                                name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_snapshot_formats(){
+    let mut machine = headless_chip8();
+    machine.load_rom(&[0x60, 0x0A, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06])
+        .unwrap();
+    machine.run_frame().unwrap();
+    let screen = machine.display();
+
+    let ascii = snapshot::parse(screen.to_ascii().as_bytes()).unwrap();
+    assert_eq!(&ascii[..], screen.pixels());
+    let pbm = snapshot::parse(screen.to_pbm().as_bytes()).unwrap();
+    assert_eq!(&pbm[..], screen.pixels());
+
+    let mut raw = b"P4\n# a comment\n64 32\n".to_vec();
+    for row in screen.pixels().chunks(8) {
+        raw.push(row.iter().fold(0x0, |byte, &pixel| {
+            byte << 1 | if pixel == io::Pixel::On {0x1} else {0x0}
+        }));
+    }
+    let pbm = snapshot::parse(&raw).unwrap();
+    assert_eq!(&pbm[..], screen.pixels());
+
+    assert!(snapshot::parse(b"P1\n32 32\n").is_err());
+    assert!(snapshot::parse(b"P1\n64 32\n0101").is_err());
+    assert!(snapshot::parse(b"########\n").is_err());
+    assert!(snapshot::parse(screen.to_ascii().replace('#', "x").as_bytes())
+            .is_err());
+}
+
+#[test]
+fn test_snapshot_run(){
+    let dir = snapshot_dir("run");
+    let (first, second) = (dir.join("first.txt"), dir.join("second.pbm"));
+
+    // shows the digit of each key pressed at (key, key)
+    let rom = [0xF0, 0x0A, 0x00, 0xE0, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x00];
+    let mut test = snapshot::SnapshotTest::new(&rom);
+    test.press(2, 0x1);
+    test.press(5, 0x7);
+    test.snapshot(4, &first);
+    test.snapshot(8, &second);
+
+    test.set_update(false);
+    match test.run() {
+        Err(snapshot::SnapshotError::Missing(ref path)) if *path == first => (),
+        result => panic!("expected a missing snapshot, got {:?}", result),
+    }
+
+    test.set_update(true);
+    test.run().unwrap();
+    assert!(std::fs::read_to_string(&second).unwrap().starts_with("P1\n"));
+    test.set_update(false);
+    test.run().unwrap();
+
+    // a 7 rather than a 1 changes the first snapshot
+    let mut other = snapshot::SnapshotTest::new(&rom);
+    other.set_update(false);
+    other.press(2, 0x7);
+    other.snapshot(4, &first);
+    match other.run() {
+        Err(snapshot::SnapshotError::Mismatch{frame:4, ref diff, ..}) =>
+            assert!(diff.contains("row  1") && diff.contains("row  7")),
+        result => panic!("expected a mismatch, got {:?}", result),
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// Random ROMs
+////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn test_random_roms_never_panic(){
+    let mut rng = test_rng();
+    for _ in 0..200 {
+        let mut rom = vec![0; rng.gen_range(1, 0x200)];
+        rng.fill_bytes(&mut rom);
+        let keys:Vec<(u64, u8)> = (0..8)
+            .map(|_| (rng.gen_range(0, 60), rng.gen::<u8>()))
+            .collect();
+
+        let mut machine = Chip8::new(
+            headless::NullAudio::default(),
+            headless::FrameBuffer::default(),
+            headless::ScriptedInput::new(&keys),
+        );
+        let mut quirks = Quirks::default();
+        for name in quirks::NAMES.iter() {
+            quirks.set(name, rng.gen());
+        }
+        machine.set_quirks(quirks);
+        machine.seed(rng.gen());
+        machine.load_rom(&rom).unwrap();
+
+        // an error ends the run with pc left on the failing instruction
+        for _ in 0..60 {
+            if let Err(err) = machine.run_frame() {
+                assert_eq!(machine.run_frame(), Err(err));
+                break;
+            }
+        }
+
+        // garbage states are rejected, or load as some valid machine
+        let mut state = machine.save_state();
+        for _ in 0..8 {
+            let at = rng.gen_range(0, state.len());
+            state[at] = rng.gen();
+        }
+        state.truncate(rng.gen_range(0, state.len() + 1));
+        if machine.load_state(&state).is_ok() {
+            for _ in 0..10 {
+                if machine.run_frame().is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Differential Tests
+////////////////////////////////////////////////////////////////////////
+
+/// Instructions the differential test builds programs from, as the fixed
+/// bits and a mask of the bits filled in at random. Jumps and calls stay
+/// near the program so it runs for a while, and the last entry is any
+/// opcode at all.
+const FAMILIES:[(u16, u16);0x24] = [
+    (0x00E0, 0x0000), (0x00EE, 0x0000), (0x0000, 0x0FFF), (0x1200, 0x003E),
+    (0x2200, 0x003E), (0x3000, 0x0FFF), (0x4000, 0x0FFF), (0x5000, 0x0FF0),
+    (0x6000, 0x0FFF), (0x7000, 0x0FFF), (0x8000, 0x0FF0), (0x8001, 0x0FF0),
+    (0x8002, 0x0FF0), (0x8003, 0x0FF0), (0x8004, 0x0FF0), (0x8005, 0x0FF0),
+    (0x8006, 0x0FF0), (0x8007, 0x0FF0), (0x800E, 0x0FF0), (0x9000, 0x0FF0),
+    (0xA000, 0x0FFF), (0xB200, 0x0F3E), (0xC000, 0x0FFF), (0xD000, 0x0FFF),
+    (0xE09E, 0x0F00), (0xE0A1, 0x0F00), (0xF007, 0x0F00), (0xF00A, 0x0F00),
+    (0xF015, 0x0F00), (0xF018, 0x0F00), (0xF01E, 0x0F00), (0xF029, 0x0F00),
+    (0xF033, 0x0F00), (0xF055, 0x0F00), (0xF065, 0x0F00), (0x0000, 0xFFFF),
+];
+
+/// Instructions run per program and quirk profile.
+const DIFFERENTIAL_STEPS:usize = 64;
+
+fn differential_program(ops:&[(usize, u16)]) -> Vec<u8> {
+    let mut program = Vec::new();
+    for &(family, bits) in ops {
+        let (fixed, mask) = FAMILIES[family];
+        let op = fixed | (bits & mask);
+        program.push((op >> 0x8) as u8);
+        program.push(op as u8);
+    }
+    program
+}
+
+fn listing(program:&[u8]) -> String {
+    program.chunks(2).enumerate()
+        .map(|(i, op)| {
+            let opcode = (op[0] as u16) << 0x8 | op[1] as u16;
+            format!("  {:03X}: {:04X}  {}\n", 0x200 + 2*i, opcode,
+                    disasm::disassemble(opcode))
+        })
+        .collect()
+}
+
+/// Reads a processor's state back as a reference machine.
+fn observe(processor:&processor::Processor,
+           bus:&bus::Bus<MockAudio, MockDisplay, MockInput>,
+           quirks:Quirks) -> reference::Machine {
+    let mut state = Writer::default();
+    processor.save_state(&mut state);
+    let state = state.finish();
+    let memory = (0..memory::RAM_SIZE as u16)
+        .map(|address| bus.memory.read_memory(address))
+        .collect();
+    let mut reader = Reader::new(&state).unwrap();
+    reference::Machine::load_processor(&mut reader, memory, quirks).unwrap()
+}
+
+/// Runs a processor alongside the reference interpreter from `start`,
+/// describing the first instruction after which they differ.
+fn run_differential(start:&reference::Machine) -> Result<(), String> {
+    let mut expected = start.clone();
+    let mut bus = new_mock_bus();
+    for (address, &byte) in start.memory.iter().enumerate() {
+        bus.memory.write_memory(address as u16, byte);
+    }
+    let mut processor = processor::Processor::default();
+    processor.set_quirks(start.quirks);
+    let mut state = Writer::default();
+    start.save_processor(&mut state);
+    let state = state.finish();
+    processor.load_state(&mut Reader::new(&state).unwrap()).unwrap();
+
+    for step in 0..DIFFERENTIAL_STEPS {
+        let (pc, opcode) = (expected.pc, expected.opcode());
+        let want = expected.step();
+        let got = processor.step(&mut bus);
+
+        let mut problems = Vec::new();
+        if want != got {
+            problems.push(format!("expected {:?}, found {:?}", want, got));
+        }
+        problems.extend(expected.differences(&observe(&processor, &bus,
+                                                      start.quirks)));
+        if !problems.is_empty() {
+            return Err(format!("step {}, {:04X} at {:03X}:\n  {}", step,
+                               opcode, pc, problems.join("\n  ")));
+        }
+        if want.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn test_differential(
+            ops in prop::collection::vec((0..FAMILIES.len(), any::<u16>()),
+                                         1..32),
+            v in any::<[u8;0x10]>(),
+            index in any::<u16>(),
+            keys in any::<u16>(),
+            delay in any::<u8>(),
+            seed in any::<u64>()){
+        let program = differential_program(&ops);
+        for name in quirks::PROFILES.iter() {
+            let mut start = reference::Machine::new(
+                &program, Quirks::profile(name).unwrap(), seed);
+            start.v = v;
+            start.i = index;
+            start.delay = delay;
+            for key in 0x0..0x10 {
+                start.keys[key] = keys & (0x1 << key) != 0;
+            }
+
+            if let Err(report) = run_differential(&start) {
+                prop_assert!(false, "under {} quirks, {}\nprogram:\n{}", name,
+                             report, listing(&program));
+            }
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::fmt;
+
+use chip_8::io::MAX_LEVEL;
+
+// Colors
+////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    /// Parses a color written `#rrggbb`.
+    pub fn parse(text:&str) -> Result<Rgb, String> {
+        let digits = match text.strip_prefix('#') {
+            Some(digits) if digits.len() == 6 && digits.is_ascii() => digits,
+            _ => return Err(format!("color '{}' is not #rrggbb", text)),
+        };
+        let channel = |at:usize| u8::from_str_radix(&digits[at..at + 2], 16)
+            .map_err(|_| format!("color '{}' is not #rrggbb", text));
+        Ok(Rgb(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    /// The color `level` of the way from this one to `other`, `MAX_LEVEL`
+    /// being all the way.
+    pub fn mix(self, other:Rgb, level:u8) -> Rgb {
+        let mix = |from:u8, to:u8| {
+            let (from, to, level) = (from as u32, to as u32, level as u32);
+            let max = MAX_LEVEL as u32;
+            ((from*(max - level) + to*level + max/2)/max) as u8
+        };
+        Rgb(mix(self.0, other.0), mix(self.1, other.1), mix(self.2, other.2))
+    }
+
+    /// The nearest color in the 256-color xterm palette: the 6x6x6 cube
+    /// from 16 and the gray ramp from 232.
+    pub fn xterm256(self) -> i16 {
+        const STEPS:[u8;0x6] = [0x00, 0x5F, 0x87, 0xAF, 0xD7, 0xFF];
+        let step = |value:u8| (0..STEPS.len())
+            .min_by_key(|&i| (STEPS[i] as i32 - value as i32).abs())
+            .unwrap();
+        let cube = Rgb(STEPS[step(self.0)], STEPS[step(self.1)],
+                       STEPS[step(self.2)]);
+        let cube_index = 16 + 36*step(self.0) + 6*step(self.1) + step(self.2);
+
+        let average = (self.0 as u32 + self.1 as u32 + self.2 as u32)/3;
+        let gray_step = (average.saturating_sub(3)/10).min(23) as u8;
+        let value = 8 + 10*gray_step;
+        let gray = Rgb(value, value, value);
+
+        match self.distance(gray) < self.distance(cube) {
+            true => 232 + gray_step as i16,
+            false => cube_index as i16,
+        }
+    }
+
+    /// The nearest of the eight basic terminal colors, numbered the way
+    /// curses numbers them.
+    pub fn basic(self) -> i16 {
+        (0..8)
+            .min_by_key(|&color| {
+                let value = |bit:i16| if color & bit != 0 {0xFF} else {0x0};
+                self.distance(Rgb(value(1), value(2), value(4)))
+            })
+            .unwrap()
+    }
+
+    fn distance(self, other:Rgb) -> u32 {
+        let square = |a:u8, b:u8| (a as i32 - b as i32).pow(2) as u32;
+        square(self.0, other.0) + square(self.1, other.1)
+            + square(self.2, other.2)
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+// Themes
+////////////////////////////////////////////////////////////////////////
+
+/// Shades a pixel is drawn in: off, then fainter to brighter, for the
+/// levels blending leaves.
+pub const SHADES:usize = 0x4;
+
+/// Named themes, as off and on colors.
+pub const THEMES:[(&str, [Rgb;0x2]);0x3] = [
+    ("green", [Rgb(0x00, 0x14, 0x00), Rgb(0x33, 0xFF, 0x33)]),
+    ("amber", [Rgb(0x14, 0x0A, 0x00), Rgb(0xFF, 0xB0, 0x00)]),
+    ("lcd", [Rgb(0x9B, 0xBC, 0x0F), Rgb(0x0F, 0x38, 0x0F)]),
+];
+
+/// Colors to draw the screen in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    name:Option<&'static str>,
+    palette:[Rgb;0x2],
+}
+
+impl Theme {
+    /// Parses a theme name, or two `#rrggbb` colors separated by a comma,
+    /// for off and on. The interpreter draws only one bitplane, so there
+    /// are no colors for XO-CHIP's second.
+    pub fn parse(text:&str) -> Result<Theme, String> {
+        if let Some(&(name, palette)) = THEMES.iter()
+                .find(|&&(name, _)| name == text) {
+            return Ok(Theme{name:Some(name), palette});
+        }
+        if !text.starts_with('#') {
+            let names:Vec<&str> = THEMES.iter().map(|&(name, _)| name)
+                .collect();
+            return Err(format!("unknown theme '{}' (expected one of: {}, \
+                                or colors like #000000,#ffffff)",
+                               text, names.join(", ")));
+        }
+
+        let colors = text.split(',').map(|color| Rgb::parse(color.trim()))
+            .collect::<Result<Vec<Rgb>, String>>()?;
+        let palette = match colors[..] {
+            [off, on] => [off, on],
+            _ => return Err(format!("theme '{}' needs two colors, off and \
+                                     on", text)),
+        };
+        Ok(Theme{name:None, palette})
+    }
+
+    /// The color of unlit pixels, for 0, or lit ones, for 1.
+    pub fn color(&self, lit:usize) -> Rgb {
+        self.palette[lit]
+    }
+
+    /// Swaps the off and on colors.
+    pub fn invert(&mut self){
+        self.palette.swap(0x0, 0x1);
+    }
+
+    /// The color for shade `shade`, from off to on.
+    pub fn shade(&self, shade:usize) -> Rgb {
+        let level = (shade*MAX_LEVEL as usize/(SHADES - 1)) as u8;
+        self.color(0x0).mix(self.color(0x1), level)
+    }
+}
+
+impl fmt::Display for Theme {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(name) => write!(f, "{}", name),
+            None => {
+                let colors:Vec<String> = self.palette.iter()
+                    .map(|color| color.to_string())
+                    .collect();
+                write!(f, "{}", colors.join(","))
+            },
+        }
+    }
+}
+
+/// The shade a pixel at `level` is drawn in, with the same thresholds as
+/// the stipple patterns of the block renderer.
+pub fn shade(level:u8) -> usize {
+    match level {
+        0xC0..=0xFF => 0x3,
+        0x60..=0xBF => 0x2,
+        0x01..=0x5F => 0x1,
+        _ => 0x0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse(){
+        let theme = Theme::parse("amber").unwrap();
+        assert_eq!(theme.color(0x1), Rgb(0xFF, 0xB0, 0x00));
+        assert_eq!(theme.to_string(), "amber");
+
+        let theme = Theme::parse("#000000, #FFffff").unwrap();
+        assert_eq!(theme.color(0x1), Rgb(0xFF, 0xFF, 0xFF));
+        assert_eq!(theme.to_string(), "#000000,#ffffff");
+        assert_eq!(Theme::parse(&theme.to_string()), Ok(theme));
+
+        assert!(Theme::parse("pink").is_err());
+        assert!(Theme::parse("#000000").is_err());
+        assert!(Theme::parse("#000000,#fffff").is_err());
+        assert!(Theme::parse("#000000,#gggggg").is_err());
+        assert!(Theme::parse("#000000,#ffffff,#ffffff").is_err());
+        // no second bitplane to draw in the other two colors
+        assert!(Theme::parse("#000000,#ff0000,#00ff00,#0000ff").is_err());
+    }
+
+    #[test]
+    fn test_shades(){
+        let mut theme = Theme::parse("#000000,#ffffff").unwrap();
+        assert_eq!(theme.shade(0x0), Rgb(0x00, 0x00, 0x00));
+        assert_eq!(theme.shade(0x1), Rgb(0x55, 0x55, 0x55));
+        assert_eq!(theme.shade(0x3), Rgb(0xFF, 0xFF, 0xFF));
+        theme.invert();
+        assert_eq!(theme.shade(0x3), Rgb(0x00, 0x00, 0x00));
+
+        assert_eq!(shade(0x00), 0x0);
+        assert_eq!(shade(0x5F), 0x1);
+        assert_eq!(shade(0x60), 0x2);
+        assert_eq!(shade(0xFF), 0x3);
+    }
+
+    #[test]
+    fn test_nearest_colors(){
+        assert_eq!(Rgb(0x00, 0x00, 0x00).xterm256(), 16);
+        assert_eq!(Rgb(0xFF, 0xFF, 0xFF).xterm256(), 231);
+        assert_eq!(Rgb(0xFF, 0xB0, 0x00).xterm256(), 214);
+        assert_eq!(Rgb(0x80, 0x80, 0x80).xterm256(), 244);
+
+        assert_eq!(Rgb(0x33, 0xFF, 0x33).basic(), 2);
+        assert_eq!(Rgb(0xFF, 0xB0, 0x00).basic(), 3);
+        assert_eq!(Rgb(0x14, 0x0A, 0x00).basic(), 0);
+    }
+}
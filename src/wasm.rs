@@ -0,0 +1,79 @@
+extern crate wasm_bindgen;
+
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+use self::wasm_bindgen::prelude::*;
+
+use super::chip8::Chip8;
+use super::headless::{FrameBuffer, HeldKeys, NullAudio};
+
+// WebAssembly Bindings
+////////////////////////////////////////////////////////////////////////
+
+/// A machine for web pages to run, drawing into an RGBA framebuffer and
+/// reading keys the page sets. The page paces it, running a frame on
+/// each animation frame at 60 Hz, and plays a tone while `soundOn`.
+///
+/// ```js
+/// const machine = new Machine();
+/// machine.loadRom(new Uint8Array(rom));
+/// addEventListener("keydown", e => machine.setKey(keymap[e.key], true));
+/// machine.runFrame();
+/// context.putImageData(new ImageData(
+///     new Uint8ClampedArray(machine.framebuffer()), 64, 32), 0, 0);
+/// ```
+#[wasm_bindgen]
+pub struct Machine {
+    chip8:Chip8<NullAudio, FrameBuffer, HeldKeys>,
+}
+
+impl Default for Machine {
+    fn default() -> Machine {
+        Machine::new()
+    }
+}
+
+#[wasm_bindgen]
+impl Machine {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Machine {
+        Machine{chip8:Chip8::new(NullAudio::default(),
+                                 FrameBuffer::default(),
+                                 HeldKeys::default())}
+    }
+
+    /// Loads a ROM and starts it over, throwing if it doesn't fit.
+    #[wasm_bindgen(js_name = loadRom)]
+    pub fn load_rom(&mut self, rom:&[u8]) -> Result<(), String> {
+        self.chip8.load_rom(rom).map(|_| ()).map_err(|err| err.to_string())
+    }
+
+    /// Runs one frame, throwing if an instruction fails.
+    #[wasm_bindgen(js_name = runFrame)]
+    pub fn run_frame(&mut self) -> Result<(), String> {
+        self.chip8.run_frame().map_err(|err| err.to_string())
+    }
+
+    /// Presses or lets go of CHIP-8 key `key`, from 0x0 to 0xF.
+    #[wasm_bindgen(js_name = setKey)]
+    pub fn set_key(&mut self, key:u8, down:bool){
+        self.chip8.input().set(key, down);
+    }
+
+    /// The screen as 64x32 RGBA pixels, row by row, for `ImageData`.
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.chip8.display().to_rgba()
+    }
+
+    /// Whether the sound timer is running, so a tone should be playing.
+    #[wasm_bindgen(js_name = soundOn)]
+    pub fn sound_on(&self) -> bool {
+        self.chip8.registers().sound_timer > 0x0
+    }
+
+    /// Seeds the random numbers `CXNN` uses, making runs reproducible.
+    pub fn seed(&mut self, seed:u64){
+        self.chip8.seed(seed);
+    }
+}
@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Polls a file for changes by its modification time and size.
+pub struct FileWatcher {
+    path:PathBuf,
+    last:Option<(SystemTime, u64)>,
+}
+
+impl FileWatcher {
+    pub fn new<P:Into<PathBuf>>(path:P) -> FileWatcher {
+        let mut watcher = FileWatcher{path:path.into(), last:None};
+        watcher.last = watcher.stamp();
+        watcher
+    }
+
+    fn stamp(&self) -> Option<(SystemTime, u64)> {
+        let metadata = fs::metadata(&self.path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+
+    /// Returns true once for each change to the file since the last
+    /// call. A missing file, as while an editor replaces it, is not a
+    /// change.
+    pub fn changed(&mut self) -> bool {
+        match self.stamp() {
+            Some(stamp) if Some(stamp) != self.last => {
+                self.last = Some(stamp);
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+
+    #[test]
+    fn test_changed(){
+        let path = env::temp_dir().join(format!("chip-8-watch-{}.ch8",
+                                                std::process::id()));
+        File::create(&path).unwrap().write_all(&[0x12, 0x00]).unwrap();
+
+        let mut watcher = FileWatcher::new(&path);
+        assert!(!watcher.changed());
+
+        File::create(&path).unwrap().write_all(&[0x12, 0x00, 0x00, 0xE0])
+            .unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        fs::remove_file(&path).unwrap();
+        assert!(!watcher.changed());
+    }
+}
@@ -0,0 +1,132 @@
+//! Runs the test ROMs listed in tests/conformance.ini and compares their
+//! final screens to the golden images in tests/golden. Run with
+//! `UPDATE_SNAPSHOTS=1` to rewrite the golden images instead.
+
+extern crate chip_8;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chip_8::snapshot::SnapshotTest;
+use chip_8::{Quirks, DEFAULT_SPEED};
+
+// Test Descriptions
+////////////////////////////////////////////////////////////////////////
+
+struct Test {
+    name:String,
+    rom:String,
+    frames:u64,
+    quirks:Quirks,
+    speed:usize,
+    /// Keys pressed as (frame, key), in frame order.
+    keys:Vec<(u64, u8)>,
+}
+
+impl Test {
+    fn new(name:&str) -> Test {
+        Test{
+            name:name.to_string(),
+            rom:String::new(),
+            frames:0,
+            quirks:Quirks::default(),
+            speed:DEFAULT_SPEED,
+            keys:Vec::new(),
+        }
+    }
+
+    fn set(&mut self, key:&str, value:&str) -> Result<(), String> {
+        let number = |value:&str| value.parse::<u64>()
+            .map_err(|_| format!("expected a number, found '{}'", value));
+        match key {
+            "rom" => self.rom = value.to_string(),
+            "frames" => self.frames = number(value)?,
+            "quirks" => self.quirks = Quirks::profile(value)
+                .ok_or_else(|| format!("unknown quirk profile '{}'", value))?,
+            "speed" => self.speed = number(value)? as usize,
+            "keys" => for press in value.split_whitespace() {
+                let bad = || format!("expected <frame>:<key>, found '{}'",
+                                     press);
+                let split = press.find(':').ok_or_else(bad)?;
+                let frame = number(&press[..split])?;
+                let key = u8::from_str_radix(&press[split + 1..], 16).ok()
+                    .filter(|&key| key < 0x10)
+                    .ok_or_else(bad)?;
+                self.keys.push((frame, key));
+            },
+            _ => return Err(format!("unknown field '{}'", key)),
+        }
+        Ok(())
+    }
+}
+
+fn tests_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+fn load_tests() -> Vec<Test> {
+    let path = tests_dir().join("conformance.ini");
+    let text = fs::read_to_string(&path).unwrap();
+    let mut tests:Vec<Test> = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            tests.push(Test::new(&line[1..line.len() - 1]));
+            continue;
+        }
+
+        let result = match (line.find('='), tests.last_mut()) {
+            (Some(split), Some(test)) =>
+                test.set(line[..split].trim(), line[split + 1..].trim()),
+            (None, _) => Err("expected '<field> = <value>'".to_string()),
+            (_, None) => Err("field outside of a test section".to_string()),
+        };
+        if let Err(err) = result {
+            panic!("{}:{}: {}", path.display(), number + 1, err);
+        }
+    }
+    tests
+}
+
+// Running
+////////////////////////////////////////////////////////////////////////
+
+/// Runs a test's ROM and compares its final screen to the golden image,
+/// or with `UPDATE_SNAPSHOTS=1` rewrites the golden image.
+fn run(test:&Test) -> Result<(), String> {
+    let path = tests_dir().join("roms").join(&test.rom);
+    let rom = fs::read(&path)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+
+    let mut snapshot = SnapshotTest::new(&rom);
+    snapshot.set_speed(test.speed);
+    snapshot.set_quirks(test.quirks);
+    for &(frame, key) in &test.keys {
+        snapshot.press(frame, key);
+    }
+    snapshot.snapshot(test.frames, tests_dir().join("golden")
+                      .join(format!("{}.txt", test.name)));
+    snapshot.run().map_err(|err| err.to_string())
+}
+
+#[test]
+fn conformance(){
+    let tests = load_tests();
+    assert!(!tests.is_empty());
+
+    let mut failures = Vec::new();
+    for test in &tests {
+        if let Err(err) = run(test) {
+            failures.push(format!("{}: {}", test.name, err));
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!("{} of {} conformance tests failed:\n\n{}", failures.len(),
+               tests.len(), failures.join("\n"));
+    }
+}
@@ -0,0 +1,119 @@
+/* Exercises the C API through include/chip8.h, the way C programs use
+ * it. tests/ffi.rs builds and runs it; it prints the first check that
+ * fails and exits with 1. */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "chip8.h"
+
+#define CHECK(condition) do { \
+        if (!(condition)) { \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, \
+                    __LINE__, #condition); \
+            return 1; \
+        } \
+    } while (0)
+
+#define SCREEN_SIZE (CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT)
+
+/* Waits for a key, then draws its digit in the top left and spins. */
+static const uint8_t ROM[] = {0xF1, 0x0A, 0xF1, 0x29, 0xD0, 0x05, 0x12, 0x06};
+
+/* Waits for two keys, then draws the second's digit and spins. */
+static const uint8_t TWO_KEYS[] = {
+    0xF1, 0x0A, 0xF2, 0x0A, 0xF2, 0x29, 0xD0, 0x05, 0x12, 0x08,
+};
+
+static int run_frames(Chip8Machine *machine, int frames)
+{
+    for (int i = 0; i < frames; i++) {
+        if (chip8_run_frame(machine) != CHIP8_STATUS_OK) {
+            return 0;
+        }
+    }
+    return 1;
+}
+
+int main(void)
+{
+    uint8_t screen[SCREEN_SIZE];
+    uint8_t state[0x2000] = {0};
+    uint8_t bad[sizeof state];
+    size_t size;
+
+    CHECK(chip8_abi_version() == CHIP8_ABI_VERSION);
+    CHECK(strcmp(chip8_status_message(CHIP8_STATUS_ROM_EMPTY),
+                 "ROM is empty") == 0);
+    CHECK(strcmp(chip8_status_message(99), "unknown status") == 0);
+    CHECK(chip8_run_frame(NULL) == CHIP8_STATUS_NULL_POINTER);
+    chip8_free(NULL);
+
+    Chip8Machine *machine = chip8_new();
+    CHECK(machine != NULL);
+    CHECK(chip8_load_rom(machine, NULL, 0) == CHIP8_STATUS_ROM_EMPTY);
+    CHECK(chip8_load_rom(machine, NULL, 2) == CHIP8_STATUS_NULL_POINTER);
+    CHECK(chip8_load_rom(machine, state, sizeof state)
+          == CHIP8_STATUS_ROM_TOO_LARGE);
+    CHECK(chip8_load_rom(machine, ROM, sizeof ROM) == CHIP8_STATUS_OK);
+
+    /* nothing is drawn until a key is pressed */
+    CHECK(run_frames(machine, 3));
+    CHECK(chip8_framebuffer(machine, screen, sizeof screen)
+          == CHIP8_STATUS_OK);
+    CHECK(screen[0] == 0);
+
+    /* a null buffer asks for the state's size */
+    CHECK(chip8_save_state(machine, NULL, 0, &size)
+          == CHIP8_STATUS_BUFFER_TOO_SMALL);
+    CHECK(size > 0 && size <= sizeof state);
+    CHECK(chip8_save_state(machine, state, sizeof state, &size)
+          == CHIP8_STATUS_OK);
+
+    /* 8 is a full row of four pixels on top */
+    CHECK(chip8_set_keys(machine, 1 << 0x8) == CHIP8_STATUS_OK);
+    CHECK(run_frames(machine, 3));
+    CHECK(chip8_framebuffer(machine, screen, SCREEN_SIZE - 1)
+          == CHIP8_STATUS_BUFFER_TOO_SMALL);
+    CHECK(chip8_framebuffer(machine, screen, sizeof screen)
+          == CHIP8_STATUS_OK);
+    CHECK(screen[0] == 1 && screen[3] == 1 && screen[4] == 0);
+    CHECK(chip8_set_keys(machine, 0) == CHIP8_STATUS_OK);
+
+    /* a bad state changes nothing; byte 10 is the stack pointer's low */
+    CHECK(chip8_load_state(machine, state, 3) == CHIP8_STATUS_BAD_STATE);
+    memcpy(bad, state, size);
+    bad[10] = 0x20;
+    CHECK(chip8_load_state(machine, bad, size) == CHIP8_STATUS_BAD_STATE);
+    CHECK(run_frames(machine, 1));
+    CHECK(chip8_framebuffer(machine, screen, sizeof screen)
+          == CHIP8_STATUS_OK);
+    CHECK(screen[0] == 1);
+
+    /* loading the state goes back to before the key */
+    CHECK(chip8_load_state(machine, state, size) == CHIP8_STATUS_OK);
+    CHECK(run_frames(machine, 1));
+    CHECK(chip8_framebuffer(machine, screen, sizeof screen)
+          == CHIP8_STATUS_OK);
+    CHECK(screen[0] == 0);
+
+    /* a key reported down every frame is pressed only once */
+    CHECK(chip8_load_rom(machine, TWO_KEYS, sizeof TWO_KEYS)
+          == CHIP8_STATUS_OK);
+    for (int i = 0; i < 5; i++) {
+        CHECK(chip8_set_keys(machine, 1 << 0x5) == CHIP8_STATUS_OK);
+        CHECK(run_frames(machine, 1));
+    }
+    CHECK(chip8_framebuffer(machine, screen, sizeof screen)
+          == CHIP8_STATUS_OK);
+    CHECK(screen[0] == 0);
+    CHECK(chip8_set_keys(machine, 0) == CHIP8_STATUS_OK);
+    CHECK(chip8_set_keys(machine, 1 << 0x8) == CHIP8_STATUS_OK);
+    CHECK(run_frames(machine, 3));
+    CHECK(chip8_framebuffer(machine, screen, sizeof screen)
+          == CHIP8_STATUS_OK);
+    CHECK(screen[0] == 1 && screen[3] == 1 && screen[4] == 0);
+
+    chip8_free(machine);
+    return 0;
+}
@@ -0,0 +1,71 @@
+//! Builds the C library in capi/, then compiles tests/ffi.c against it
+//! and include/chip8.h with the system C compiler (or `CC`) and runs it. Also checks that include/chip8.h matches src/ffi.rs.
+
+extern crate cbindgen;
+
+use std::env;
+use std::fs;
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+
+/// Runs `command`, panicking with its output if it fails.
+fn run(command:&mut Command){
+    let output = command.output()
+        .unwrap_or_else(|err| panic!("couldn't run {:?}: {}", command, err));
+    if !output.status.success() {
+        panic!("{:?} failed with {}:\n{}{}", command, output.status,
+               String::from_utf8_lossy(&output.stdout),
+               String::from_utf8_lossy(&output.stderr));
+    }
+}
+
+#[test]
+fn test_c_program(){
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    // a target directory of its own, as this one is locked while the
+    // tests run
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+    run(Command::new(env::var_os("CARGO").unwrap_or("cargo".into()))
+        .arg("build")
+        .arg("--manifest-path").arg(root.join("capi/Cargo.toml"))
+        .arg("--target-dir").arg(&out));
+
+    let lib = out.join("debug");
+    let program = out.join("ffi-test");
+    let mut rpath = OsString::from("-Wl,-rpath,");
+    rpath.push(&lib);
+    run(Command::new(env::var_os("CC").unwrap_or("cc".into()))
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror"])
+        .arg("-I").arg(root.join("include"))
+        .arg(root.join("tests/ffi.c"))
+        .arg("-L").arg(&lib).arg("-lchip8").arg(rpath)
+        .arg("-o").arg(&program));
+    // cargo points the library path at its own builds, which would win
+    // over the rpath
+    run(Command::new(&program)
+        .env_remove("LD_LIBRARY_PATH")
+        .env_remove("DYLD_LIBRARY_PATH"));
+}
+
+#[test]
+fn test_header_current(){
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(root.join("cbindgen.toml"))
+        .unwrap();
+    // only the one module, so the rest of the crate's constants stay out
+    let mut header = Vec::new();
+    cbindgen::Builder::new()
+        .with_src(root.join("src/ffi.rs"))
+        .with_config(config)
+        .generate()
+        .expect("couldn't generate the header")
+        .write(&mut header);
+    let path = root.join("include/chip8.h");
+    if env::var_os("CHIP8_BLESS").is_some() {
+        fs::write(&path, &header).unwrap();
+    } else if fs::read(&path).ok().as_ref() != Some(&header) {
+        panic!("include/chip8.h is out of date with src/ffi.rs; rerun \
+                this test with CHIP8_BLESS=1 to regenerate it");
+    }
+}
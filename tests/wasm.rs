@@ -0,0 +1,64 @@
+//! Tests the WebAssembly bindings. Natively these are plain tests; built
+//! for wasm32 they run in Node under wasm-bindgen-test-runner, as the
+//! README describes.
+
+extern crate chip_8;
+#[cfg(target_arch = "wasm32")]
+extern crate wasm_bindgen_test;
+
+use chip_8::io::{SCREEN_WIDTH, SCREEN_HEIGHT};
+use chip_8::wasm::Machine;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen_test::wasm_bindgen_test as test;
+
+/// Whether the pixel at `row` and `col` of an RGBA framebuffer is lit.
+fn lit(framebuffer:&[u8], row:usize, col:usize) -> bool {
+    let pixel = &framebuffer[(row*SCREEN_WIDTH + col)*4..][..4];
+    assert!(pixel == [0xFF;4] || pixel == [0x0, 0x0, 0x0, 0xFF]);
+    pixel[0] == 0xFF
+}
+
+#[test]
+fn test_load_rom(){
+    let mut machine = Machine::new();
+    assert_eq!(machine.load_rom(&[]), Err("ROM is empty".to_string()));
+    assert!(machine.load_rom(&[0x12, 0x00]).is_ok());
+    assert!(machine.run_frame().is_ok());
+    assert_eq!(machine.framebuffer().len(), SCREEN_WIDTH*SCREEN_HEIGHT*4);
+}
+
+#[test]
+fn test_keys_and_framebuffer(){
+    // waits for a key, then draws its digit in the top left and spins
+    let rom = [0xF1, 0x0A, 0xF1, 0x29, 0xD0, 0x05, 0x12, 0x06];
+    let mut machine = Machine::new();
+    machine.load_rom(&rom).unwrap();
+    for _ in 0..3 {
+        machine.run_frame().unwrap();
+    }
+    assert!(!lit(&machine.framebuffer(), 0, 0));
+
+    // 8 is a full row of four pixels on top
+    machine.set_key(0x8, true);
+    for _ in 0..3 {
+        machine.run_frame().unwrap();
+    }
+    let framebuffer = machine.framebuffer();
+    assert!((0..4).all(|col| lit(&framebuffer, 0, col)));
+    assert!(!lit(&framebuffer, 0, 4));
+}
+
+#[test]
+fn test_sound_on(){
+    // sets the sound timer to 5 and spins
+    let rom = [0x60, 0x05, 0xF0, 0x18, 0x12, 0x04];
+    let mut machine = Machine::new();
+    machine.load_rom(&rom).unwrap();
+    assert!(!machine.sound_on());
+    machine.run_frame().unwrap();
+    assert!(machine.sound_on());
+    for _ in 0..5 {
+        machine.run_frame().unwrap();
+    }
+    assert!(!machine.sound_on());
+}